@@ -0,0 +1,166 @@
+//! The interface between consensus and the replicated application.
+//!
+//! Consensus decides the order of blocks; the application decides what they
+//! mean. When a new chain starts, the application receives the genesis
+//! through [`Application::init_chain`] and may override the initial
+//! validators. After a block is committed, consensus hands it to
+//! [`Application::finalize_block`], and the application may answer with
+//! changes to the validator set, which take effect two heights later, and
+//! with the result of each of its transactions.
+//!
+//! Transaction results and blocks carry [`Event`]s: typed key/value records
+//! that clients can subscribe to (e.g. `transfer.sender='alice'`).
+//!
+//! Transactions are checked with [`Application::check_tx`] before they enter
+//! the mempool, and again after every block for those still waiting.
+//! [`Application::query`] serves reads of the application state over RPC.
+
+use std::fmt;
+
+use serde::{Deserialize, Serialize};
+
+use crate::consensus::block::Block;
+use crate::consensus::validator::ValidatorUpdate;
+use crate::genesis::Genesis;
+
+/// What the application returns when the chain starts.
+#[derive(Debug, Clone, Default)]
+pub struct InitChainResponse {
+    /// If not empty, replaces the genesis validators. Entries with power 0 are ignored.
+    pub validators: Vec<ValidatorUpdate>,
+}
+
+/// Response code of a successful check, transaction or query. Any other code is a failure.
+pub const CODE_OK: u32 = 0;
+
+/// Something that happened while executing a block or transaction, e.g. a
+/// `transfer` with `sender`, `recipient` and `amount` attributes.
+#[derive(Debug, Clone, PartialEq, Eq, Default, Serialize, Deserialize)]
+pub struct Event {
+    #[serde(rename = "type")]
+    pub kind: String,
+    pub attributes: Vec<EventAttribute>,
+}
+
+/// One key/value pair of an [`Event`].
+#[derive(Debug, Clone, PartialEq, Eq, Default, Serialize, Deserialize)]
+pub struct EventAttribute {
+    pub key: String,
+    pub value: String,
+}
+
+impl Event {
+    /// Creates an event of `kind` with the given attributes.
+    pub fn new(kind: &str, attributes: &[(&str, &str)]) -> Self {
+        Self {
+            kind: kind.to_string(),
+            attributes: attributes
+                .iter()
+                .map(|(key, value)| EventAttribute {
+                    key: key.to_string(),
+                    value: value.to_string(),
+                })
+                .collect(),
+        }
+    }
+}
+
+/// Whether a transaction may enter the mempool.
+#[derive(Debug, Clone, PartialEq, Eq, Default, Serialize, Deserialize)]
+pub struct CheckTxResponse {
+    /// [`CODE_OK`] to accept the transaction.
+    pub code: u32,
+    /// Why the transaction was rejected, or any other message for the sender.
+    pub log: String,
+}
+
+impl CheckTxResponse {
+    /// Returns whether the transaction was accepted.
+    pub fn is_ok(&self) -> bool {
+        self.code == CODE_OK
+    }
+}
+
+/// The result of executing one transaction of a block.
+#[derive(Debug, Clone, PartialEq, Eq, Default, Serialize, Deserialize)]
+pub struct ExecTxResult {
+    /// [`CODE_OK`] if the transaction succeeded.
+    pub code: u32,
+    /// Output for the sender, opaque to consensus.
+    pub data: Vec<u8>,
+    pub log: String,
+    #[serde(default)]
+    pub events: Vec<Event>,
+}
+
+/// What the application returns after executing a block.
+#[derive(Debug, Clone, Default)]
+pub struct FinalizeBlockResponse {
+    /// One result per transaction of the block, in order.
+    pub tx_results: Vec<ExecTxResult>,
+    /// Events of the block as a whole (not of a single transaction).
+    pub events: Vec<Event>,
+    /// Validators to add, re-weight or (with power 0) remove.
+    pub validator_updates: Vec<ValidatorUpdate>,
+}
+
+/// The answer to a query of the application state.
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct QueryResponse {
+    /// [`CODE_OK`] if the query succeeded.
+    pub code: u32,
+    pub log: String,
+    /// The value found, if any.
+    pub value: Vec<u8>,
+    /// The height the state was read at (0 lets the node fill in its latest height).
+    pub height: u64,
+}
+
+/// A replicated state machine driven by committed blocks.
+pub trait Application: Send {
+    /// Initializes the application from the genesis (`genesis.app_state` in
+    /// particular). Called once, before the first block.
+    fn init_chain(&mut self, genesis: &Genesis) -> InitChainResponse {
+        let _ = genesis;
+        InitChainResponse::default()
+    }
+
+    /// Decides whether a transaction may enter the mempool. Called when the
+    /// transaction arrives, and again after each block while it waits.
+    fn check_tx(&mut self, tx: &[u8]) -> CheckTxResponse {
+        let _ = tx;
+        CheckTxResponse::default()
+    }
+
+    /// Executes a committed block.
+    fn finalize_block(&mut self, block: &Block) -> FinalizeBlockResponse;
+
+    /// Reads the application state at `path` (application-defined) for `data`.
+    fn query(&mut self, path: &str, data: &[u8]) -> QueryResponse {
+        let _ = data;
+        QueryResponse {
+            code: 1,
+            log: format!("unknown query path {}", path),
+            ..QueryResponse::default()
+        }
+    }
+}
+
+impl fmt::Debug for dyn Application {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("Application")
+    }
+}
+
+/// An application that accepts every transaction and block and never changes anything.
+#[derive(Debug, Default)]
+pub struct BaseApplication;
+
+impl Application for BaseApplication {
+    fn finalize_block(&mut self, block: &Block) -> FinalizeBlockResponse {
+        FinalizeBlockResponse {
+            tx_results: vec![ExecTxResult::default(); block.txs.len()],
+            ..FinalizeBlockResponse::default()
+        }
+    }
+}
@@ -0,0 +1,639 @@
+//! Node configuration.
+//!
+//! Everything a node needs to know at startup lives in its home directory:
+//!
+//! ```text
+//! <home>/
+//!   config/config.toml    this configuration
+//!   config/genesis.json   the chain's genesis
+//!   config/node_key.json  the node's ID and private key
+//!   data/                 block store, evidence and peer bans
+//! ```
+//!
+//! Settings are layered, each layer overriding the previous one:
+//! 1. built-in defaults,
+//! 2. `config.toml`, with one table per section (`[p2p]`, `[consensus]`,
+//!    `[mempool]`, `[rpc]`, `[storage]`, `[logging]`, `[instrumentation]`),
+//! 3. environment variables named `TMLIKE_<SECTION>_<FIELD>`, e.g.
+//!    `TMLIKE_P2P_MAX_INBOUND=20` or `TMLIKE_MONIKER=val-1`; list fields take
+//!    comma-separated values,
+//! 4. command-line flags.
+//!
+//! Relative paths are resolved against the home directory. [`Config::validate`]
+//! checks the result and reports the offending field by its dotted name.
+
+use std::fmt;
+use std::net::{IpAddr, SocketAddr};
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use tracing_subscriber::filter::LevelFilter;
+use tracing_subscriber::EnvFilter;
+
+use crate::p2p::codec::WireFormat;
+use crate::p2p::limits::ConnectionLimits;
+use crate::p2p::reconnect::ReconnectConfig;
+use crate::p2p::score::ScoreConfig;
+
+/// Prefix of environment variables that override configuration fields.
+pub const ENV_PREFIX: &str = "TMLIKE_";
+
+/// The configuration file, relative to the home directory.
+pub const CONFIG_FILE: &str = "config/config.toml";
+
+/// The genesis file, relative to the home directory.
+pub const GENESIS_FILE: &str = "config/genesis.json";
+
+/// The node key file, relative to the home directory.
+pub const NODE_KEY_FILE: &str = "config/node_key.json";
+
+/// A configuration value that failed validation.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ConfigError {
+    /// Dotted name of the field, e.g. `p2p.listen_addr`.
+    pub field: String,
+    /// What is wrong with it.
+    pub message: String,
+}
+
+impl ConfigError {
+    fn new(field: impl Into<String>, message: impl fmt::Display) -> Self {
+        Self {
+            field: field.into(),
+            message: message.to_string(),
+        }
+    }
+}
+
+impl fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "invalid {}: {}", self.field, self.message)
+    }
+}
+
+impl std::error::Error for ConfigError {}
+
+/// Which transport P2P connections use.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum TransportKind {
+    #[default]
+    Tcp,
+    /// QUIC, authenticated with the node key.
+    Quic,
+}
+
+/// The complete node configuration.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    /// The home directory everything else is relative to. Not stored in the file.
+    #[serde(skip)]
+    pub home: PathBuf,
+    /// A human-readable name for the node.
+    pub moniker: String,
+    /// How long shutdown waits for queued messages to be sent, in milliseconds.
+    pub shutdown_timeout_ms: u64,
+    pub p2p: P2PConfig,
+    pub consensus: ConsensusConfig,
+    pub mempool: MempoolConfig,
+    pub rpc: RpcConfig,
+    pub storage: StorageConfig,
+    pub logging: LoggingConfig,
+    pub instrumentation: InstrumentationConfig,
+}
+
+/// Peer-to-peer networking.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct P2PConfig {
+    /// Address (host:port) to listen on for peers.
+    pub listen_addr: String,
+    /// Peers (host:port) to dial at startup, with a limited number of retries.
+    pub peers: Vec<String>,
+    /// Peers (host:port) to stay connected to, re-dialed forever.
+    pub persistent_peers: Vec<String>,
+    /// IP addresses exempt from the connection limits.
+    pub unconditional_peers: Vec<String>,
+    pub max_inbound: usize,
+    pub max_outbound: usize,
+    pub max_per_ip: usize,
+    pub max_per_subnet: usize,
+    /// Delay before the first redial, in milliseconds.
+    pub dial_base_delay_ms: u64,
+    /// Upper bound on the delay between redials, in milliseconds.
+    pub dial_max_delay_ms: u64,
+    /// Failed dials before a non-persistent peer is given up on.
+    pub max_dial_attempts: u32,
+    /// Peers whose score drops to this value or below are banned.
+    pub ban_threshold: i64,
+    /// How long a ban lasts, in seconds.
+    pub ban_duration_secs: u64,
+    pub transport: TransportKind,
+    /// Encoding of outgoing messages (`binary`, or `json` for debugging).
+    pub wire_format: WireFormat,
+}
+
+/// Local consensus behavior. Chain-wide parameters live in the genesis.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ConsensusConfig {
+    /// Time between rounds, in milliseconds.
+    pub block_interval_ms: u64,
+}
+
+/// Limits for the pool of unconfirmed transactions.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct MempoolConfig {
+    /// Maximum number of transactions held.
+    pub size: usize,
+    /// Maximum size of a single transaction, in bytes.
+    pub max_tx_bytes: usize,
+    /// Number of recently seen transaction hashes remembered to drop duplicates.
+    pub cache_size: usize,
+}
+
+/// The JSON-RPC server.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct RpcConfig {
+    /// Address (host:port) to serve RPC on. Empty disables the server.
+    pub listen_addr: String,
+    /// How long `broadcast_tx_commit` waits for the transaction to be committed, in milliseconds.
+    pub timeout_broadcast_tx_commit_ms: u64,
+    /// Maximum number of event subscriptions of one WebSocket client.
+    pub max_subscriptions_per_client: usize,
+    /// Events buffered per subscription; a client that falls further behind is disconnected.
+    pub subscription_buffer_size: usize,
+}
+
+/// Where persistent state is kept. Relative paths are resolved against the home directory.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct StorageConfig {
+    pub blocks_dir: PathBuf,
+    pub evidence_file: PathBuf,
+    /// Transaction and block event indexes.
+    pub index_dir: PathBuf,
+    pub ban_file: PathBuf,
+}
+
+/// How log lines are written.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum LogFormat {
+    /// Human-readable text.
+    #[default]
+    Text,
+    /// One JSON object per line, with the fields of the enclosing spans.
+    Json,
+}
+
+/// Log output.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct LoggingConfig {
+    /// The default level (`trace`, `debug`, `info`, `warn`, `error` or
+    /// `off`), optionally followed by per-module levels, e.g.
+    /// `info,tendermint_like::p2p=debug`.
+    pub level: String,
+    pub format: LogFormat,
+}
+
+/// Metrics reporting.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct InstrumentationConfig {
+    /// Whether to serve Prometheus metrics on `prometheus_listen_addr`.
+    pub prometheus: bool,
+    /// Address (host:port) to serve `/metrics` on.
+    pub prometheus_listen_addr: String,
+    /// Prefix of every metric name.
+    pub namespace: String,
+    /// OTLP/gRPC endpoint of an OpenTelemetry collector to export spans to,
+    /// e.g. `http://127.0.0.1:4317`. Empty disables the export.
+    pub otlp_endpoint: String,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            home: PathBuf::from("."),
+            moniker: "node".into(),
+            shutdown_timeout_ms: 10_000,
+            p2p: P2PConfig::default(),
+            consensus: ConsensusConfig::default(),
+            mempool: MempoolConfig::default(),
+            rpc: RpcConfig::default(),
+            storage: StorageConfig::default(),
+            logging: LoggingConfig::default(),
+            instrumentation: InstrumentationConfig::default(),
+        }
+    }
+}
+
+impl Default for P2PConfig {
+    fn default() -> Self {
+        let limits = ConnectionLimits::default();
+        let reconnect = ReconnectConfig::default();
+        let scoring = ScoreConfig::default();
+        Self {
+            listen_addr: "127.0.0.1:26656".into(),
+            peers: Vec::new(),
+            persistent_peers: Vec::new(),
+            unconditional_peers: Vec::new(),
+            max_inbound: limits.max_inbound,
+            max_outbound: limits.max_outbound,
+            max_per_ip: limits.max_per_ip,
+            max_per_subnet: limits.max_per_subnet,
+            dial_base_delay_ms: reconnect.base_delay.as_millis() as u64,
+            dial_max_delay_ms: reconnect.max_delay.as_millis() as u64,
+            max_dial_attempts: reconnect.max_attempts,
+            ban_threshold: scoring.ban_threshold,
+            ban_duration_secs: scoring.ban_duration.as_secs(),
+            transport: TransportKind::default(),
+            wire_format: WireFormat::default(),
+        }
+    }
+}
+
+impl Default for ConsensusConfig {
+    fn default() -> Self {
+        Self {
+            block_interval_ms: 10_000,
+        }
+    }
+}
+
+impl Default for MempoolConfig {
+    fn default() -> Self {
+        Self {
+            size: 5_000,
+            max_tx_bytes: 1024 * 1024,
+            cache_size: 10_000,
+        }
+    }
+}
+
+impl Default for RpcConfig {
+    fn default() -> Self {
+        Self {
+            listen_addr: "127.0.0.1:26657".into(),
+            timeout_broadcast_tx_commit_ms: 10_000,
+            max_subscriptions_per_client: 5,
+            subscription_buffer_size: 100,
+        }
+    }
+}
+
+impl Default for StorageConfig {
+    fn default() -> Self {
+        Self {
+            blocks_dir: "data/blocks".into(),
+            evidence_file: "data/evidence.json".into(),
+            index_dir: "data/index".into(),
+            ban_file: "data/banned_peers.json".into(),
+        }
+    }
+}
+
+impl Default for LoggingConfig {
+    fn default() -> Self {
+        Self {
+            level: "info".into(),
+            format: LogFormat::default(),
+        }
+    }
+}
+
+impl Default for InstrumentationConfig {
+    fn default() -> Self {
+        Self {
+            prometheus: false,
+            prometheus_listen_addr: "127.0.0.1:26660".into(),
+            namespace: "tendermint".into(),
+            otlp_endpoint: String::new(),
+        }
+    }
+}
+
+impl Config {
+    /// Loads `<home>/config/config.toml` (defaults if it doesn't exist) and
+    /// applies overrides from `env`, typically `std::env::vars()`.
+    ///
+    /// The result is not validated, so that command-line flags can still be
+    /// applied; call [`Config::validate`] afterwards.
+    pub fn load(home: impl Into<PathBuf>, env: impl IntoIterator<Item = (String, String)>) -> Result<Self> {
+        let home = home.into();
+        let path = home.join(CONFIG_FILE);
+        let mut table = if path.exists() {
+            let text = std::fs::read_to_string(&path)
+                .with_context(|| format!("reading {}", path.display()))?;
+            toml::from_str(&text).with_context(|| format!("parsing {}", path.display()))?
+        } else {
+            toml::Table::new()
+        };
+        apply_env(&mut table, env)?;
+
+        let mut config: Config = toml::Value::Table(table)
+            .try_into()
+            .with_context(|| format!("loading configuration from {}", path.display()))?;
+        config.home = home;
+        Ok(config)
+    }
+
+    /// Writes the configuration (without `home`) to `<home>/config/config.toml`.
+    pub fn save(&self) -> Result<()> {
+        let path = self.home.join(CONFIG_FILE);
+        if let Some(dir) = path.parent() {
+            std::fs::create_dir_all(dir)?;
+        }
+        std::fs::write(&path, toml::to_string_pretty(self)?)
+            .with_context(|| format!("writing {}", path.display()))
+    }
+
+    /// Checks every field, reporting the first invalid one.
+    pub fn validate(&self) -> Result<(), ConfigError> {
+        if self.moniker.is_empty() {
+            return Err(ConfigError::new("moniker", "must not be empty"));
+        }
+
+        let p2p = &self.p2p;
+        parse_socket_addr("p2p.listen_addr", &p2p.listen_addr)?;
+        for (i, addr) in p2p.peers.iter().enumerate() {
+            parse_socket_addr(&format!("p2p.peers[{}]", i), addr)?;
+        }
+        for (i, addr) in p2p.persistent_peers.iter().enumerate() {
+            parse_socket_addr(&format!("p2p.persistent_peers[{}]", i), addr)?;
+        }
+        for (i, ip) in p2p.unconditional_peers.iter().enumerate() {
+            ip.parse::<IpAddr>()
+                .map_err(|e| ConfigError::new(format!("p2p.unconditional_peers[{}]", i), e))?;
+        }
+        if p2p.max_per_ip == 0 {
+            return Err(ConfigError::new("p2p.max_per_ip", "must be at least 1"));
+        }
+        if p2p.max_per_subnet < p2p.max_per_ip {
+            return Err(ConfigError::new("p2p.max_per_subnet", "must be at least p2p.max_per_ip"));
+        }
+        if p2p.dial_base_delay_ms == 0 {
+            return Err(ConfigError::new("p2p.dial_base_delay_ms", "must be positive"));
+        }
+        if p2p.dial_max_delay_ms < p2p.dial_base_delay_ms {
+            return Err(ConfigError::new(
+                "p2p.dial_max_delay_ms",
+                "must be at least p2p.dial_base_delay_ms",
+            ));
+        }
+        if p2p.ban_threshold >= 0 {
+            return Err(ConfigError::new("p2p.ban_threshold", "must be negative"));
+        }
+
+        if self.consensus.block_interval_ms == 0 {
+            return Err(ConfigError::new("consensus.block_interval_ms", "must be positive"));
+        }
+
+        if self.mempool.size == 0 {
+            return Err(ConfigError::new("mempool.size", "must be at least 1"));
+        }
+        if self.mempool.max_tx_bytes == 0 {
+            return Err(ConfigError::new("mempool.max_tx_bytes", "must be positive"));
+        }
+
+        if !self.rpc.listen_addr.is_empty() {
+            parse_socket_addr("rpc.listen_addr", &self.rpc.listen_addr)?;
+        }
+        if self.rpc.subscription_buffer_size == 0 {
+            return Err(ConfigError::new("rpc.subscription_buffer_size", "must be at least 1"));
+        }
+
+        let storage = &self.storage;
+        for (field, path) in [
+            ("storage.blocks_dir", &storage.blocks_dir),
+            ("storage.evidence_file", &storage.evidence_file),
+            ("storage.index_dir", &storage.index_dir),
+            ("storage.ban_file", &storage.ban_file),
+        ] {
+            if path.as_os_str().is_empty() {
+                return Err(ConfigError::new(field, "must not be empty"));
+            }
+        }
+
+        self.log_filter()?;
+
+        let instrumentation = &self.instrumentation;
+        if instrumentation.prometheus {
+            parse_socket_addr("instrumentation.prometheus_listen_addr", &instrumentation.prometheus_listen_addr)?;
+        }
+        let namespace = &instrumentation.namespace;
+        if namespace.starts_with(|c: char| c.is_ascii_digit())
+            || !namespace.chars().all(|c| c.is_ascii_alphanumeric() || c == '_')
+        {
+            return Err(ConfigError::new(
+                "instrumentation.namespace",
+                "must consist of letters, digits and underscores, and not start with a digit",
+            ));
+        }
+        Ok(())
+    }
+
+    /// Resolves `path` against the home directory (absolute paths are kept).
+    pub fn resolve(&self, path: impl AsRef<Path>) -> PathBuf {
+        self.home.join(path)
+    }
+
+    /// Path of the genesis file.
+    pub fn genesis_file(&self) -> PathBuf {
+        self.resolve(GENESIS_FILE)
+    }
+
+    /// Path of the node key file.
+    pub fn node_key_file(&self) -> PathBuf {
+        self.resolve(NODE_KEY_FILE)
+    }
+
+    /// The configured log levels, as a filter.
+    pub fn log_filter(&self) -> Result<EnvFilter, ConfigError> {
+        let invalid = |reason: String| ConfigError::new("logging.level", reason);
+        let directives: Vec<&str> = self.logging.level.split(',').map(str::trim).collect();
+        // `EnvFilter` takes a bare word for a module name, so check the levels ourselves.
+        for directive in &directives {
+            let level = directive.rsplit_once('=').map_or(*directive, |(_, level)| level);
+            level
+                .parse::<LevelFilter>()
+                .map_err(|_| invalid(format!("unknown level {:?}", level)))?;
+        }
+        EnvFilter::try_new(directives.join(",")).map_err(|e| invalid(e.to_string()))
+    }
+
+    /// Time between consensus rounds.
+    pub fn block_interval(&self) -> Duration {
+        Duration::from_millis(self.consensus.block_interval_ms)
+    }
+
+    /// How long shutdown waits for queued messages to be sent.
+    pub fn shutdown_timeout(&self) -> Duration {
+        Duration::from_millis(self.shutdown_timeout_ms)
+    }
+
+    /// Connection limits for the peer manager. Assumes a validated config.
+    pub fn connection_limits(&self) -> ConnectionLimits {
+        ConnectionLimits {
+            max_inbound: self.p2p.max_inbound,
+            max_outbound: self.p2p.max_outbound,
+            max_per_ip: self.p2p.max_per_ip,
+            max_per_subnet: self.p2p.max_per_subnet,
+            unconditional_peers: self
+                .p2p
+                .unconditional_peers
+                .iter()
+                .filter_map(|ip| ip.parse().ok())
+                .collect(),
+        }
+    }
+
+    /// Redial settings for outbound peers.
+    pub fn reconnect_config(&self) -> ReconnectConfig {
+        ReconnectConfig {
+            base_delay: Duration::from_millis(self.p2p.dial_base_delay_ms),
+            max_delay: Duration::from_millis(self.p2p.dial_max_delay_ms),
+            max_attempts: self.p2p.max_dial_attempts,
+        }
+    }
+
+    /// Peer scoring settings, with bans persisted to `storage.ban_file`.
+    pub fn score_config(&self) -> ScoreConfig {
+        ScoreConfig {
+            ban_threshold: self.p2p.ban_threshold,
+            ban_duration: Duration::from_secs(self.p2p.ban_duration_secs),
+            ban_file: Some(self.resolve(&self.storage.ban_file)),
+            ..ScoreConfig::default()
+        }
+    }
+}
+
+fn parse_socket_addr(field: &str, addr: &str) -> Result<SocketAddr, ConfigError> {
+    addr.parse()
+        .map_err(|e| ConfigError::new(field, format!("{:?} is not a host:port address ({})", addr, e)))
+}
+
+/// Applies `TMLIKE_<SECTION>_<FIELD>` variables from `env` to `table`.
+///
+/// Values are parsed according to the type of the field's default: strings
+/// are taken verbatim, lists are split on commas, and anything else is
+/// parsed as a TOML literal.
+fn apply_env(table: &mut toml::Table, env: impl IntoIterator<Item = (String, String)>) -> Result<()> {
+    let defaults = toml::Table::try_from(Config::default())?;
+
+    for (name, raw) in env {
+        let Some(key) = name.strip_prefix(ENV_PREFIX) else {
+            continue;
+        };
+        let key = key.to_lowercase();
+        // `TMLIKE_HOME` selects the home directory and isn't a config field.
+        if key == "home" {
+            continue;
+        }
+        let section = defaults
+            .iter()
+            .filter(|(_, v)| v.is_table())
+            .map(|(s, _)| s)
+            .find(|s| key.starts_with(&format!("{}_", s)));
+        let (target, default, field) = match section {
+            Some(s) => {
+                let field = key[s.len() + 1..].to_string();
+                let target = table
+                    .entry(s.clone())
+                    .or_insert_with(|| toml::Value::Table(toml::Table::new()))
+                    .as_table_mut()
+                    .with_context(|| format!("{} is not a table", s))?;
+                (target, defaults[s].get(&field), format!("{}.{}", s, field))
+            }
+            None => (&mut *table, defaults.get(&key), key.clone()),
+        };
+        let value = match default {
+            None => anyhow::bail!("{} names unknown configuration field {}", name, field),
+            Some(toml::Value::String(_)) => toml::Value::String(raw),
+            Some(toml::Value::Array(_)) => toml::Value::Array(
+                raw.split(',')
+                    .map(str::trim)
+                    .filter(|s| !s.is_empty())
+                    .map(|s| toml::Value::String(s.to_string()))
+                    .collect(),
+            ),
+            Some(_) => toml::from_str::<toml::Table>(&format!("v = {}", raw))
+                .ok()
+                .and_then(|mut t| t.remove("v"))
+                .with_context(|| format!("{}: cannot parse {:?} for {}", name, raw, field))?,
+        };
+        target.insert(field.rsplit('.').next().unwrap_or(&field).to_string(), value);
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn env(vars: &[(&str, &str)]) -> Vec<(String, String)> {
+        vars.iter().map(|(k, v)| (k.to_string(), v.to_string())).collect()
+    }
+
+    #[test]
+    fn file_and_environment_override_defaults() {
+        let home = std::env::temp_dir().join(format!("tmlike-config-{}", std::process::id()));
+        let mut config = Config {
+            home: home.clone(),
+            ..Config::default()
+        };
+        config.p2p.max_inbound = 7;
+        config.rpc.listen_addr = "127.0.0.1:9000".into();
+        config.save().unwrap();
+
+        let loaded = Config::load(
+            &home,
+            env(&[
+                ("TMLIKE_HOME", "/elsewhere"),
+                ("TMLIKE_MONIKER", "val-1"),
+                ("TMLIKE_P2P_MAX_INBOUND", "20"),
+                ("TMLIKE_P2P_PERSISTENT_PEERS", "127.0.0.1:1, 127.0.0.1:2"),
+                ("UNRELATED", "x"),
+            ]),
+        )
+        .unwrap();
+        std::fs::remove_dir_all(&home).unwrap();
+
+        assert_eq!(loaded.moniker, "val-1");
+        assert_eq!(loaded.p2p.max_inbound, 20);
+        assert_eq!(loaded.p2p.persistent_peers, ["127.0.0.1:1", "127.0.0.1:2"]);
+        assert_eq!(loaded.rpc.listen_addr, "127.0.0.1:9000");
+        assert_eq!(loaded.home, home);
+        loaded.validate().unwrap();
+
+        let unknown = Config::load(&home, env(&[("TMLIKE_P2P_MAX_INBOUD", "1")])).unwrap_err();
+        assert!(unknown.to_string().contains("p2p.max_inboud"));
+    }
+
+    #[test]
+    fn validation_names_the_offending_field() {
+        let mut config = Config::default();
+        config.p2p.persistent_peers = vec!["127.0.0.1:1".into(), "nowhere".into()];
+        assert_eq!(config.validate().unwrap_err().field, "p2p.persistent_peers[1]");
+
+        let mut config = Config::default();
+        config.logging.level = "loud".into();
+        assert_eq!(config.validate().unwrap_err().field, "logging.level");
+        config.logging.level = "info,tendermint_like::p2p=chatty".into();
+        assert_eq!(config.validate().unwrap_err().to_string(), "invalid logging.level: unknown level \"chatty\"");
+        config.logging.level = "warn, tendermint_like::consensus=debug".into();
+        config.validate().unwrap();
+
+        let mut config = Config::default();
+        config.p2p.dial_max_delay_ms = 1;
+        let err = config.validate().unwrap_err();
+        assert_eq!(err.to_string(), "invalid p2p.dial_max_delay_ms: must be at least p2p.dial_base_delay_ms");
+    }
+}
@@ -0,0 +1,121 @@
+//! A fixed-size bit array, used to describe which validators' votes (or which
+//! block parts) a node holds.
+//!
+//! For votes, bit `i` refers to the validator at position `i` in the
+//! `ValidatorSet`, so a whole round's worth of votes fits in a few bytes.
+
+use std::fmt;
+
+use serde::{Deserialize, Serialize};
+
+/// A bit array of a fixed length.
+#[derive(Clone, PartialEq, Eq, Default, Serialize, Deserialize)]
+pub struct BitArray {
+    len: u32,
+    words: Vec<u64>,
+}
+
+impl BitArray {
+    /// Creates an array of `len` bits, all unset.
+    pub fn new(len: usize) -> Self {
+        Self {
+            len: len as u32,
+            words: vec![0; len.div_ceil(64)],
+        }
+    }
+
+    /// Returns the number of bits in the array.
+    pub fn len(&self) -> usize {
+        self.len as usize
+    }
+
+    /// Returns `true` if the array has no bits.
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// Returns `true` if the array's storage matches its length. Arrays
+    /// received from peers must be checked before use.
+    pub fn is_well_formed(&self) -> bool {
+        self.words.len() == self.len().div_ceil(64)
+    }
+
+    /// Returns bit `index`; out-of-range bits read as unset.
+    pub fn get(&self, index: usize) -> bool {
+        index < self.len()
+            && self
+                .words
+                .get(index / 64)
+                .is_some_and(|w| w & (1 << (index % 64)) != 0)
+    }
+
+    /// Sets bit `index` and returns its previous value. Out-of-range indexes are ignored.
+    pub fn set(&mut self, index: usize) -> bool {
+        let previous = self.get(index);
+        if index < self.len() {
+            if let Some(w) = self.words.get_mut(index / 64) {
+                *w |= 1 << (index % 64);
+            }
+        }
+        previous
+    }
+
+    /// Returns the number of set bits.
+    pub fn count(&self) -> usize {
+        (0..self.len()).filter(|&i| self.get(i)).count()
+    }
+
+    /// Returns the indexes of all set bits, in ascending order.
+    pub fn ones(&self) -> impl Iterator<Item = usize> + '_ {
+        (0..self.len()).filter(|&i| self.get(i))
+    }
+
+    /// Returns the bits set in `self` but not in `other`.
+    pub fn sub(&self, other: &BitArray) -> BitArray {
+        let mut out = BitArray::new(self.len());
+        for i in self.ones().filter(|&i| !other.get(i)) {
+            out.set(i);
+        }
+        out
+    }
+
+    /// Returns the bits set in either `self` or `other`, sized to the longer of the two.
+    pub fn or(&self, other: &BitArray) -> BitArray {
+        let mut out = BitArray::new(self.len().max(other.len()));
+        for i in self.ones().chain(other.ones()) {
+            out.set(i);
+        }
+        out
+    }
+}
+
+impl fmt::Debug for BitArray {
+    /// Formats as `BA{xx_x}`, with `x` for set bits and `_` for unset ones.
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let bits: String = (0..self.len())
+            .map(|i| if self.get(i) { 'x' } else { '_' })
+            .collect();
+        write!(f, "BA{{{}}}", bits)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn set_get_and_set_operations() {
+        let mut a = BitArray::new(70);
+        assert!(!a.set(3));
+        assert!(a.set(3));
+        a.set(69);
+        a.set(70); // out of range, ignored
+        assert_eq!(a.ones().collect::<Vec<_>>(), [3, 69]);
+
+        let mut b = BitArray::new(70);
+        b.set(69);
+        b.set(10);
+        assert_eq!(a.sub(&b).ones().collect::<Vec<_>>(), [3]);
+        assert_eq!(a.or(&b).count(), 3);
+    }
+}
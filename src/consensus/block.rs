@@ -0,0 +1,216 @@
+//! Splitting proposed blocks into Merkle-proven parts and putting them back together.
+//!
+//! The proposer splits a block into fixed-size parts, computes the Merkle root
+//! over them and announces it in the proposal's `PartSetHeader`. Each part is
+//! then gossiped on its own together with a proof against that root, so a
+//! receiver can check every part as it arrives, long before it has the whole block.
+
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+
+use super::evidence::Evidence;
+use super::merkle::{root_and_proofs, Hash, MerkleProof};
+
+/// Size of every part except possibly the last one.
+pub const BLOCK_PART_SIZE: usize = 64 * 1024;
+
+/// A transaction: opaque bytes that only the application interprets.
+pub type Tx = Vec<u8>;
+
+/// Returns the hash that identifies a transaction (hex-encoded SHA-256).
+pub fn tx_hash(tx: &[u8]) -> String {
+    hex::encode(Sha256::digest(tx))
+}
+
+/// A proposed block: its height, opaque payload, the transactions taken
+/// from the proposer's mempool, and any evidence of misbehavior the proposer
+/// included so the application can act on it.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Block {
+    pub height: u64,
+    pub data: String,
+    pub txs: Vec<Tx>,
+    pub evidence: Vec<Evidence>,
+}
+
+impl Block {
+    /// Creates a block.
+    pub fn new(height: u64, data: String, txs: Vec<Tx>, evidence: Vec<Evidence>) -> Self {
+        Self { height, data, txs, evidence }
+    }
+
+    /// Serializes the block into the bytes that are split into parts.
+    pub fn encode(&self) -> Vec<u8> {
+        bincode::serialize(self).expect("serializing a block cannot fail")
+    }
+
+    /// Parses bytes produced by [`Block::encode`].
+    pub fn decode(bytes: &[u8]) -> Option<Self> {
+        bincode::deserialize(bytes).ok()
+    }
+
+    /// Returns the hash that identifies the block (hex-encoded SHA-256 of its encoding).
+    pub fn hash(&self) -> String {
+        hex::encode(Sha256::digest(self.encode()))
+    }
+}
+
+/// Describes the parts of a block: how many there are and their Merkle root.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PartSetHeader {
+    pub total: u32,
+    pub hash: Hash,
+}
+
+/// One chunk of a block, with a proof that it belongs under `PartSetHeader::hash`.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Part {
+    pub index: u32,
+    pub bytes: Vec<u8>,
+    pub proof: MerkleProof,
+}
+
+/// Why a part was rejected.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum PartError {
+    /// The part's index is outside `0..total`.
+    IndexOutOfRange,
+    /// The part is larger than `BLOCK_PART_SIZE`.
+    TooLarge,
+    /// The Merkle proof doesn't match the header.
+    InvalidProof,
+}
+
+/// A (possibly incomplete) set of parts for one block.
+#[derive(Debug, Clone)]
+pub struct PartSet {
+    header: PartSetHeader,
+    parts: Vec<Option<Part>>,
+    count: u32,
+}
+
+impl PartSet {
+    /// Splits `data` into parts. The resulting set is complete.
+    pub fn from_data(data: &[u8]) -> Self {
+        let chunks: Vec<&[u8]> = if data.is_empty() {
+            vec![data]
+        } else {
+            data.chunks(BLOCK_PART_SIZE).collect()
+        };
+        let (hash, proofs) = root_and_proofs(&chunks);
+        let parts: Vec<Option<Part>> = chunks
+            .iter()
+            .zip(proofs)
+            .enumerate()
+            .map(|(i, (bytes, proof))| {
+                Some(Part {
+                    index: i as u32,
+                    bytes: bytes.to_vec(),
+                    proof,
+                })
+            })
+            .collect();
+        let total = parts.len() as u32;
+        Self {
+            header: PartSetHeader { total, hash },
+            parts,
+            count: total,
+        }
+    }
+
+    /// Creates an empty set expecting the parts described by `header`.
+    pub fn from_header(header: PartSetHeader) -> Self {
+        Self {
+            parts: vec![None; header.total as usize],
+            header,
+            count: 0,
+        }
+    }
+
+    /// Returns the header describing this set.
+    pub fn header(&self) -> &PartSetHeader {
+        &self.header
+    }
+
+    /// Returns `true` once every part has been received.
+    pub fn is_complete(&self) -> bool {
+        self.count == self.header.total
+    }
+
+    /// Returns the part at `index`, if we have it.
+    pub fn part(&self, index: u32) -> Option<&Part> {
+        self.parts.get(index as usize)?.as_ref()
+    }
+
+    /// Returns which parts we have, indexed by part number.
+    pub fn has_parts(&self) -> Vec<bool> {
+        self.parts.iter().map(Option::is_some).collect()
+    }
+
+    /// Verifies `part` against the header and stores it.
+    ///
+    /// Returns `Ok(false)` if we already had this part.
+    pub fn add_part(&mut self, part: Part) -> Result<bool, PartError> {
+        let slot = self
+            .parts
+            .get_mut(part.index as usize)
+            .ok_or(PartError::IndexOutOfRange)?;
+        if slot.is_some() {
+            return Ok(false);
+        }
+        if part.bytes.len() > BLOCK_PART_SIZE {
+            return Err(PartError::TooLarge);
+        }
+        if part.proof.index != part.index
+            || part.proof.total != self.header.total
+            || !part.proof.verify(&self.header.hash, &part.bytes)
+        {
+            return Err(PartError::InvalidProof);
+        }
+        *slot = Some(part);
+        self.count += 1;
+        Ok(true)
+    }
+
+    /// Concatenates all parts back into the original data, once complete.
+    pub fn assemble(&self) -> Option<Vec<u8>> {
+        if !self.is_complete() {
+            return None;
+        }
+        Some(self.parts.iter().flatten().flat_map(|p| p.bytes.iter().copied()).collect())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parts_round_trip_in_any_order() {
+        let data: Vec<u8> = (0..(BLOCK_PART_SIZE * 3 + 17)).map(|i| i as u8).collect();
+        let full = PartSet::from_data(&data);
+        assert_eq!(full.header().total, 4);
+
+        let mut received = PartSet::from_header(full.header().clone());
+        for index in [3, 1, 0, 2] {
+            assert!(!received.is_complete());
+            assert_eq!(received.add_part(full.part(index).unwrap().clone()), Ok(true));
+        }
+        assert_eq!(received.add_part(full.part(0).unwrap().clone()), Ok(false));
+        assert_eq!(received.assemble().unwrap(), data);
+    }
+
+    #[test]
+    fn corrupted_part_is_rejected() {
+        let full = PartSet::from_data(&vec![7u8; BLOCK_PART_SIZE + 1]);
+        let mut received = PartSet::from_header(full.header().clone());
+
+        let mut bad = full.part(1).unwrap().clone();
+        bad.bytes[0] ^= 1;
+        assert_eq!(received.add_part(bad), Err(PartError::InvalidProof));
+
+        let mut misplaced = full.part(1).unwrap().clone();
+        misplaced.index = 5;
+        assert_eq!(received.add_part(misplaced), Err(PartError::IndexOutOfRange));
+    }
+}
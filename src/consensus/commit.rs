@@ -0,0 +1,79 @@
+//! Commits: the +2/3 precommits that finalize a block.
+//!
+//! A commit is what makes a block trustworthy to someone who didn't take part
+//! in the round: given the validator set, anyone can check who signed it.
+
+use std::collections::HashSet;
+use std::fmt;
+
+use serde::{Deserialize, Serialize};
+
+use super::types::VoteType;
+use super::validator::ValidatorSet;
+use super::vote::Vote;
+
+/// The precommits for `block_hash` at `height`/`round`.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Commit {
+    pub height: u64,
+    pub round: u64,
+    pub block_hash: String,
+    /// Signed precommits, one per signing validator.
+    pub signatures: Vec<Vote>,
+}
+
+impl Commit {
+    /// Checks every signature against `validators` and returns the IDs of the
+    /// validators that signed, in the order they appear.
+    pub fn verified_signers(&self, validators: &ValidatorSet) -> Result<Vec<String>, CommitError> {
+        let mut seen = HashSet::new();
+        let mut signers = Vec::with_capacity(self.signatures.len());
+        for vote in &self.signatures {
+            if vote.vote_type != VoteType::Precommit
+                || vote.height != self.height
+                || vote.round != self.round
+                || vote.block_hash != self.block_hash
+            {
+                return Err(CommitError::MismatchedVote);
+            }
+            let pub_key = validators
+                .pub_key(&vote.validator_id)
+                .ok_or(CommitError::UnknownValidator)?;
+            if !seen.insert(vote.validator_id.as_str()) {
+                return Err(CommitError::DuplicateSigner);
+            }
+            if !vote.verify(pub_key) {
+                return Err(CommitError::BadSignature);
+            }
+            signers.push(vote.validator_id.clone());
+        }
+        Ok(signers)
+    }
+}
+
+/// Why a commit failed verification.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum CommitError {
+    /// A signature is not a precommit for the committed block.
+    MismatchedVote,
+    /// A signer is not in the validator set.
+    UnknownValidator,
+    /// A validator signed more than once.
+    DuplicateSigner,
+    /// A signature doesn't verify.
+    BadSignature,
+}
+
+impl fmt::Display for CommitError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let s = match self {
+            CommitError::MismatchedVote => "signature is not a precommit for the committed block",
+            CommitError::UnknownValidator => "signer not in the validator set",
+            CommitError::DuplicateSigner => "validator signed twice",
+            CommitError::BadSignature => "bad signature",
+        };
+        f.write_str(s)
+    }
+}
+
+impl std::error::Error for CommitError {}
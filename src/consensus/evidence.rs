@@ -0,0 +1,388 @@
+//! Proof that a validator misbehaved.
+//!
+//! Two kinds of misbehavior are detected:
+//! - A validator must cast at most one vote of each type per height and round.
+//!   When we see two conflicting votes from the same validator, we keep both
+//!   signed votes as a `DuplicateVoteEvidence` record instead of letting the
+//!   second silently replace the first.
+//! - A light client trusting a validator set accepts any block signed by more
+//!   than a third of it. A commit for a block that conflicts with one in our
+//!   `BlockStore`, signed by +1/3 of the trusted set, is therefore an attack on
+//!   light clients and becomes `LightClientAttackEvidence`.
+//!
+//! Evidence is self-contained: anyone who knows the validator set at the
+//! evidence's height can check it, which is what lets it be gossiped and
+//! included in blocks (see `evidence_pool.rs`).
+
+use std::fmt;
+
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+
+use crate::p2p::score::Misbehavior;
+
+use super::commit::{Commit, CommitError};
+use super::store::BlockStore;
+use super::validator::ValidatorSet;
+use super::vote::Vote;
+
+/// Two conflicting signed votes from the same validator.
+///
+/// The votes are stored in a canonical order (by block hash), so the same pair
+/// always produces the same evidence no matter which vote arrived first.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct DuplicateVoteEvidence {
+    pub vote_a: Vote,
+    pub vote_b: Vote,
+}
+
+impl DuplicateVoteEvidence {
+    /// Builds evidence from two votes, or returns `None` if they don't conflict.
+    pub fn new(first: Vote, second: Vote) -> Option<Self> {
+        if !first.conflicts_with(&second) {
+            return None;
+        }
+        let (vote_a, vote_b) = if first.block_hash < second.block_hash {
+            (first, second)
+        } else {
+            (second, first)
+        };
+        Some(Self { vote_a, vote_b })
+    }
+
+    /// Returns the ID of the validator that equivocated.
+    pub fn validator_id(&self) -> &str {
+        &self.vote_a.validator_id
+    }
+
+    /// Returns the height at which the validator equivocated.
+    pub fn height(&self) -> u64 {
+        self.vote_a.height
+    }
+
+    /// Checks that the votes really conflict, are in canonical order, come
+    /// from a member of `validators` and are both signed by it.
+    pub fn verify(&self, validators: &ValidatorSet) -> Result<(), EvidenceError> {
+        if !self.vote_a.conflicts_with(&self.vote_b) || self.vote_a.block_hash > self.vote_b.block_hash {
+            return Err(EvidenceError::Invalid("votes do not conflict"));
+        }
+        let pub_key = validators
+            .pub_key(self.validator_id())
+            .ok_or(EvidenceError::UnknownValidator)?;
+        if !self.vote_a.verify(pub_key) || !self.vote_b.verify(pub_key) {
+            return Err(EvidenceError::BadSignature);
+        }
+        Ok(())
+    }
+}
+
+impl fmt::Display for DuplicateVoteEvidence {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "validator {} cast conflicting {:?} votes at height {} round {} ({} vs {})",
+            self.vote_a.validator_id,
+            self.vote_a.vote_type,
+            self.vote_a.height,
+            self.vote_a.round,
+            self.vote_a.block_hash,
+            self.vote_b.block_hash
+        )
+    }
+}
+
+/// A commit for a block that conflicts with one we committed, signed by more
+/// than a third of the validator set trusted at `common_height`.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct LightClientAttackEvidence {
+    /// The attackers' commit for the conflicting block.
+    pub conflicting_commit: Commit,
+    /// Height of the last block both chains agree on; its validator set is
+    /// the one a light client would trust.
+    pub common_height: u64,
+    /// The validators that signed the conflicting block, sorted.
+    pub byzantine_validators: Vec<String>,
+}
+
+impl LightClientAttackEvidence {
+    /// Builds evidence from a commit that diverges from our `store`, and
+    /// verifies it against `trusted`.
+    pub fn from_divergent_commit(
+        conflicting_commit: Commit,
+        store: &BlockStore,
+        trusted: &ValidatorSet,
+    ) -> Result<Self, EvidenceError> {
+        let mut byzantine_validators = conflicting_commit.verified_signers(trusted)?;
+        byzantine_validators.sort();
+        let evidence = Self {
+            common_height: conflicting_commit.height.saturating_sub(1),
+            conflicting_commit,
+            byzantine_validators,
+        };
+        evidence.verify(store, trusted)?;
+        Ok(evidence)
+    }
+
+    /// Checks that the commit conflicts with the block we committed at its
+    /// height, and that it carries valid signatures from more than a third of
+    /// the voting power of `trusted`, whose owners are exactly the listed
+    /// byzantine validators.
+    pub fn verify(&self, store: &BlockStore, trusted: &ValidatorSet) -> Result<(), EvidenceError> {
+        let height = self.conflicting_commit.height;
+        let ours = store.load_commit(height).ok_or(EvidenceError::FromFuture {
+            height,
+            current: store.height(),
+        })?;
+        if ours.block_hash == self.conflicting_commit.block_hash {
+            return Err(EvidenceError::Invalid("block does not conflict with ours"));
+        }
+        if self.common_height + 1 != height {
+            return Err(EvidenceError::Invalid("wrong common height"));
+        }
+
+        let mut signers = self.conflicting_commit.verified_signers(trusted)?;
+        let signed_power: u64 = signers.iter().map(|id| trusted.power_of(id)).sum();
+        if signed_power * 3 <= trusted.total_power() {
+            return Err(EvidenceError::Invalid(
+                "conflicting block not signed by +1/3 of the trusted validators",
+            ));
+        }
+        signers.sort();
+        if signers != self.byzantine_validators {
+            return Err(EvidenceError::Invalid("wrong byzantine validators"));
+        }
+        Ok(())
+    }
+}
+
+impl fmt::Display for LightClientAttackEvidence {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "validators {:?} signed conflicting block {} at height {}",
+            self.byzantine_validators, self.conflicting_commit.block_hash, self.conflicting_commit.height
+        )
+    }
+}
+
+/// Any kind of evidence of validator misbehavior.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Evidence {
+    DuplicateVote(DuplicateVoteEvidence),
+    LightClientAttack(LightClientAttackEvidence),
+}
+
+impl Evidence {
+    /// Returns the height the evidence counts from: where the votes were
+    /// cast, or the common height of a light-client attack.
+    pub fn height(&self) -> u64 {
+        match self {
+            Evidence::DuplicateVote(ev) => ev.height(),
+            Evidence::LightClientAttack(ev) => ev.common_height,
+        }
+    }
+
+    /// Returns the IDs of the validators the evidence accuses.
+    pub fn byzantine_validators(&self) -> Vec<&str> {
+        match self {
+            Evidence::DuplicateVote(ev) => vec![ev.validator_id()],
+            Evidence::LightClientAttack(ev) => ev.byzantine_validators.iter().map(String::as_str).collect(),
+        }
+    }
+
+    /// Returns the hex-encoded SHA-256 of the evidence, which identifies it.
+    pub fn hash(&self) -> String {
+        let bytes = bincode::serialize(self).expect("serializing evidence cannot fail");
+        hex::encode(Sha256::digest(bytes))
+    }
+
+    /// Checks the evidence against the validator set at its height and, for
+    /// light-client attacks, against the blocks we committed.
+    pub fn verify(&self, validators: &ValidatorSet, store: &BlockStore) -> Result<(), EvidenceError> {
+        match self {
+            Evidence::DuplicateVote(ev) => ev.verify(validators),
+            Evidence::LightClientAttack(ev) => ev.verify(store, validators),
+        }
+    }
+}
+
+impl fmt::Display for Evidence {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Evidence::DuplicateVote(ev) => ev.fmt(f),
+            Evidence::LightClientAttack(ev) => ev.fmt(f),
+        }
+    }
+}
+
+/// Why a piece of evidence was rejected.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum EvidenceError {
+    /// The evidence is internally inconsistent.
+    Invalid(&'static str),
+    /// The accused validator is not in the validator set.
+    UnknownValidator,
+    /// A signature doesn't verify.
+    BadSignature,
+    /// The evidence claims a height we haven't reached.
+    FromFuture { height: u64, current: u64 },
+    /// The evidence is older than the maximum evidence age.
+    Expired { height: u64, current: u64 },
+    /// The evidence was already committed in a block.
+    AlreadyCommitted,
+}
+
+impl EvidenceError {
+    /// Returns the misbehavior a peer commits by sending us this evidence, if
+    /// any. Stale evidence can legitimately cross in flight, and a peer ahead
+    /// of us can send evidence we can't check yet, so neither is held against
+    /// the sender.
+    pub fn misbehavior(&self) -> Option<Misbehavior> {
+        match self {
+            EvidenceError::BadSignature => Some(Misbehavior::BadSignature),
+            EvidenceError::Invalid(_) | EvidenceError::UnknownValidator => Some(Misbehavior::InvalidEvidence),
+            EvidenceError::FromFuture { .. } | EvidenceError::Expired { .. } | EvidenceError::AlreadyCommitted => {
+                None
+            }
+        }
+    }
+}
+
+impl fmt::Display for EvidenceError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            EvidenceError::Invalid(reason) => write!(f, "invalid evidence: {}", reason),
+            EvidenceError::UnknownValidator => write!(f, "validator not in the validator set"),
+            EvidenceError::BadSignature => write!(f, "bad signature"),
+            EvidenceError::FromFuture { height, current } => {
+                write!(f, "evidence from height {} is ahead of current height {}", height, current)
+            }
+            EvidenceError::Expired { height, current } => {
+                write!(f, "evidence from height {} is too old at height {}", height, current)
+            }
+            EvidenceError::AlreadyCommitted => write!(f, "evidence already committed"),
+        }
+    }
+}
+
+impl std::error::Error for EvidenceError {}
+
+impl From<CommitError> for EvidenceError {
+    fn from(e: CommitError) -> Self {
+        match e {
+            CommitError::BadSignature => EvidenceError::BadSignature,
+            CommitError::UnknownValidator => EvidenceError::UnknownValidator,
+            CommitError::MismatchedVote => EvidenceError::Invalid("commit signature is not a matching precommit"),
+            CommitError::DuplicateSigner => EvidenceError::Invalid("commit signed twice by one validator"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::consensus::block::Block;
+    use crate::consensus::state::ConsensusCore;
+    use crate::consensus::types::VoteType;
+    use crate::consensus::validator::Validator;
+    use crate::p2p::key::NodeKey;
+
+    fn signed_vote(key: &NodeKey, validator_id: &str, block_hash: &str) -> Vote {
+        let mut vote = Vote::new(VoteType::Prevote, 1, 1, block_hash.into(), validator_id.into());
+        vote.sign(key);
+        vote
+    }
+
+    #[test]
+    fn conflicting_votes_produce_evidence_instead_of_overwriting() {
+        let mut core = ConsensusCore::new("node-a".into(), "127.0.0.1:0".into());
+        let key = NodeKey::generate();
+        core.set_validators(ValidatorSet::new(vec![
+            Validator::new("node-a".into(), core.public_key(), 10),
+            Validator::new("node-b".into(), key.public_key(), 10),
+        ]));
+        core.start_new_round("block-1".into());
+
+        let first = signed_vote(&key, "node-b", "hash-x");
+        core.on_vote(first.clone()).unwrap();
+        // Receiving the same vote again is harmless.
+        core.on_vote(first.clone()).unwrap();
+        assert!(core.evidence_pool.pending(10).is_empty());
+
+        let second = signed_vote(&key, "node-b", "hash-y");
+        core.on_vote(second.clone()).unwrap();
+
+        // The first vote is kept, and both signed votes end up in the evidence.
+        assert_eq!(core.round_state.prevotes["node-b"], first);
+        let evidence = core.evidence_pool.pending(10);
+        let expected = DuplicateVoteEvidence::new(second, first).unwrap();
+        assert_eq!(evidence, [Evidence::DuplicateVote(expected.clone())]);
+        assert_eq!(expected.validator_id(), "node-b");
+        assert_eq!(expected.verify(&core.validators), Ok(()));
+
+        // A forged vote is rejected before it can frame anyone.
+        let mut forged = signed_vote(&NodeKey::generate(), "node-b", "hash-z");
+        forged.signature[0] ^= 1;
+        let err = core.on_vote(forged).unwrap_err();
+        assert_eq!(err.downcast_ref::<Misbehavior>(), Some(&Misbehavior::BadSignature));
+    }
+
+    fn commit(keys: &[(&str, &NodeKey)], block_hash: &str) -> Commit {
+        let signatures = keys
+            .iter()
+            .map(|(id, key)| {
+                let mut vote = Vote::new(VoteType::Precommit, 1, 0, block_hash.into(), id.to_string());
+                vote.sign(key);
+                vote
+            })
+            .collect();
+        Commit {
+            height: 1,
+            round: 0,
+            block_hash: block_hash.into(),
+            signatures,
+        }
+    }
+
+    #[test]
+    fn conflicting_commit_from_a_third_of_validators_is_a_light_client_attack() {
+        let keys: Vec<NodeKey> = (0..3).map(|_| NodeKey::generate()).collect();
+        let ids = ["val-0", "val-1", "val-2"];
+        let signers: Vec<(&str, &NodeKey)> = ids.iter().copied().zip(keys.iter()).collect();
+
+        let mut core = ConsensusCore::new("observer".into(), "127.0.0.1:0".into());
+        core.set_validators(ValidatorSet::new(
+            signers
+                .iter()
+                .map(|(id, key)| Validator::new(id.to_string(), key.public_key(), 10))
+                .collect(),
+        ));
+        let block = Block::new(1, "honest".into(), Vec::new(), Vec::new());
+        core.block_store
+            .save_block(block.clone(), commit(&signers, &block.hash()))
+            .unwrap();
+
+        // Our own block again, or a height we haven't reached, is no attack.
+        core.on_commit(commit(&signers, &block.hash())).unwrap();
+        assert!(core.evidence_pool.pending(10).is_empty());
+
+        // One of three validators is not more than a third.
+        let err = core.on_commit(commit(&signers[..1], "forked")).unwrap_err();
+        assert_eq!(err.downcast_ref::<Misbehavior>(), Some(&Misbehavior::InvalidEvidence));
+
+        // Two of three is.
+        core.on_commit(commit(&signers[1..], "forked")).unwrap();
+        let pending = core.evidence_pool.pending(10);
+        let [Evidence::LightClientAttack(attack)] = pending.as_slice() else {
+            panic!("expected light client attack evidence, got {:?}", pending);
+        };
+        assert_eq!(attack.common_height, 0);
+        assert_eq!(attack.byzantine_validators, ["val-1", "val-2"]);
+        assert_eq!(attack.verify(&core.block_store, &core.validators), Ok(()));
+
+        // Tampering with the accused set invalidates the evidence.
+        let mut tampered = attack.clone();
+        tampered.byzantine_validators.pop();
+        assert!(tampered.verify(&core.block_store, &core.validators).is_err());
+    }
+}
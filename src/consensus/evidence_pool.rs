@@ -0,0 +1,223 @@
+//! The evidence pool: verified evidence waiting to be committed.
+//!
+//! Evidence enters the pool either from local detection (e.g. conflicting
+//! votes seen by `ConsensusCore`) or from peers via `P2PMessage::Evidence`.
+//! Before it is accepted it must
+//! - verify against the validator set in force at the evidence's height
+//!   (membership and signatures) and, for light-client attacks, against the
+//!   blocks in our `BlockStore`,
+//! - not be from a future height,
+//! - be no older than `ConsensusParams::evidence_max_age_blocks`,
+//! - not have been committed already.
+//!
+//! Accepted evidence stays pending until a proposer includes it in a block
+//! and that block is committed. Committed evidence is remembered (until it
+//! expires) so it can't be included twice. Both sets are written to the pool
+//! file, if configured, so they survive restarts.
+
+use std::collections::HashMap;
+use std::path::PathBuf;
+
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+use tracing::warn;
+
+use super::evidence::{Evidence, EvidenceError};
+use super::store::BlockStore;
+use super::types::ConsensusParams;
+
+/// The on-disk form of the pool.
+#[derive(Debug, Default, Serialize, Deserialize)]
+struct StoredPool {
+    pending: Vec<Evidence>,
+    /// Evidence hash -> height of the block that committed it.
+    committed: HashMap<String, u64>,
+}
+
+/// Pending and committed evidence.
+#[derive(Debug, Default)]
+pub struct EvidencePool {
+    /// Verified evidence not yet in a committed block, in arrival order.
+    pending: Vec<Evidence>,
+    /// Evidence hash -> height of the block that committed it.
+    committed: HashMap<String, u64>,
+    /// Where the pool is persisted. `None` keeps it in memory only.
+    file: Option<PathBuf>,
+}
+
+impl EvidencePool {
+    /// Creates an empty, in-memory pool.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Persists the pool to `path` from now on, first loading whatever was
+    /// stored there. A missing file is not an error. Returns the number of
+    /// pending evidence items loaded.
+    pub fn load(&mut self, path: PathBuf) -> Result<usize> {
+        let stored: StoredPool = match std::fs::read(&path) {
+            Ok(data) => serde_json::from_slice(&data)?,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => StoredPool::default(),
+            Err(e) => return Err(e.into()),
+        };
+        self.pending = stored.pending;
+        self.committed = stored.committed;
+        self.file = Some(path);
+        Ok(self.pending.len())
+    }
+
+    /// Checks `evidence` without adding it to the pool.
+    ///
+    /// `height` is the height we're currently deciding and `store` holds our
+    /// committed blocks and the validator set history.
+    pub fn check(
+        &self,
+        evidence: &Evidence,
+        height: u64,
+        store: &BlockStore,
+        params: &ConsensusParams,
+    ) -> Result<(), EvidenceError> {
+        let ev_height = evidence.height();
+        if ev_height > height {
+            return Err(EvidenceError::FromFuture { height: ev_height, current: height });
+        }
+        if height - ev_height > params.evidence_max_age_blocks {
+            return Err(EvidenceError::Expired { height: ev_height, current: height });
+        }
+        if self.committed.contains_key(&evidence.hash()) {
+            return Err(EvidenceError::AlreadyCommitted);
+        }
+        // Height 0 is the genesis state, whose validators are those of height 1.
+        let validators = store
+            .validators_at(ev_height.max(1))
+            .ok_or(EvidenceError::Invalid("no validator set at evidence height"))?;
+        evidence.verify(validators, store)
+    }
+
+    /// Verifies `evidence` and adds it to the pending set.
+    ///
+    /// Returns `Ok(false)` if it was already pending, so callers only gossip
+    /// evidence that is new to us.
+    pub fn add(
+        &mut self,
+        evidence: Evidence,
+        height: u64,
+        store: &BlockStore,
+        params: &ConsensusParams,
+    ) -> Result<bool, EvidenceError> {
+        if self.pending.contains(&evidence) {
+            return Ok(false);
+        }
+        self.check(&evidence, height, store, params)?;
+        self.pending.push(evidence);
+        self.persist();
+        Ok(true)
+    }
+
+    /// Returns up to `max` pending evidence items, oldest first, for inclusion in a block.
+    pub fn pending(&self, max: usize) -> Vec<Evidence> {
+        self.pending.iter().take(max).cloned().collect()
+    }
+
+    /// Returns `true` if `evidence` was committed in a block.
+    pub fn is_committed(&self, evidence: &Evidence) -> bool {
+        self.committed.contains_key(&evidence.hash())
+    }
+
+    /// Records that the block at `height` committed `evidence`, and drops
+    /// pending and committed entries that have expired.
+    pub fn mark_committed(&mut self, evidence: &[Evidence], height: u64, params: &ConsensusParams) {
+        for ev in evidence {
+            self.committed.insert(ev.hash(), height);
+        }
+        let committed = &self.committed;
+        let max_age = params.evidence_max_age_blocks;
+        self.pending
+            .retain(|ev| !committed.contains_key(&ev.hash()) && height.saturating_sub(ev.height()) <= max_age);
+        self.committed
+            .retain(|_, committed_at| height.saturating_sub(*committed_at) <= max_age);
+        self.persist();
+    }
+
+    /// Writes the pool to its file, if it has one. Failures are logged: losing
+    /// the file costs at most some re-verification after a restart.
+    fn persist(&self) {
+        let Some(path) = &self.file else {
+            return;
+        };
+        let stored = StoredPool {
+            pending: self.pending.clone(),
+            committed: self.committed.clone(),
+        };
+        let result = serde_json::to_vec_pretty(&stored)
+            .map_err(anyhow::Error::from)
+            .and_then(|data| std::fs::write(path, data).map_err(Into::into));
+        if let Err(e) = result {
+            warn!("Failed to persist evidence pool to {}: {:?}", path.display(), e);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::consensus::evidence::DuplicateVoteEvidence;
+    use crate::consensus::types::VoteType;
+    use crate::consensus::validator::{Validator, ValidatorSet};
+    use crate::consensus::vote::Vote;
+    use crate::p2p::key::NodeKey;
+
+    fn duplicate_vote(key: &NodeKey, height: u64) -> Evidence {
+        let vote = |hash: &str| {
+            let mut v = Vote::new(VoteType::Precommit, height, 0, hash.into(), "val".into());
+            v.sign(key);
+            v
+        };
+        Evidence::DuplicateVote(DuplicateVoteEvidence::new(vote("a"), vote("b")).unwrap())
+    }
+
+    #[test]
+    fn verifies_tracks_and_persists_evidence() {
+        let key = NodeKey::generate();
+        let validators = ValidatorSet::new(vec![Validator::new("val".into(), key.public_key(), 10)]);
+        let params = ConsensusParams {
+            evidence_max_age_blocks: 10,
+            ..ConsensusParams::default()
+        };
+        let path = std::env::temp_dir().join(format!("evidence-{}.json", uuid::Uuid::new_v4()));
+        let mut store = BlockStore::new();
+        store.save_validators(5, validators).unwrap();
+        let mut pool = EvidencePool::new();
+        pool.load(path.clone()).unwrap();
+
+        let ev = duplicate_vote(&key, 5);
+        assert_eq!(
+            pool.add(ev.clone(), 4, &store, &params),
+            Err(EvidenceError::FromFuture { height: 5, current: 4 })
+        );
+        assert_eq!(
+            pool.add(ev.clone(), 16, &store, &params),
+            Err(EvidenceError::Expired { height: 5, current: 16 })
+        );
+        let forged = duplicate_vote(&NodeKey::generate(), 5);
+        assert_eq!(pool.add(forged, 6, &store, &params), Err(EvidenceError::BadSignature));
+
+        assert_eq!(pool.add(ev.clone(), 6, &store, &params), Ok(true));
+        assert_eq!(pool.add(ev.clone(), 6, &store, &params), Ok(false));
+
+        // Pending evidence survives a restart.
+        let mut reloaded = EvidencePool::new();
+        assert_eq!(reloaded.load(path.clone()).unwrap(), 1);
+
+        // Once committed, it leaves the pending set and can't be added again.
+        reloaded.mark_committed(std::slice::from_ref(&ev), 7, &params);
+        assert!(reloaded.pending(10).is_empty());
+        assert!(reloaded.is_committed(&ev));
+        assert_eq!(
+            reloaded.add(ev, 8, &store, &params),
+            Err(EvidenceError::AlreadyCommitted)
+        );
+
+        std::fs::remove_file(path).unwrap();
+    }
+}
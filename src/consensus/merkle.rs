@@ -0,0 +1,134 @@
+//! A simple binary Merkle tree over byte slices, with inclusion proofs.
+//!
+//! The layout follows RFC 6962 (as Tendermint does): leaves are hashed as
+//! `SHA256(0x00 || data)`, inner nodes as `SHA256(0x01 || left || right)`, and
+//! a list of `n` items is split at the largest power of two below `n`.
+
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+
+/// A SHA-256 digest.
+pub type Hash = [u8; 32];
+
+fn leaf_hash(data: &[u8]) -> Hash {
+    Sha256::new().chain_update([0u8]).chain_update(data).finalize().into()
+}
+
+fn inner_hash(left: &Hash, right: &Hash) -> Hash {
+    Sha256::new()
+        .chain_update([1u8])
+        .chain_update(left)
+        .chain_update(right)
+        .finalize()
+        .into()
+}
+
+/// Largest power of two strictly less than `n` (`n` must be at least 2).
+fn split_point(n: usize) -> usize {
+    let mut k = 1;
+    while k * 2 < n {
+        k *= 2;
+    }
+    k
+}
+
+/// Proof that a leaf is the `index`-th of `total` items under some root.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct MerkleProof {
+    pub total: u32,
+    pub index: u32,
+    /// Sibling hashes from the leaf up to (but excluding) the root.
+    pub aunts: Vec<Hash>,
+}
+
+impl MerkleProof {
+    /// Returns `true` if `data` is the proven leaf under `root`.
+    pub fn verify(&self, root: &Hash, data: &[u8]) -> bool {
+        if self.index >= self.total {
+            return false;
+        }
+        compute_root(self.index as usize, self.total as usize, leaf_hash(data), &self.aunts)
+            .is_some_and(|computed| &computed == root)
+    }
+}
+
+fn compute_root(index: usize, total: usize, leaf: Hash, aunts: &[Hash]) -> Option<Hash> {
+    match total {
+        0 => None,
+        1 => aunts.is_empty().then_some(leaf),
+        _ => {
+            let (top, rest) = aunts.split_last()?;
+            let k = split_point(total);
+            if index < k {
+                Some(inner_hash(&compute_root(index, k, leaf, rest)?, top))
+            } else {
+                Some(inner_hash(top, &compute_root(index - k, total - k, leaf, rest)?))
+            }
+        }
+    }
+}
+
+/// Computes the root of `items` and an inclusion proof for every item.
+pub fn root_and_proofs<T: AsRef<[u8]>>(items: &[T]) -> (Hash, Vec<MerkleProof>) {
+    let total = items.len() as u32;
+    let (root, trails) = build(items);
+    let proofs = trails
+        .into_iter()
+        .enumerate()
+        .map(|(i, aunts)| MerkleProof {
+            total,
+            index: i as u32,
+            aunts,
+        })
+        .collect();
+    (root, proofs)
+}
+
+/// Returns the root and, for each leaf, its aunts ordered bottom-up.
+fn build<T: AsRef<[u8]>>(items: &[T]) -> (Hash, Vec<Vec<Hash>>) {
+    match items.len() {
+        0 => (Sha256::digest([]).into(), Vec::new()),
+        1 => (leaf_hash(items[0].as_ref()), vec![Vec::new()]),
+        n => {
+            let k = split_point(n);
+            let (left, mut left_trails) = build(&items[..k]);
+            let (right, right_trails) = build(&items[k..]);
+            for trail in &mut left_trails {
+                trail.push(right);
+            }
+            left_trails.extend(right_trails.into_iter().map(|mut trail| {
+                trail.push(left);
+                trail
+            }));
+            (inner_hash(&left, &right), left_trails)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn every_proof_verifies_and_only_for_its_own_leaf() {
+        for n in 1..=9 {
+            let items: Vec<Vec<u8>> = (0..n).map(|i| vec![i as u8; 3]).collect();
+            let (root, proofs) = root_and_proofs(&items);
+            for (i, proof) in proofs.iter().enumerate() {
+                assert!(proof.verify(&root, &items[i]), "n={} i={}", n, i);
+                let other = &items[(i + 1) % n];
+                assert_eq!(proof.verify(&root, other), n == 1, "n={} i={}", n, i);
+            }
+        }
+    }
+
+    #[test]
+    fn tampered_proof_is_rejected() {
+        let items = [b"a".to_vec(), b"b".to_vec(), b"c".to_vec()];
+        let (root, mut proofs) = root_and_proofs(&items);
+        proofs[2].aunts[0][0] ^= 1;
+        assert!(!proofs[2].verify(&root, b"c"));
+        proofs[1].index = 7;
+        assert!(!proofs[1].verify(&root, b"b"));
+    }
+}
@@ -1,26 +1,63 @@
-/// The consensus module includes the main data structures and logic
-/// needed to run a simplified Tendermint-like round-based consensus.
-///
-/// It consists of:
-/// - A `ConsensusState` struct that holds references to shared data.
-/// - The main `run_consensus_loop` function, which drives the consensus steps.
-/// - Submodules like `state.rs`, `types.rs`, and `validator.rs`.
+//! The consensus module includes the main data structures and logic
+//! needed to run a simplified Tendermint-like round-based consensus.
+//!
+//! It consists of:
+//! - A `ConsensusState` struct that holds references to shared data.
+//! - The main `run_consensus_loop` function, which drives the consensus steps.
+//! - Submodules like `state.rs`, `types.rs`, `validator.rs` and `block.rs`.
 
 use anyhow::Result;
+use std::future::Future;
+use std::net::SocketAddr;
+use std::path::PathBuf;
 use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use ed25519_dalek::VerifyingKey;
+use tokio::task::JoinHandle;
+use tokio_util::sync::CancellationToken;
+use tokio_util::task::TaskTracker;
 
-use tracing::{debug, info, warn};
+use tracing::{debug, error, info, warn};
 
-use crate::p2p::message::P2PMessage;
+use crate::app::{Application, CheckTxResponse, QueryResponse};
+use crate::config::MempoolConfig;
+use crate::events::EventBus;
+use crate::genesis::Genesis;
+use crate::indexer::Indexer;
+use crate::mempool::{Mempool, MempoolError};
+use crate::metrics::Metrics;
+use crate::p2p::codec::{FrameLimits, WireFormat};
+use crate::p2p::key::NodeKey;
+use crate::p2p::message::{Channel, P2PMessage};
 use crate::p2p::peer::{Peer, PeerManager};
+use crate::p2p::queues::SendQueues;
+use crate::p2p::score::Misbehavior;
+use crate::p2p::tcp::TcpTransport;
+use crate::p2p::transport::{connect_to_peer, Transport};
 
+pub mod bits;
+pub mod block;
+pub mod commit;
+pub mod evidence;
+pub mod evidence_pool;
+pub mod merkle;
+pub mod reactor;
 pub mod state;
+pub mod store;
 pub mod types;
 pub mod validator;
+pub mod vote;
 
+use bits::BitArray;
+use block::{Part, PartSetHeader, Tx};
+use commit::Commit;
+use evidence::Evidence;
+use reactor::ConsensusReactor;
+use types::VoteType;
 use state::ConsensusCore;
-use types::{RoundState, Step};
-use validator::ValidatorSet;
+use store::BlockStore;
+use vote::Vote;
 
 /// `ConsensusState` is the primary handle that the rest of the application
 /// uses to interact with the consensus engine.
@@ -28,121 +65,475 @@ use validator::ValidatorSet;
 /// It holds:
 /// - A unique `node_id` for the local node
 /// - The local listen address
-/// - A `PeerManager` to track known peers
+/// - The wire format used for outgoing messages and the per-channel size limits for incoming ones
+/// - The transport used to reach peers
+/// - The chain's genesis hash, which peers must share
+/// - A `PeerManager` to track known peers, and the queues of messages waiting to be sent to them
 /// - A `ConsensusCore` that implements the internal logic
+/// - A `ConsensusReactor` that tracks what each peer still needs
+/// - The node's `Metrics`, shared with the `ConsensusCore`
+/// - The shutdown token every long-running task watches, and the tasks joined on shutdown
 #[derive(Clone)]
 pub struct ConsensusState {
     /// The unique ID of this node.
     pub node_id: String,
     /// The TCP address (host:port) on which this node listens.
     pub listen_addr: String,
+    /// Encoding for messages we send. Inbound frames are accepted in any format.
+    pub wire_format: WireFormat,
+    /// Maximum encoded message size per channel, enforced on inbound frames.
+    pub frame_limits: FrameLimits,
+    /// How connections are established (TCP by default).
+    pub transport: Arc<dyn Transport>,
+    /// Hash of the genesis this node started from (empty until `init_chain`).
+    /// Peers announcing a different hash are refused.
+    pub genesis_hash: String,
 
     /// Manages the list of known peers.
     peer_manager: PeerManager,
 
+    /// Messages waiting to be written to each peer's connection.
+    queues: SendQueues,
+
     /// The core consensus logic and state.
     consensus_core: Arc<Mutex<ConsensusCore>>,
+
+    /// Tracks peers' round states for vote and block-part gossip.
+    reactor: ConsensusReactor,
+
+    /// Where the node's metrics are reported.
+    metrics: Metrics,
+
+    /// Cancelled when the node shuts down.
+    shutdown: CancellationToken,
+
+    /// Tasks that touch the node's state or talk to peers (listeners,
+    /// connections, dialers, loops and outgoing messages), joined on shutdown.
+    tasks: TaskTracker,
 }
 
 impl ConsensusState {
     /// Creates a new `ConsensusState` with a given `node_id` and `listen_addr`.
     ///
-    /// Also initializes a `PeerManager` and a `ConsensusCore`.
+    /// Also initializes a `PeerManager` (with default connection limits) and a `ConsensusCore`.
     pub fn new(node_id: String, listen_addr: String) -> Self {
-        let peer_manager = PeerManager::new();
+        Self::with_peer_manager(node_id, listen_addr, PeerManager::new())
+    }
+
+    /// Creates a new `ConsensusState` that uses an already configured `PeerManager`.
+    pub fn with_peer_manager(node_id: String, listen_addr: String, peer_manager: PeerManager) -> Self {
         let consensus_core = ConsensusCore::new(node_id.clone(), listen_addr.clone());
+        let metrics = consensus_core.metrics.clone();
 
         Self {
             node_id,
             listen_addr,
+            wire_format: WireFormat::default(),
+            frame_limits: FrameLimits::default(),
+            transport: Arc::new(TcpTransport),
+            genesis_hash: String::new(),
             peer_manager,
+            queues: SendQueues::default(),
             consensus_core: Arc::new(Mutex::new(consensus_core)),
+            reactor: ConsensusReactor::new(),
+            metrics,
+            shutdown: CancellationToken::new(),
+            tasks: TaskTracker::new(),
         }
     }
 
     /// Called whenever a P2P message arrives from a peer.
     ///
     /// This function delegates to more specific handlers depending on the message type.
-    pub async fn process_p2p_message(&self, msg: P2PMessage) -> Result<()> {
-        debug!("process_p2p_message: {:?}", msg);
+    /// `peer_id` is the node ID the connection announced in its handshake; the
+    /// gossip reactor's view of the peer is keyed by it, never by IDs in the
+    /// message itself.
+    pub async fn process_p2p_message(&self, peer_id: &str, msg: P2PMessage) -> Result<()> {
+        debug!("process_p2p_message from {}: {:?}", peer_id, msg);
 
         match msg {
             // A peer announces itself
-            P2PMessage::PeerInfo { node_id, listen_addr } => {
+            P2PMessage::PeerInfo { node_id, listen_addr, .. } => {
+                // The genesis hash was checked with the handshake; see `handle_connection`.
                 info!("Received PeerInfo from {} at {}", node_id, listen_addr);
                 let peer = Peer::new(node_id, listen_addr);
                 self.peer_manager.add_peer(peer);
+                self.metrics.p2p.peers.set(self.peer_manager.get_all_peers().len() as i64);
             }
             // A new block proposal
-            P2PMessage::Proposal { proposer_id, round, block } => {
-                self.handle_proposal(proposer_id, round, block).await?;
+            P2PMessage::Proposal { proposer_id, height, round, block_hash, parts_header } => {
+                self.handle_proposal(proposer_id, height, round, block_hash, parts_header).await?;
+            }
+            // A part of the proposed block
+            P2PMessage::BlockPart { height, round, part } => {
+                self.handle_block_part(height, round, part).await?;
             }
-            // A prevote
-            P2PMessage::Prevote { voter_id, round, block_hash } => {
-                self.handle_prevote(voter_id, round, block_hash).await?;
+            // A prevote or precommit
+            P2PMessage::Vote { vote } => {
+                self.handle_vote(vote).await?;
+            }
+            // Evidence of validator misbehavior
+            P2PMessage::Evidence { evidence } => {
+                self.handle_evidence(evidence).await?;
+            }
+            // A peer is shutting down; only ever the sender itself
+            P2PMessage::Goodbye => {
+                if self.peer_manager.remove_peer(peer_id).is_some() {
+                    info!("Peer {} is shutting down", peer_id);
+                }
+                self.reactor.remove_peer(peer_id);
+                self.metrics.p2p.peers.set(self.peer_manager.get_all_peers().len() as i64);
             }
-            // A precommit
-            P2PMessage::Precommit { voter_id, round, block_hash } => {
-                self.handle_precommit(voter_id, round, block_hash).await?;
+            // A transaction for the mempool
+            P2PMessage::Tx { tx } => {
+                self.consensus_core.lock().unwrap().on_tx(tx);
             }
             // A commit
-            P2PMessage::Commit { block_hash, round } => {
-                self.handle_commit(block_hash, round).await?;
+            P2PMessage::Commit { commit } => {
+                self.handle_commit(commit).await?;
+            }
+            // A peer reports its progress
+            P2PMessage::NewRoundStep { height, round, step } => {
+                self.reactor.apply_new_round_step(peer_id, height, round, step);
+            }
+            // A peer reports a vote it holds
+            P2PMessage::HasVote { height, round, vote_type, index } => {
+                let num_validators = self.consensus_core.lock().unwrap().validators.len();
+                self.reactor
+                    .apply_has_vote(peer_id, height, round, vote_type, index as usize, num_validators);
+            }
+            // A peer claims a +2/3 majority and wants to know which of those votes we hold
+            P2PMessage::VoteSetMaj23 { height, round, vote_type, block_hash } => {
+                self.handle_vote_set_maj23(peer_id, height, round, vote_type, block_hash);
+            }
+            // A peer answers our `VoteSetMaj23`
+            P2PMessage::VoteSetBits { height, round, vote_type, block_hash, votes } => {
+                self.handle_vote_set_bits(peer_id, height, round, vote_type, block_hash, votes)?;
             }
         }
 
+        // Send out anything the handler decided to broadcast (e.g. our prevote)
+        self.flush_outbox().await;
+
         Ok(())
     }
 
     // ----- Handlers for each message type -----
 
     /// Handle a `Proposal` message from a peer (including ourselves).
-    async fn handle_proposal(&self, proposer_id: String, round: u64, block: String) -> Result<()> {
+    async fn handle_proposal(
+        &self,
+        proposer_id: String,
+        height: u64,
+        round: u64,
+        block_hash: String,
+        parts_header: PartSetHeader,
+    ) -> Result<()> {
+        let mut core = self.consensus_core.lock().unwrap();
+        core.on_proposal(proposer_id, height, round, block_hash, parts_header)
+    }
+
+    /// Handle a `BlockPart` message from a peer.
+    async fn handle_block_part(&self, height: u64, round: u64, part: Part) -> Result<()> {
         let mut core = self.consensus_core.lock().unwrap();
-        core.on_proposal(proposer_id, round, block)
+        core.on_block_part(height, round, part)
     }
 
-    /// Handle a `Prevote` message from a peer (including ourselves).
-    async fn handle_prevote(&self, voter_id: String, round: u64, block_hash: String) -> Result<()> {
+    /// Handle a `Vote` message from a peer (including ourselves).
+    async fn handle_vote(&self, vote: Vote) -> Result<()> {
         let mut core = self.consensus_core.lock().unwrap();
-        core.on_prevote(voter_id, round, block_hash)
+        core.on_vote(vote)
     }
 
-    /// Handle a `Precommit` message from a peer (including ourselves).
-    async fn handle_precommit(&self, voter_id: String, round: u64, block_hash: String) -> Result<()> {
+    /// Handle an `Evidence` message from a peer.
+    async fn handle_evidence(&self, evidence: Evidence) -> Result<()> {
         let mut core = self.consensus_core.lock().unwrap();
-        core.on_precommit(voter_id, round, block_hash)
+        core.on_evidence(evidence)
     }
 
     /// Handle a `Commit` message from a peer (including ourselves).
-    async fn handle_commit(&self, block_hash: String, round: u64) -> Result<()> {
+    async fn handle_commit(&self, commit: Commit) -> Result<()> {
         let mut core = self.consensus_core.lock().unwrap();
-        core.on_commit(block_hash, round)
+        core.on_commit(commit)
+    }
+
+    /// Handle a `VoteSetMaj23` claim by telling the peer which of the claimed
+    /// votes we hold, so it can send us the rest.
+    fn handle_vote_set_maj23(
+        &self,
+        peer_id: &str,
+        height: u64,
+        round: u64,
+        vote_type: VoteType,
+        block_hash: String,
+    ) {
+        let Some(peer) = self.peer_manager.get_peer(peer_id) else {
+            return;
+        };
+        let reply = {
+            let core = self.consensus_core.lock().unwrap();
+            if core.round_state.height != height || core.round_state.round != round {
+                return;
+            }
+            P2PMessage::VoteSetBits {
+                height,
+                round,
+                vote_type,
+                votes: core.vote_bits(vote_type, &block_hash),
+                block_hash,
+            }
+        };
+        self.send_to_peer(&peer, &reply);
+    }
+
+    /// Handle a `VoteSetBits` answer to one of our `VoteSetMaj23` claims.
+    /// Answers for another height or round than ours are dropped, since their
+    /// queries have expired.
+    ///
+    /// Fails with [`Misbehavior::UnsolicitedResponse`] if we never asked, and
+    /// with [`Misbehavior::MalformedMessage`] if the bit array doesn't match
+    /// the validator set.
+    fn handle_vote_set_bits(
+        &self,
+        peer_id: &str,
+        height: u64,
+        round: u64,
+        vote_type: VoteType,
+        block_hash: String,
+        votes: BitArray,
+    ) -> Result<()> {
+        let core = self.consensus_core.lock().unwrap();
+        // Queries of other heights and rounds have expired; late answers are dropped.
+        if height != core.round_state.height || round != core.round_state.round {
+            return Ok(());
+        }
+        if !votes.is_well_formed() || votes.len() != core.validators.len() {
+            return Err(Misbehavior::MalformedMessage.into());
+        }
+        let ours = core.vote_bits(vote_type, &block_hash);
+        let solicited = self
+            .reactor
+            .apply_vote_set_bits(peer_id, height, round, vote_type, &block_hash, &ours, &votes);
+        if !solicited {
+            return Err(Misbehavior::UnsolicitedResponse.into());
+        }
+        Ok(())
     }
 
     // ----- Utilities -----
 
+    /// Starts the chain described by `genesis` (see [`ConsensusCore::init_chain`])
+    /// and remembers its hash for the peer handshake.
+    ///
+    /// Call after `open_block_store`, so a node that already has blocks
+    /// resumes instead of re-initializing the application.
+    pub fn init_chain(&mut self, genesis: &Genesis) -> Result<()> {
+        self.consensus_core.lock().unwrap().init_chain(genesis)?;
+        self.genesis_hash = genesis.hash();
+        Ok(())
+    }
+
+    /// Returns our `PeerInfo` announcement, sent at the start of every outbound connection.
+    pub fn peer_info(&self) -> P2PMessage {
+        P2PMessage::PeerInfo {
+            node_id: self.node_id.clone(),
+            listen_addr: self.listen_addr.clone(),
+            genesis_hash: self.genesis_hash.clone(),
+        }
+    }
+
+    /// Returns the public key our votes are signed with.
+    pub fn public_key(&self) -> VerifyingKey {
+        self.consensus_core.lock().unwrap().public_key()
+    }
+
+    /// Persists the evidence pool to `path`, loading any evidence stored there.
+    /// Returns the number of pending evidence items loaded.
+    pub fn load_evidence(&self, path: impl Into<PathBuf>) -> Result<usize> {
+        let mut core = self.consensus_core.lock().unwrap();
+        core.evidence_pool.load(path.into())
+    }
+
+    /// Opens the block store in `dir` and resumes consensus after its latest
+    /// block. Returns the height of that block (0 for an empty store).
+    pub fn open_block_store(&self, dir: impl Into<PathBuf>) -> Result<u64> {
+        let store = BlockStore::open(dir.into())?;
+        let height = store.height();
+        self.consensus_core.lock().unwrap().set_block_store(store);
+        Ok(height)
+    }
+
+    /// Opens the transaction and block indexes in `dir`.
+    pub fn open_indexer(&self, dir: impl Into<PathBuf>) -> Result<()> {
+        let indexer = Indexer::open(dir.into())?;
+        self.consensus_core.lock().unwrap().indexer = indexer;
+        Ok(())
+    }
+
+    /// Replaces the key our votes are signed with.
+    pub fn set_signing_key(&self, key: NodeKey) {
+        self.consensus_core.lock().unwrap().set_signing_key(key);
+    }
+
+    /// Replaces the application committed blocks are executed by.
+    pub fn set_application(&self, app: Box<dyn Application>) {
+        self.consensus_core.lock().unwrap().set_application(app);
+    }
+
+    /// Starts a new round (see [`ConsensusCore::start_new_round`]) and
+    /// broadcasts our proposal and votes, if any.
+    pub async fn start_new_round(&self, data: String) {
+        self.consensus_core.lock().unwrap().start_new_round(data);
+        self.flush_outbox().await;
+    }
+
+    /// Replaces the mempool with an empty one using `config`'s limits, and
+    /// sizes the mempool channel's frame limit to match.
+    pub fn set_mempool_config(&mut self, config: MempoolConfig) {
+        self.frame_limits.set(Channel::Mempool, config.max_tx_bytes + 1024);
+        self.consensus_core.lock().unwrap().mempool = Mempool::new(config);
+    }
+
+    /// Submits a transaction to the mempool (see [`ConsensusCore::check_tx`])
+    /// and gossips it if it was accepted.
+    pub async fn check_tx(&self, tx: Tx) -> Result<CheckTxResponse, MempoolError> {
+        let response = self.consensus_core.lock().unwrap().check_tx(tx)?;
+        self.flush_outbox().await;
+        Ok(response)
+    }
+
+    /// Queries the application state.
+    pub fn query(&self, path: &str, data: &[u8]) -> QueryResponse {
+        self.consensus_core.lock().unwrap().query(path, data)
+    }
+
+    /// Replaces the metrics everything is reported in, e.g. with ones using
+    /// the configured namespace.
+    pub fn set_metrics(&mut self, metrics: Metrics) {
+        self.consensus_core.lock().unwrap().metrics = metrics.clone();
+        self.metrics = metrics;
+    }
+
+    /// Returns the node's metrics.
+    pub fn metrics(&self) -> &Metrics {
+        &self.metrics
+    }
+
+    /// Returns the bus consensus events are published on.
+    pub fn event_bus(&self) -> EventBus {
+        self.consensus_core.lock().unwrap().event_bus.clone()
+    }
+
+    /// Runs `f` on the consensus core, for read-only views of the node such as RPC.
+    pub fn inspect<R>(&self, f: impl FnOnce(&ConsensusCore) -> R) -> R {
+        f(&self.consensus_core.lock().unwrap())
+    }
+
+    /// Returns the token cancelled when the node shuts down. Long-running
+    /// tasks (listeners, connections, dialers, loops and servers) stop once it is.
+    pub fn shutdown_token(&self) -> CancellationToken {
+        self.shutdown.clone()
+    }
+
+    /// Spawns `task` so that [`ConsensusState::shutdown`] waits for it to
+    /// finish before flushing storage. Long-running tasks must stop once the
+    /// shutdown token is cancelled.
+    pub fn spawn<F>(&self, task: F) -> JoinHandle<F::Output>
+    where
+        F: Future + Send + 'static,
+        F::Output: Send + 'static,
+    {
+        self.tasks.spawn(task)
+    }
+
+    /// Shuts the node down: cancels the shutdown token, queues whatever the
+    /// consensus core still has to send and a `Goodbye` to every peer, closes
+    /// the send queues, waits up to `timeout` for connections to write out
+    /// their queues and for every task started with [`ConsensusState::spawn`]
+    /// to stop, and syncs the block store and indexes to disk.
+    pub async fn shutdown(&self, timeout: Duration) {
+        self.shutdown.cancel();
+        self.flush_outbox().await;
+        self.broadcast_message(&P2PMessage::Goodbye).await;
+        self.queues.close();
+
+        self.tasks.close();
+        if tokio::time::timeout(timeout, self.tasks.wait()).await.is_err() {
+            warn!("Gave up on {} tasks after {:?}", self.tasks.len(), timeout);
+        }
+
+        if let Err(e) = self.consensus_core.lock().unwrap().flush_storage() {
+            warn!("Failed to flush storage: {:?}", e);
+        }
+    }
+
+    /// Returns the peer manager shared by all connections.
+    pub fn peer_manager(&self) -> &PeerManager {
+        &self.peer_manager
+    }
+
+    /// Returns the queues of messages waiting to be sent to peers.
+    pub fn send_queues(&self) -> &SendQueues {
+        &self.queues
+    }
+
+    /// Returns the gossip reactor's view of every peer's round state.
+    pub fn reactor(&self) -> &ConsensusReactor {
+        &self.reactor
+    }
+
+    /// Broadcasts every message the consensus core has queued. If consensus
+    /// has halted, also cancels the shutdown token, so the node stops.
+    pub async fn flush_outbox(&self) {
+        let (messages, halted) = {
+            let mut core = self.consensus_core.lock().unwrap();
+            (core.take_outbox(), core.halted().is_some())
+        };
+        if halted && !self.shutdown.is_cancelled() {
+            error!("Consensus halted, shutting down");
+            self.shutdown.cancel();
+        }
+        for msg in &messages {
+            self.broadcast_message(msg).await;
+        }
+    }
+
     /// Broadcasts a message to all known peers.
     ///
-    /// This function looks up all peers in the `PeerManager` and,
-    /// for each peer, spawns a task to send the message via `send_message`.
+    /// This function looks up all peers in the `PeerManager` and
+    /// sends the message to each of them via `send_to_peer`.
     pub async fn broadcast_message(&self, msg: &P2PMessage) {
-        use crate::p2p::transport::send_message;
         let peers = self.peer_manager.get_all_peers();
         for peer in peers {
-            let addr = match peer.listen_addr.parse() {
-                Ok(a) => a,
+            self.send_to_peer(&peer, msg);
+        }
+    }
+
+    /// Sends a message to a single peer.
+    ///
+    /// The message is queued for the connection serving the peer (see
+    /// [`SendQueues`]). If there is none, a connection to the peer's listen
+    /// address is dialed with [`connect_to_peer`] and serves the queue from
+    /// then on. Messages to a peer whose queue is full are dropped.
+    pub fn send_to_peer(&self, peer: &Peer, msg: &P2PMessage) {
+        if let Some(queue) = self.queues.register(&peer.id) {
+            match peer.listen_addr.parse::<SocketAddr>() {
+                Ok(addr) => {
+                    let cs = self.clone();
+                    self.spawn(async move {
+                        if let Err(e) = connect_to_peer(cs, addr, Some(queue)).await {
+                            warn!("Failed to connect to {}: {:?}", addr, e);
+                        }
+                    });
+                }
                 Err(e) => {
                     warn!("Invalid peer address {}: {:?}", peer.listen_addr, e);
-                    continue;
-                }
-            };
-            let msg_clone = msg.clone();
-            tokio::spawn(async move {
-                if let Err(e) = send_message(addr, &msg_clone).await {
-                    warn!("Failed to send {} to {}: {:?}", msg_clone.msg_type(), addr, e);
+                    return;
                 }
-            });
+            }
+        }
+        if let Err(e) = self.queues.push(&peer.id, msg.clone()) {
+            warn!("Dropped {} for peer {}: {}", msg.msg_type(), peer.id, e);
         }
     }
 }
@@ -151,21 +542,25 @@ impl ConsensusState {
 ///
 /// In real Tendermint, there is a complex interplay of
 /// timeouts, round increments, and the Propose/Prevote/Precommit steps.
-/// This simplified loop just starts a new round (with a new block) every 10 seconds.
+/// This simplified loop just starts a new round (with a new block) every
+/// `interval`, until the node shuts down.
 ///
 /// # Arguments
 ///
 /// * `cs` - The consensus state to operate on.
-pub async fn run_consensus_loop(cs: ConsensusState) {
-    use tokio::time::{sleep, Duration};
+/// * `interval` - Time between rounds.
+pub async fn run_consensus_loop(cs: ConsensusState, interval: Duration) {
+    let shutdown = cs.shutdown_token();
     loop {
-        sleep(Duration::from_secs(10)).await;
+        tokio::select! {
+            _ = shutdown.cancelled() => return,
+            _ = tokio::time::sleep(interval) => {}
+        }
 
         let new_block = format!("block-{}", uuid::Uuid::new_v4());
         info!("Proposing a new block: {}", new_block);
 
-        let mut core = cs.consensus_core.lock().unwrap();
-        core.start_new_round(new_block);
+        cs.start_new_round(new_block).await;
     }
 }
 
@@ -0,0 +1,393 @@
+//! The consensus gossip reactor.
+//!
+//! Broadcasting a message once is not enough: a peer that was briefly
+//! disconnected, or simply dropped a message, would never see it again.
+//! The reactor instead keeps a picture of every peer's round state, built from
+//! the `NewRoundStep` and `HasVote` messages peers announce and kept under the
+//! node ID of the connection they arrived on, and periodically
+//! sends each peer whatever it is still missing for the round we are both in:
+//! - the proposal,
+//! - block parts it doesn't have yet,
+//! - prevotes and precommits it doesn't have yet.
+//!
+//! Votes are tracked as bit arrays indexed by the voter's position in the
+//! `ValidatorSet`, parts as bit arrays indexed by part number. Whatever we
+//! send is marked as held by the peer right away, so it is sent only once per
+//! round unless the peer tells us otherwise.
+//!
+//! Because that bookkeeping is optimistic, the reactor also reconciles it
+//! every [`QUERY_MAJ23_INTERVAL`]: for each vote type where we have seen a +2/3
+//! majority, it sends the peer a `VoteSetMaj23` claim. The peer answers with a
+//! `VoteSetBits` listing exactly which of those votes it holds, and we then
+//! gossip only the missing ones. A `VoteSetBits` we never asked for is
+//! reported as an unsolicited response. Queries expire once we move to another
+//! height or round, so at most one per peer and vote type is outstanding.
+
+use std::collections::{HashMap, HashSet};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use tracing::debug;
+
+use crate::p2p::message::P2PMessage;
+
+use super::bits::BitArray;
+use super::state::ConsensusCore;
+use super::types::{Step, VoteType};
+use super::ConsensusState;
+
+/// How often the reactor looks for something to send to each peer.
+pub const PEER_GOSSIP_INTERVAL: Duration = Duration::from_millis(100);
+
+/// How often we ask peers which votes they hold for the majorities we've seen.
+pub const QUERY_MAJ23_INTERVAL: Duration = Duration::from_secs(2);
+
+/// What we know about a peer's consensus progress.
+#[derive(Debug, Clone, Default)]
+pub struct PeerRoundState {
+    pub height: u64,
+    pub round: u64,
+    pub step: Step,
+    /// Whether the peer has the proposal for its current round.
+    pub has_proposal: bool,
+    /// Which block parts the peer has, by part index.
+    pub proposal_parts: BitArray,
+    /// Whose prevotes the peer has, by validator index.
+    pub prevotes: BitArray,
+    /// Whose precommits the peer has, by validator index.
+    pub precommits: BitArray,
+}
+
+impl PeerRoundState {
+    fn votes_mut(&mut self, vote_type: VoteType) -> &mut BitArray {
+        match vote_type {
+            VoteType::Prevote => &mut self.prevotes,
+            VoteType::Precommit => &mut self.precommits,
+        }
+    }
+}
+
+/// Sets bit `index` of `bits`, first growing the array to `len` bits if it is
+/// shorter. Returns the previous value of the bit.
+fn mark(bits: &mut BitArray, index: usize, len: usize) -> bool {
+    if bits.len() < len {
+        *bits = bits.or(&BitArray::new(len));
+    }
+    bits.set(index)
+}
+
+/// A `VoteSetMaj23` we sent and are waiting on a `VoteSetBits` answer for.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+struct Maj23Query {
+    peer_id: String,
+    height: u64,
+    round: u64,
+    vote_type: VoteType,
+    block_hash: String,
+}
+
+/// Per-peer round states, shared by the message handlers and the gossip loop.
+#[derive(Clone, Default)]
+pub struct ConsensusReactor {
+    peers: Arc<Mutex<HashMap<String, PeerRoundState>>>,
+    /// Outstanding `VoteSetMaj23` queries.
+    queries: Arc<Mutex<HashSet<Maj23Query>>>,
+}
+
+impl ConsensusReactor {
+    /// Creates a reactor that knows nothing about any peer yet.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Returns a copy of what we know about `peer_id`.
+    pub fn peer_state(&self, peer_id: &str) -> Option<PeerRoundState> {
+        self.peers.lock().unwrap().get(peer_id).cloned()
+    }
+
+    /// Records a `NewRoundStep` from `peer_id`. Moving to a new height or
+    /// round clears everything we knew the peer had.
+    pub fn apply_new_round_step(&self, peer_id: &str, height: u64, round: u64, step: Step) {
+        let mut peers = self.peers.lock().unwrap();
+        let prs = peers.entry(peer_id.to_string()).or_default();
+        if prs.height != height || prs.round != round {
+            *prs = PeerRoundState {
+                height,
+                round,
+                ..PeerRoundState::default()
+            };
+        }
+        prs.step = step;
+    }
+
+    /// Records a `HasVote` from `peer_id`. Ignored unless it matches the
+    /// peer's current height and round and `index` is a valid validator position.
+    pub fn apply_has_vote(
+        &self,
+        peer_id: &str,
+        height: u64,
+        round: u64,
+        vote_type: VoteType,
+        index: usize,
+        num_validators: usize,
+    ) {
+        let mut peers = self.peers.lock().unwrap();
+        if let Some(prs) = peers.get_mut(peer_id) {
+            if prs.height == height && prs.round == round && index < num_validators {
+                mark(prs.votes_mut(vote_type), index, num_validators);
+            }
+        }
+    }
+
+    /// Applies a `VoteSetBits` answer from `peer_id`.
+    ///
+    /// `our_votes` are the votes we hold for the queried block. For those,
+    /// the peer's answer replaces our guess; votes outside that set keep
+    /// whatever we knew before.
+    ///
+    /// Returns `false` if we never asked `peer_id` this question.
+    #[allow(clippy::too_many_arguments)]
+    pub fn apply_vote_set_bits(
+        &self,
+        peer_id: &str,
+        height: u64,
+        round: u64,
+        vote_type: VoteType,
+        block_hash: &str,
+        our_votes: &BitArray,
+        their_votes: &BitArray,
+    ) -> bool {
+        let query = Maj23Query {
+            peer_id: peer_id.to_string(),
+            height,
+            round,
+            vote_type,
+            block_hash: block_hash.to_string(),
+        };
+        if !self.queries.lock().unwrap().remove(&query) {
+            return false;
+        }
+
+        let mut peers = self.peers.lock().unwrap();
+        if let Some(prs) = peers.get_mut(peer_id) {
+            if prs.height == height && prs.round == round {
+                let votes = prs.votes_mut(vote_type);
+                *votes = votes.sub(our_votes).or(their_votes);
+            }
+        }
+        true
+    }
+
+    /// Forgets a peer, e.g. after it disconnected.
+    pub fn remove_peer(&self, peer_id: &str) {
+        self.peers.lock().unwrap().remove(peer_id);
+        self.queries.lock().unwrap().retain(|q| q.peer_id != peer_id);
+    }
+
+    /// Returns a `VoteSetMaj23` claim for every vote type in which we have
+    /// seen a +2/3 majority in the round `peer_id` is also in, and remembers
+    /// that we're waiting for the peer's answers. Queries of any earlier
+    /// height or round are dropped first.
+    pub fn maj23_queries(&self, peer_id: &str, core: &ConsensusCore) -> Vec<P2PMessage> {
+        let rs = &core.round_state;
+        self.queries
+            .lock()
+            .unwrap()
+            .retain(|q| q.height == rs.height && q.round == rs.round);
+        let same_round = self
+            .peer_state(peer_id)
+            .is_some_and(|prs| prs.height == rs.height && prs.round == rs.round);
+        if !same_round {
+            return Vec::new();
+        }
+
+        let mut queries = self.queries.lock().unwrap();
+        let mut out = Vec::new();
+        for vote_type in [VoteType::Prevote, VoteType::Precommit] {
+            let Some(block_hash) = core.two_thirds_majority(vote_type) else {
+                continue;
+            };
+            queries.insert(Maj23Query {
+                peer_id: peer_id.to_string(),
+                height: rs.height,
+                round: rs.round,
+                vote_type,
+                block_hash: block_hash.clone(),
+            });
+            out.push(P2PMessage::VoteSetMaj23 {
+                height: rs.height,
+                round: rs.round,
+                vote_type,
+                block_hash,
+            });
+        }
+        out
+    }
+
+    /// Returns the messages `peer_id` is missing from our current round and
+    /// marks them as held by the peer.
+    pub fn next_messages(&self, peer_id: &str, core: &ConsensusCore) -> Vec<P2PMessage> {
+        let mut peers = self.peers.lock().unwrap();
+        let Some(prs) = peers.get_mut(peer_id) else {
+            return Vec::new();
+        };
+        let rs = &core.round_state;
+        if prs.height != rs.height || prs.round != rs.round {
+            return Vec::new();
+        }
+
+        let mut out = Vec::new();
+
+        // The proposal, then any parts the peer still lacks
+        if let (Some(proposer_id), Some(block_hash), Some(parts)) =
+            (&rs.proposer_id, &rs.proposal_block_hash, &rs.proposal_parts)
+        {
+            if !prs.has_proposal {
+                prs.has_proposal = true;
+                out.push(P2PMessage::Proposal {
+                    proposer_id: proposer_id.clone(),
+                    height: rs.height,
+                    round: rs.round,
+                    block_hash: block_hash.clone(),
+                    parts_header: parts.header().clone(),
+                });
+            }
+            for index in 0..parts.header().total {
+                if let Some(part) = parts.part(index) {
+                    if !mark(&mut prs.proposal_parts, index as usize, parts.header().total as usize) {
+                        out.push(P2PMessage::BlockPart {
+                            height: rs.height,
+                            round: rs.round,
+                            part: part.clone(),
+                        });
+                    }
+                }
+            }
+        }
+
+        // Votes the peer lacks
+        for (vote_type, votes) in [(VoteType::Prevote, &rs.prevotes), (VoteType::Precommit, &rs.precommits)] {
+            for (voter_id, vote) in votes {
+                let Some(index) = core.validators.index_of(voter_id) else {
+                    continue;
+                };
+                if mark(prs.votes_mut(vote_type), index, core.validators.len()) {
+                    continue;
+                }
+                out.push(P2PMessage::Vote { vote: vote.clone() });
+            }
+        }
+
+        out
+    }
+}
+
+/// Runs the gossip loop until the node shuts down: every [`PEER_GOSSIP_INTERVAL`], sends each
+/// known peer whatever it is missing from our current round, and every
+/// [`QUERY_MAJ23_INTERVAL`] asks it which votes it holds for our majorities.
+pub async fn run_gossip_loop(cs: ConsensusState) {
+    let mut last_query = tokio::time::Instant::now();
+    let shutdown = cs.shutdown_token();
+    loop {
+        tokio::select! {
+            _ = shutdown.cancelled() => return,
+            _ = tokio::time::sleep(PEER_GOSSIP_INTERVAL) => {}
+        }
+        let query_maj23 = last_query.elapsed() >= QUERY_MAJ23_INTERVAL;
+        if query_maj23 {
+            last_query = tokio::time::Instant::now();
+        }
+
+        for peer in cs.peer_manager().get_all_peers() {
+            let messages = {
+                let core = cs.consensus_core.lock().unwrap();
+                let mut messages = cs.reactor.next_messages(&peer.id, &core);
+                if query_maj23 {
+                    messages.extend(cs.reactor.maj23_queries(&peer.id, &core));
+                }
+                messages
+            };
+            if !messages.is_empty() {
+                debug!("Gossiping {} messages to {}", messages.len(), peer.id);
+            }
+            for msg in &messages {
+                cs.send_to_peer(&peer, msg);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::consensus::validator::{Validator, ValidatorSet};
+    use crate::consensus::vote::Vote;
+    use crate::p2p::key::NodeKey;
+
+    fn msg_types(msgs: &[P2PMessage]) -> Vec<&'static str> {
+        msgs.iter().map(P2PMessage::msg_type).collect()
+    }
+
+    #[test]
+    fn sends_missing_data_once_per_round() {
+        let mut core = ConsensusCore::new("node-a".into(), "127.0.0.1:0".into());
+        // A second validator keeps the round from committing on our vote alone.
+        core.set_validators(ValidatorSet::new(vec![
+            Validator::new("node-a".into(), core.public_key(), 10),
+            Validator::new("node-b".into(), NodeKey::generate().public_key(), 10),
+        ]));
+        core.start_new_round("block-1".into());
+        let reactor = ConsensusReactor::new();
+
+        // Nothing is sent to a peer we know nothing about.
+        assert!(reactor.next_messages("peer-b", &core).is_empty());
+
+        reactor.apply_new_round_step("peer-b", 1, 1, Step::Propose);
+        let msgs = reactor.next_messages("peer-b", &core);
+        assert_eq!(msg_types(&msgs), ["Proposal", "BlockPart", "Vote"]);
+        assert!(reactor.next_messages("peer-b", &core).is_empty());
+
+        // A peer that already announced our prevote only gets the proposal data.
+        reactor.apply_new_round_step("peer-c", 1, 1, Step::Propose);
+        reactor.apply_has_vote("peer-c", 1, 1, VoteType::Prevote, 0, 1);
+        let msgs = reactor.next_messages("peer-c", &core);
+        assert_eq!(msg_types(&msgs), ["Proposal", "BlockPart"]);
+
+        // Moving to another round resets what we think the peer has.
+        // Round 2 is node-b's to propose, so we only propose again in round 3.
+        core.start_new_round("block-2".into());
+        core.start_new_round("block-3".into());
+        reactor.apply_new_round_step("peer-b", 1, 3, Step::Propose);
+        assert_eq!(reactor.next_messages("peer-b", &core).len(), 3);
+    }
+
+    #[test]
+    fn maj23_queries_expire_with_the_round() {
+        let mut core = ConsensusCore::new("node-a".into(), "127.0.0.1:0".into());
+        let key_b = NodeKey::generate();
+        core.set_validators(ValidatorSet::new(vec![
+            Validator::new("node-a".into(), core.public_key(), 10),
+            Validator::new("node-b".into(), key_b.public_key(), 10),
+        ]));
+        let reactor = ConsensusReactor::new();
+
+        let mut queried = 0;
+        for _ in 0..20 {
+            core.start_new_round("block".into());
+            let rs = &core.round_state;
+            let (height, round) = (rs.height, rs.round);
+            // node-b's prevote for our block makes a +2/3 majority.
+            if let Some(ours) = rs.prevotes.get("node-a") {
+                let mut vote = Vote::new(VoteType::Prevote, height, round, ours.block_hash.clone(), "node-b".into());
+                vote.sign(&key_b);
+                core.on_vote(vote).unwrap();
+            }
+            reactor.apply_new_round_step("peer-b", height, round, Step::Prevote);
+            queried += reactor.maj23_queries("peer-b", &core).len();
+            // The peer never answers, yet only this round's queries are kept.
+            assert!(reactor.queries.lock().unwrap().iter().all(|q| q.round == round));
+            assert!(reactor.queries.lock().unwrap().len() <= 2);
+        }
+        assert!(queried >= 10);
+    }
+}
@@ -1,12 +1,69 @@
-/// `ConsensusCore` implements the low-level logic for each consensus round.
-/// It stores the current round state, a validator set, and methods to respond
-/// to inbound messages (proposal, prevote, precommit, commit).
+//! `ConsensusCore` implements the low-level logic for each consensus round.
+//! It stores the current round state, a validator set, and methods to respond
+//! to inbound messages (proposal, block part, prevote, precommit, commit).
+//!
+//! A vote never replaces a different vote from the same validator in the same
+//! round: the conflicting pair is kept as `DuplicateVoteEvidence` instead, and
+//! handed to the evidence pool, from where it is gossiped and included in the
+//! next block we propose.
+//!
+//! Once +2/3 of the validators prevote for the proposal we precommit it, and
+//! once +2/3 precommit it the block is committed: it goes into the
+//! `BlockStore` together with those precommits, and we move to the next
+//! height. If the block can't be stored, consensus halts instead (see
+//! [`ConsensusCore::halted`]). A peer's commit that conflicts with a block in our store is checked
+//! for a light-client attack.
+//!
+//! The proposer fills its block with transactions from the mempool. Committed
+//! blocks are passed to the application, which answers with the result of
+//! each transaction (kept in the block store) and may answer with
+//! validator updates. Updates returned for height `H` apply from height
+//! `H + 2`, so the validators of the next height are always known before it
+//! starts. The set for every height is kept in the block store.
+//!
+//! Everything notable (new rounds, votes, timeouts, blocks, transactions and
+//! validator set changes) is published on the event bus. Committed
+//! transactions and block events are also indexed for search. Progress,
+//! commits and the mempool are reported in [`Metrics`].
+//!
+//! Each height is traced as a `height` span, and each of its rounds as a
+//! `round` span inside it; every handler runs in the current round's span,
+//! so its logs carry the height and round they belong to.
+//!
+//! Handlers never send anything themselves: messages the node wants to
+//! broadcast (its proposal, block parts, votes and new transactions) are queued in an outbox
+//! that `ConsensusState` drains after each call.
 
-use anyhow::Result;
-use tracing::{debug, info};
+use std::collections::HashSet;
+use std::time::Instant;
 
-use super::types::{RoundState, Step, ConsensusParams};
-use super::validator::ValidatorSet;
+use anyhow::{bail, Result};
+use ed25519_dalek::VerifyingKey;
+use tracing::{debug, error, info, info_span, warn, Span};
+
+use crate::app::{Application, BaseApplication, CheckTxResponse, QueryResponse};
+use crate::config::MempoolConfig;
+use crate::events::{EventBus, EventData};
+use crate::indexer::{Indexer, TxRecord};
+use crate::genesis::Genesis;
+use crate::mempool::{Mempool, MempoolError};
+use crate::metrics::Metrics;
+use crate::p2p::key::NodeKey;
+use crate::p2p::message::P2PMessage;
+use crate::p2p::score::Misbehavior;
+
+use super::bits::BitArray;
+use super::block::{Block, Part, PartSet, PartSetHeader, Tx, BLOCK_PART_SIZE};
+use super::commit::Commit;
+use super::evidence::{DuplicateVoteEvidence, Evidence, LightClientAttackEvidence};
+use super::evidence_pool::EvidencePool;
+use super::store::BlockStore;
+use super::types::{RoundState, Step, ConsensusParams, VoteType};
+use super::validator::{ProposerSchedule, Validator, ValidatorSet, ValidatorUpdate};
+use super::vote::Vote;
+
+/// Proposals for rounds further ahead of ours than this are dropped.
+pub const MAX_ROUNDS_AHEAD: u64 = 8;
 
 /// Core structure holding the local node's consensus-related data.
 #[derive(Debug)]
@@ -16,105 +73,1119 @@ pub struct ConsensusCore {
     /// The address on which this node listens for inbound connections.
     pub listen_addr: String,
 
-    /// The set of validators participating in consensus (simplified here).
+    /// The validators of the current height.
     pub validators: ValidatorSet,
 
+    /// The proposers of the current height's rounds, elected once each.
+    proposers: ProposerSchedule,
+
     /// The current round state (round number, step, locked block, etc.).
     pub round_state: RoundState,
 
     /// Configuration parameters, e.g., the threshold for quorum.
     pub params: ConsensusParams,
+
+    /// Verified evidence of misbehavior, waiting to be included in a block.
+    pub evidence_pool: EvidencePool,
+
+    /// Committed blocks and their commits, and the validator set history.
+    pub block_store: BlockStore,
+
+    /// Transactions waiting to be proposed.
+    pub mempool: Mempool,
+
+    /// Where events are published for subscribers.
+    pub event_bus: EventBus,
+
+    /// Committed transactions and block events, searchable by event attributes.
+    pub indexer: Indexer,
+
+    /// Where consensus and mempool metrics are reported.
+    pub metrics: Metrics,
+
+    /// The step we are in and when we entered it, for step durations.
+    step_started: Option<(Step, Instant)>,
+
+    /// When we last committed a block, for block intervals.
+    last_commit_at: Option<Instant>,
+
+    /// Span of the current height.
+    height_span: Span,
+
+    /// Span of the current round, inside `height_span` (the height span
+    /// itself until the height's first round starts).
+    round_span: Span,
+
+    /// The application committed blocks are executed by.
+    app: Box<dyn Application>,
+
+    /// The key our own votes are signed with.
+    signing_key: NodeKey,
+
+    /// Messages waiting to be broadcast.
+    outbox: Vec<P2PMessage>,
+
+    /// Why consensus stopped, if it did.
+    halted: Option<String>,
 }
 
 impl ConsensusCore {
     /// Constructs a new `ConsensusCore` object with a simple validator set
     /// containing just our local node (for demonstration).
     pub fn new(node_id: String, listen_addr: String) -> Self {
-        let validators = ValidatorSet::new_simple(vec![node_id.clone()]);
-        let round_state = RoundState::default();
+        let signing_key = NodeKey::generate();
+        let validators = ValidatorSet::new(vec![Validator::new(
+            node_id.clone(),
+            signing_key.public_key(),
+            10,
+        )]);
+        let round_state = RoundState::new();
         let params = ConsensusParams::default();
 
-        Self {
+        let mut core = Self {
             node_id,
             listen_addr,
-            validators,
+            proposers: ProposerSchedule::new(&validators),
+            validators: validators.clone(),
             round_state,
             params,
+            evidence_pool: EvidencePool::new(),
+            block_store: BlockStore::new(),
+            mempool: Mempool::new(MempoolConfig::default()),
+            event_bus: EventBus::new(),
+            indexer: Indexer::new(),
+            metrics: Metrics::default(),
+            step_started: None,
+            last_commit_at: None,
+            height_span: Span::none(),
+            round_span: Span::none(),
+            app: Box::new(BaseApplication),
+            signing_key,
+            outbox: Vec::new(),
+            halted: None,
+        };
+        core.set_validators(validators);
+        core.enter_height();
+        core
+    }
+
+    /// Returns the public key our votes are signed with.
+    pub fn public_key(&self) -> VerifyingKey {
+        self.signing_key.public_key()
+    }
+
+    /// Replaces the key our votes are signed with.
+    pub fn set_signing_key(&mut self, key: NodeKey) {
+        self.signing_key = key;
+    }
+
+    /// Replaces the application committed blocks are executed by.
+    pub fn set_application(&mut self, app: Box<dyn Application>) {
+        self.app = app;
+    }
+
+    /// Makes `validators` the validator set from the current height on,
+    /// overriding any recorded history for the current and the next height.
+    pub fn set_validators(&mut self, validators: ValidatorSet) {
+        let height = self.round_state.height;
+        let mut next = validators.clone();
+        next.increment_proposer_priority(1);
+        self.save_validators(height, validators.clone());
+        self.save_validators(height + 1, next);
+        self.validators = validators;
+    }
+
+    /// Replaces the block store and resumes at the height after its latest
+    /// block, with the validators recorded for that height. A store without
+    /// validator history keeps the current validators.
+    pub fn set_block_store(&mut self, block_store: BlockStore) {
+        self.round_state = RoundState::new();
+        self.round_state.height = block_store.height() + 1;
+        self.enter_height();
+        self.block_store = block_store;
+        match self.block_store.validators_at(self.round_state.height) {
+            Some(validators) => self.validators = validators.clone(),
+            None => self.set_validators(self.validators.clone()),
         }
     }
 
-    /// Triggers a new consensus round, typically by the local node acting
-    /// as the proposer. Sets the step to `Propose`, updates the round number,
-    /// and registers the proposed block.
+    /// Starts the chain described by `genesis`: adopts its consensus
+    /// parameters and, if no block has been committed yet, hands the genesis
+    /// to the application and installs the initial validators (the
+    /// application's, if it returned any, otherwise the genesis ones).
+    ///
+    /// A node resuming from its block store keeps the validators recorded
+    /// there, and the application is not initialized again.
+    pub fn init_chain(&mut self, genesis: &Genesis) -> Result<()> {
+        self.params = genesis.consensus_params.clone();
+        if self.block_store.height() > 0 {
+            return Ok(());
+        }
+
+        let response = self.app.init_chain(genesis);
+        let validators = if response.validators.is_empty() {
+            genesis.validator_set()
+        } else {
+            ValidatorSet::new(
+                response
+                    .validators
+                    .into_iter()
+                    .filter(|u| u.power > 0)
+                    .map(|u| Validator::new(u.id, u.pub_key, u.power))
+                    .collect(),
+            )
+        };
+        if validators.is_empty() {
+            bail!("init_chain left the validator set empty");
+        }
+        info!(
+            "Initialized chain {} with {} validators",
+            genesis.chain_id,
+            validators.len()
+        );
+        self.set_validators(validators);
+        Ok(())
+    }
+
+    /// Checks a transaction submitted to this node and adds it to the
+    /// mempool if the application accepts it. Accepted transactions are
+    /// queued for broadcast.
+    pub fn check_tx(&mut self, tx: Tx) -> Result<CheckTxResponse, MempoolError> {
+        let _span = self.round_span.clone().entered();
+        let result = self.mempool.check_tx(tx.clone(), self.app.as_mut());
+        match &result {
+            Ok(response) if response.is_ok() => self.outbox.push(P2PMessage::Tx { tx }),
+            Err(MempoolError::AlreadySeen) => {}
+            _ => self.metrics.mempool.failed_txs.inc(),
+        }
+        self.record_mempool_size();
+        result
+    }
+
+    /// Queries the application state.
+    pub fn query(&mut self, path: &str, data: &[u8]) -> QueryResponse {
+        let mut response = self.app.query(path, data);
+        if response.height == 0 {
+            response.height = self.block_store.height();
+        }
+        response
+    }
+
+    /// Syncs the block store and the indexes to disk, e.g. before shutting down.
+    pub fn flush_storage(&mut self) -> Result<()> {
+        self.block_store.flush()?;
+        self.indexer.flush()
+    }
+
+    /// Returns why consensus halted, if it did. A halted core neither starts
+    /// rounds nor commits blocks; the node is expected to shut down.
+    pub fn halted(&self) -> Option<&str> {
+        self.halted.as_deref()
+    }
+
+    /// Removes and returns all messages queued for broadcast.
+    pub fn take_outbox(&mut self) -> Vec<P2PMessage> {
+        std::mem::take(&mut self.outbox)
+    }
+
+    /// Triggers a new consensus round. Sets the step to `Propose` and updates
+    /// the round number. If we are the round's proposer, also registers the
+    /// proposed block; otherwise we wait for the proposer's.
+    ///
+    /// The block carries `data`, pending evidence from the evidence pool and
+    /// as many mempool transactions as fit in `max_block_bytes`.
+    /// It is split into parts; the `Proposal` and every `BlockPart` are queued
+    /// for broadcast. Since we already hold the whole block, we move straight
+    /// on to prevoting for it.
     ///
     /// # Arguments
     ///
-    /// * `block` - A string representing the newly proposed block.
-    pub fn start_new_round(&mut self, block: String) {
+    /// * `data` - A string representing the payload of the newly proposed block.
+    pub fn start_new_round(&mut self, data: String) {
+        if self.halted.is_some() {
+            return;
+        }
+        if self.round_state.round > 0 {
+            // The previous round of this height ended without a commit.
+            self.event_bus.publish(EventData::Timeout {
+                height: self.round_state.height,
+                round: self.round_state.round,
+                step: self.round_state.step.clone(),
+            });
+        }
         let new_round = self.round_state.round + 1;
+        self.round_span = info_span!(parent: &self.height_span, "round", round = new_round);
+        let _span = self.round_span.clone().entered();
         self.round_state.round = new_round;
         self.round_state.step = Step::Propose;
-        self.round_state.proposal = Some(block);
         self.round_state.locked_block_hash = None;
+        self.announce_step();
         self.round_state.prevotes.clear();
         self.round_state.precommits.clear();
+        self.round_state.proposer_id = None;
+        self.round_state.proposal = None;
+        self.round_state.proposal_block_hash = None;
+        self.round_state.proposal_parts = None;
+
+        let proposer = self.proposer_for_round(new_round);
+        self.event_bus.publish(EventData::NewRound {
+            height: self.round_state.height,
+            round: new_round,
+            proposer: proposer.clone(),
+        });
+        if proposer.as_deref() != Some(self.node_id.as_str()) {
+            info!("Starting new round: {} (waiting for proposal)", new_round);
+            return;
+        }
+
+        let evidence = self.evidence_pool.pending(self.params.max_evidence_per_block);
+        let mut block = Block::new(self.round_state.height, data, Vec::new(), evidence);
+        let room = self.params.max_block_bytes.saturating_sub(block.encode().len());
+        block.txs = self.mempool.reap(room);
+        let parts = PartSet::from_data(&block.encode());
+        let hash = block.hash();
+        self.outbox.push(P2PMessage::Proposal {
+            proposer_id: self.node_id.clone(),
+            height: self.round_state.height,
+            round: new_round,
+            block_hash: hash.clone(),
+            parts_header: parts.header().clone(),
+        });
+        for index in 0..parts.header().total {
+            if let Some(part) = parts.part(index) {
+                self.outbox.push(P2PMessage::BlockPart {
+                    height: self.round_state.height,
+                    round: new_round,
+                    part: part.clone(),
+                });
+            }
+        }
+
+        self.round_state.proposer_id = Some(self.node_id.clone());
+        self.round_state.proposal = Some(block);
+        self.round_state.proposal_block_hash = Some(hash);
+        self.round_state.proposal_parts = Some(parts);
 
         info!("Starting new round: {}", new_round);
         info!("Proposed block: {:?}", self.round_state.proposal);
+
+        self.enter_prevote();
     }
 
     // ----- Event Handlers -----
 
     /// Called when we receive a `Proposal` message from some node.
     ///
+    /// Only the block's hash and part-set header arrive here; the block
+    /// itself is assembled from subsequent `BlockPart` messages.
+    ///
+    /// Proposals for other heights or earlier rounds, or for rounds more than
+    /// [`MAX_ROUNDS_AHEAD`] ahead of ours, are ignored: they may just have
+    /// been relayed late, and are not checked against this height's proposer.
+    ///
+    /// Fails with [`Misbehavior::InvalidBlock`] if the proposer is not the
+    /// round's proposer or the header announces an impossible number of parts.
+    ///
     /// # Arguments
     ///
     /// * `proposer_id` - ID of the node that proposed the block.
+    /// * `height` - The height the block is proposed for.
     /// * `round` - The round number of the proposal.
-    /// * `block` - The proposed block contents.
-    pub fn on_proposal(&mut self, proposer_id: String, round: u64, block: String) -> Result<()> {
-        debug!("on_proposal: from={} round={} block={}", proposer_id, round, block);
+    /// * `block_hash` - Hash of the proposed block.
+    /// * `parts_header` - Part count and Merkle root of the block's parts.
+    pub fn on_proposal(
+        &mut self,
+        proposer_id: String,
+        height: u64,
+        round: u64,
+        block_hash: String,
+        parts_header: PartSetHeader,
+    ) -> Result<()> {
+        let _span = self.round_span.clone().entered();
+        debug!(
+            "on_proposal: from={} height={} round={} block_hash={} parts={}",
+            proposer_id, height, round, block_hash, parts_header.total
+        );
 
-        // If it's an older round, ignore.
-        if round < self.round_state.round {
+        // Ignore other heights and stale rounds, and rounds too far ahead to elect their proposer.
+        if height != self.round_state.height
+            || round < self.round_state.round || round > self.round_state.round.saturating_add(MAX_ROUNDS_AHEAD) {
             return Ok(());
         }
 
+        let max_parts = self.params.max_block_bytes.div_ceil(BLOCK_PART_SIZE) as u32;
+        if self.proposer_for_round(round).as_deref() != Some(proposer_id.as_str())
+            || parts_header.total == 0
+            || parts_header.total > max_parts
+        {
+            return Err(Misbehavior::InvalidBlock.into());
+        }
+
         // If we're not in the Propose step, we might be out of sync; just ignore in this demo.
         if self.round_state.step != Step::Propose {
             return Ok(());
         }
 
-        // Accept the proposal (in real logic, you'd validate the block, etc.)
-        self.round_state.proposal = Some(block);
+        // Accept the proposal and wait for its parts
+        self.round_state.proposer_id = Some(proposer_id);
+        self.round_state.proposal = None;
+        self.round_state.proposal_block_hash = Some(block_hash);
+        self.round_state.proposal_parts = Some(PartSet::from_header(parts_header));
         Ok(())
     }
 
-    /// Called when we receive a `Prevote` message.
-    pub fn on_prevote(&mut self, voter_id: String, round: u64, block_hash: String) -> Result<()> {
-        debug!("on_prevote: from={} round={} block_hash={}", voter_id, round, block_hash);
+    /// Called when we receive a `BlockPart` message.
+    ///
+    /// Parts for another height, or for a round without a pending proposal,
+    /// are ignored (they may simply be late). Once the last part arrives, the block is assembled and
+    /// checked against the proposal's block hash, and we prevote for it.
+    ///
+    /// Fails with [`Misbehavior::InvalidBlock`] if the part's Merkle proof
+    /// doesn't verify, the assembled block doesn't match the proposal, or the
+    /// block carries evidence that doesn't pass the evidence pool's checks.
+    pub fn on_block_part(&mut self, height: u64, round: u64, part: Part) -> Result<()> {
+        let _span = self.round_span.clone().entered();
+        debug!("on_block_part: height={} round={} index={}", height, round, part.index);
+
+        if height != self.round_state.height
+            || round != self.round_state.round
+            || self.round_state.proposal.is_some()
+        {
+            return Ok(());
+        }
+        let Some(parts) = self.round_state.proposal_parts.as_mut() else {
+            return Ok(());
+        };
+
+        if parts.add_part(part).is_err() {
+            return Err(Misbehavior::InvalidBlock.into());
+        }
+        let Some(data) = parts.assemble() else {
+            return Ok(());
+        };
 
-        // Store the prevote in the round state. In real Tendermint,
-        // you would check if it matches your proposed block, etc.
-        self.round_state.prevotes.insert(voter_id, block_hash);
+        let block = Block::decode(&data).ok_or(Misbehavior::InvalidBlock)?;
+        if block.height != self.round_state.height
+            || self.round_state.proposal_block_hash.as_deref() != Some(&block.hash())
+        {
+            return Err(Misbehavior::InvalidBlock.into());
+        }
+        self.validate_block_evidence(&block)?;
 
+        info!("Received complete block for round {}", round);
+        self.round_state.proposal = Some(block);
+        self.enter_prevote();
         Ok(())
     }
 
-    /// Called when we receive a `Precommit` message.
-    pub fn on_precommit(&mut self, voter_id: String, round: u64, block_hash: String) -> Result<()> {
-        debug!("on_precommit: from={} round={} block_hash={}", voter_id, round, block_hash);
+    /// Called when we receive a `Vote` message (prevote or precommit).
+    ///
+    /// The first vote from each validator per round and type is kept. A later
+    /// vote for the same block is ignored; one for a different block is
+    /// equivocation, and both votes are kept as [`DuplicateVoteEvidence`].
+    ///
+    /// Votes for other heights and rounds are ignored before anything else is
+    /// checked: they may be valid for another validator set.
+    ///
+    /// Fails with [`Misbehavior::InvalidVote`] if the voter is not a validator,
+    /// and with [`Misbehavior::BadSignature`] if the vote isn't signed by it.
+    pub fn on_vote(&mut self, vote: Vote) -> Result<()> {
+        let _span = self.round_span.clone().entered();
+        debug!(
+            "on_vote: from={} type={:?} height={} round={} block_hash={}",
+            vote.validator_id, vote.vote_type, vote.height, vote.round, vote.block_hash
+        );
+
+        // Votes for other heights and rounds are not tracked in this demo.
+        if vote.height != self.round_state.height || vote.round != self.round_state.round {
+            return Ok(());
+        }
+
+        let Some(pub_key) = self.validators.pub_key(&vote.validator_id) else {
+            return Err(Misbehavior::InvalidVote.into());
+        };
+        if !vote.verify(pub_key) {
+            return Err(Misbehavior::BadSignature.into());
+        }
+
+        let vote_type = vote.vote_type;
+        let votes = match vote_type {
+            VoteType::Prevote => &mut self.round_state.prevotes,
+            VoteType::Precommit => &mut self.round_state.precommits,
+        };
+        if let Some(existing) = votes.get(&vote.validator_id) {
+            if let Some(evidence) = DuplicateVoteEvidence::new(existing.clone(), vote) {
+                warn!("Conflicting votes: {}", evidence);
+                self.report_evidence(Evidence::DuplicateVote(evidence));
+            }
+            return Ok(());
+        }
 
-        self.round_state.precommits.insert(voter_id, block_hash);
+        let voter_id = vote.validator_id.clone();
+        votes.insert(voter_id.clone(), vote.clone());
+        self.event_bus.publish(EventData::Vote { vote });
+        self.announce_has_vote(vote_type, &voter_id);
+        self.check_majorities();
         Ok(())
     }
 
     /// Called when we receive a `Commit` message, signifying the network
-    /// has committed a block at a given round.
-    pub fn on_commit(&mut self, block_hash: String, round: u64) -> Result<()> {
-        info!("on_commit: block_hash={} round={}", block_hash, round);
-        // In real code, you'd finalize the block, store it, etc.
+    /// has committed a block.
+    ///
+    /// Commits for heights we haven't reached are ignored. A commit for a
+    /// block other than the one we committed at that height is checked for a
+    /// light-client attack; if it is one, the evidence goes to the pool.
+    ///
+    /// Fails with the misbehavior matching the verification error if the
+    /// conflicting commit is forged or otherwise invalid.
+    pub fn on_commit(&mut self, commit: Commit) -> Result<()> {
+        let _span = self.round_span.clone().entered();
+        info!("on_commit: height={} block_hash={}", commit.height, commit.block_hash);
+
+        let Some(ours) = self.block_store.load_commit(commit.height) else {
+            return Ok(());
+        };
+        if ours.block_hash == commit.block_hash {
+            return Ok(());
+        }
+
+        // The validators a light client would trust: those of the common height.
+        let Some(trusted) = self.block_store.validators_at(commit.height.saturating_sub(1).max(1)) else {
+            return Ok(());
+        };
+        match LightClientAttackEvidence::from_divergent_commit(commit, &self.block_store, trusted) {
+            Ok(evidence) => {
+                warn!("Light client attack: {}", evidence);
+                self.report_evidence(Evidence::LightClientAttack(evidence));
+            }
+            Err(e) => {
+                if let Some(m) = e.misbehavior() {
+                    return Err(m.into());
+                }
+                debug!("Ignoring conflicting commit: {}", e);
+            }
+        }
+        Ok(())
+    }
+
+    /// Called when a peer gossips a transaction to us.
+    ///
+    /// Transactions that are new to us and that the application accepts are
+    /// added to the mempool and relayed. Anything else is dropped.
+    pub fn on_tx(&mut self, tx: Tx) {
+        let _span = self.round_span.clone().entered();
+        match self.check_tx(tx) {
+            Ok(response) if !response.is_ok() => debug!("Ignoring rejected tx: {}", response.log),
+            Ok(_) | Err(MempoolError::AlreadySeen) => {}
+            Err(e) => debug!("Ignoring tx: {}", e),
+        }
+    }
+
+    /// Called when a peer gossips evidence to us.
+    ///
+    /// Evidence that is new to us is added to the pool and relayed. Evidence
+    /// that is merely stale is ignored; anything else that fails verification
+    /// is reported as the corresponding misbehavior.
+    pub fn on_evidence(&mut self, evidence: Evidence) -> Result<()> {
+        let _span = self.round_span.clone().entered();
+        debug!("on_evidence: {}", evidence);
+        let height = self.round_state.height;
+        match self
+            .evidence_pool
+            .add(evidence.clone(), height, &self.block_store, &self.params)
+        {
+            Ok(true) => self.outbox.push(P2PMessage::Evidence { evidence }),
+            Ok(false) => {}
+            Err(e) => {
+                if let Some(m) = e.misbehavior() {
+                    return Err(m.into());
+                }
+                debug!("Ignoring evidence: {}", e);
+            }
+        }
+        Ok(())
+    }
+
+    // ----- Evidence -----
+
+    /// Adds evidence we detected ourselves to the pool and queues it for gossip.
+    fn report_evidence(&mut self, evidence: Evidence) {
+        let height = self.round_state.height;
+        match self
+            .evidence_pool
+            .add(evidence.clone(), height, &self.block_store, &self.params)
+        {
+            Ok(true) => self.outbox.push(P2PMessage::Evidence { evidence }),
+            Ok(false) => {}
+            Err(e) => warn!("Dropping locally detected evidence: {}", e),
+        }
+    }
+
+    /// Checks the evidence included in a proposed block: no more than the
+    /// per-block maximum, no duplicates, and each item valid and uncommitted.
+    fn validate_block_evidence(&self, block: &Block) -> Result<()> {
+        if block.evidence.len() > self.params.max_evidence_per_block {
+            return Err(Misbehavior::InvalidBlock.into());
+        }
+        let mut seen = std::collections::HashSet::new();
+        for evidence in &block.evidence {
+            if !seen.insert(evidence.hash()) {
+                return Err(Misbehavior::InvalidBlock.into());
+            }
+            if let Err(e) = self
+                .evidence_pool
+                .check(evidence, block.height, &self.block_store, &self.params)
+            {
+                debug!("Block carries bad evidence: {}", e);
+                return Err(Misbehavior::InvalidBlock.into());
+            }
+        }
         Ok(())
     }
+
+    // ----- Vote queries -----
+
+    /// Returns the proposer of `round` at the current height.
+    fn proposer_for_round(&mut self, round: u64) -> Option<String> {
+        self.proposers.proposer(&self.validators, round)
+    }
+
+    /// Returns the votes of the given type cast in the current round.
+    fn votes(&self, vote_type: VoteType) -> &std::collections::HashMap<String, Vote> {
+        match vote_type {
+            VoteType::Prevote => &self.round_state.prevotes,
+            VoteType::Precommit => &self.round_state.precommits,
+        }
+    }
+
+    /// Returns the block hash that validators with more than two thirds of
+    /// the voting power voted for in the current round, if any.
+    pub fn two_thirds_majority(&self, vote_type: VoteType) -> Option<String> {
+        let mut tally: std::collections::HashMap<&str, u64> = std::collections::HashMap::new();
+        for (voter_id, vote) in self.votes(vote_type) {
+            *tally.entry(vote.block_hash.as_str()).or_insert(0) += self.validators.power_of(voter_id);
+        }
+        let total = self.validators.total_power();
+        tally
+            .into_iter()
+            .find(|(_, power)| power * 3 > total * 2)
+            .map(|(hash, _)| hash.to_string())
+    }
+
+    /// Returns which validators' votes of `vote_type` for `block_hash` we hold
+    /// in the current round, indexed by validator position.
+    pub fn vote_bits(&self, vote_type: VoteType, block_hash: &str) -> BitArray {
+        let mut bits = BitArray::new(self.validators.len());
+        for (voter_id, vote) in self.votes(vote_type) {
+            if vote.block_hash == block_hash {
+                if let Some(index) = self.validators.index_of(voter_id) {
+                    bits.set(index);
+                }
+            }
+        }
+        bits
+    }
+
+    // ----- Step transitions -----
+
+    /// Moves to the `Prevote` step and, if we're a validator, prevotes for the
+    /// complete proposal of the current round.
+    fn enter_prevote(&mut self) {
+        self.round_state.step = Step::Prevote;
+        self.announce_step();
+
+        let Some(hash) = self.round_state.proposal_block_hash.clone() else {
+            return;
+        };
+        self.cast_vote(VoteType::Prevote, hash);
+        self.check_majorities();
+    }
+
+    /// Moves to the `Precommit` step, locks on `hash` and, if we're a
+    /// validator, precommits for it.
+    fn enter_precommit(&mut self, hash: String) {
+        self.round_state.step = Step::Precommit;
+        self.round_state.locked_block_hash = Some(hash.clone());
+        self.announce_step();
+        self.cast_vote(VoteType::Precommit, hash);
+    }
+
+    /// Moves on once the current round has +2/3 votes for our complete
+    /// proposal: prevotes take us to precommit, precommits commit the block.
+    fn check_majorities(&mut self) {
+        if self.halted.is_some() {
+            return;
+        }
+        let Some(hash) = self.round_state.proposal_block_hash.clone() else {
+            return;
+        };
+        if self.round_state.proposal.is_none() {
+            return;
+        }
+        if self.round_state.step == Step::Prevote
+            && self.two_thirds_majority(VoteType::Prevote).as_deref() == Some(&hash)
+        {
+            self.enter_precommit(hash.clone());
+        }
+        if self.round_state.step == Step::Precommit
+            && self.two_thirds_majority(VoteType::Precommit).as_deref() == Some(&hash)
+        {
+            self.commit_block(hash);
+        }
+    }
+
+    /// Stores the proposal with its precommits, marks its evidence as
+    /// committed, executes it in the application, updates the mempool,
+    /// announces the commit, indexes and publishes the block and its
+    /// transactions, and moves to the next height.
+    ///
+    /// If the block can't be stored, nothing else happens and consensus
+    /// halts: going on would execute and announce a block we could lose.
+    fn commit_block(&mut self, hash: String) {
+        let Some(block) = self.round_state.proposal.take() else {
+            return;
+        };
+        let mut signatures: Vec<Vote> = self
+            .round_state
+            .precommits
+            .values()
+            .filter(|v| v.block_hash == hash)
+            .cloned()
+            .collect();
+        signatures.sort_by_key(|v| self.validators.index_of(&v.validator_id));
+        let commit = Commit {
+            height: block.height,
+            round: self.round_state.round,
+            block_hash: hash,
+            signatures,
+        };
+
+        if let Err(e) = self.block_store.save_block(block.clone(), commit.clone()) {
+            error!("Failed to store block at height {}, halting: {:?}", commit.height, e);
+            self.halted = Some(format!("failed to store block at height {}: {}", commit.height, e));
+            return;
+        }
+        info!("Committed block {} at height {}", commit.block_hash, commit.height);
+        self.evidence_pool
+            .mark_committed(&block.evidence, block.height, &self.params);
+        let response = self.app.finalize_block(&block);
+        let dropped = self.mempool.update(&block.txs, self.app.as_mut());
+        if dropped > 0 {
+            debug!("Recheck dropped {} txs from the mempool", dropped);
+        }
+        // Every transaction left, and every one dropped, was checked again.
+        self.metrics
+            .mempool
+            .recheck_times
+            .inc_by((self.mempool.len() + dropped) as u64);
+        self.record_mempool_size();
+        self.record_block(&block, &commit);
+        if let Err(e) = self.block_store.save_tx_results(commit.height, response.tx_results.clone()) {
+            warn!("Failed to store tx results at height {}: {:?}", commit.height, e);
+        }
+        for (index, (tx, result)) in block.txs.iter().zip(response.tx_results).enumerate() {
+            let record = TxRecord {
+                height: block.height,
+                index,
+                tx: tx.clone(),
+                result,
+            };
+            if let Err(e) = self.indexer.txs.index(&record) {
+                warn!("Failed to index tx {} at height {}: {:?}", index, block.height, e);
+            }
+            self.event_bus.publish(EventData::Tx {
+                height: record.height,
+                index,
+                tx: record.tx,
+                result: record.result,
+            });
+        }
+        if let Err(e) = self.indexer.blocks.index(block.height, &response.events) {
+            warn!("Failed to index block events at height {}: {:?}", block.height, e);
+        }
+        self.event_bus.publish(EventData::NewBlock {
+            block,
+            events: response.events,
+        });
+        self.update_validators(commit.height, &response.validator_updates);
+        if let Err(e) = self.block_store.flush() {
+            warn!("Failed to sync the block store at height {}: {:?}", commit.height, e);
+        }
+        self.outbox.push(P2PMessage::Commit { commit });
+
+        self.round_state = RoundState::new();
+        self.round_state.height = self.block_store.height() + 1;
+        self.enter_height();
+        if let Some(validators) = self.block_store.validators_at(self.round_state.height) {
+            self.validators = validators.clone();
+        }
+        self.announce_step();
+    }
+
+    /// Records the validator set for `height + 2`: the set of `height + 1`
+    /// with the application's `updates` applied, advanced by one proposer
+    /// election. Invalid updates are rejected as a whole.
+    fn update_validators(&mut self, height: u64, updates: &[ValidatorUpdate]) {
+        let Some(current) = self.block_store.validators_at(height + 1) else {
+            return;
+        };
+        let mut next = current.clone();
+        if let Err(e) = next.apply_updates(updates) {
+            error!("Rejected validator updates from the application at height {}: {}", height, e);
+        } else if !updates.is_empty() {
+            info!("Validator updates at height {} take effect at height {}", height, height + 2);
+            self.event_bus.publish(EventData::ValidatorSetUpdates {
+                height: height + 2,
+                updates: updates.to_vec(),
+            });
+        }
+        next.increment_proposer_priority(1);
+        self.save_validators(height + 2, next);
+    }
+
+    // ----- Tracing -----
+
+    /// Opens the span of the height in `round_state`, closing the previous
+    /// height's and round's spans.
+    fn enter_height(&mut self) {
+        self.height_span = info_span!(parent: None, "height", height = self.round_state.height);
+        self.round_span = self.height_span.clone();
+    }
+
+    // ----- Metrics -----
+
+    /// Reports the mempool's current size.
+    fn record_mempool_size(&self) {
+        self.metrics.mempool.size.set(self.mempool.len() as i64);
+        self.metrics.mempool.size_bytes.set(self.mempool.size_bytes() as i64);
+    }
+
+    /// Reports a block we are committing with `commit`: its size, the time
+    /// since the previous commit, and the validators that didn't sign it or
+    /// that its evidence accuses.
+    fn record_block(&mut self, block: &Block, commit: &Commit) {
+        let consensus = &self.metrics.consensus;
+        let now = Instant::now();
+        if let Some(last) = self.last_commit_at.replace(now) {
+            consensus
+                .block_interval_seconds
+                .observe(now.duration_since(last).as_secs_f64());
+        }
+        consensus.num_txs.set(block.txs.len() as i64);
+        consensus.total_txs.inc_by(block.txs.len() as u64);
+        consensus.block_size_bytes.set(block.encode().len() as i64);
+
+        let signers: HashSet<&str> = commit
+            .signatures
+            .iter()
+            .map(|v| v.validator_id.as_str())
+            .collect();
+        let missing: Vec<&Validator> = self
+            .validators
+            .validators
+            .iter()
+            .filter(|v| !signers.contains(v.id.as_str()))
+            .collect();
+        consensus.missing_validators.set(missing.len() as i64);
+        consensus
+            .missing_validators_power
+            .set(missing.iter().map(|v| v.power as i64).sum());
+
+        let byzantine: HashSet<&str> = block
+            .evidence
+            .iter()
+            .flat_map(|e| e.byzantine_validators())
+            .collect();
+        consensus.byzantine_validators.set(byzantine.len() as i64);
+        consensus
+            .byzantine_validators_power
+            .set(byzantine.iter().map(|id| self.validators.power_of(id) as i64).sum());
+    }
+
+    /// Reports the step we just entered, and how long we spent in the previous one.
+    fn record_step(&mut self) {
+        let consensus = &self.metrics.consensus;
+        let now = Instant::now();
+        let step = self.round_state.step.clone();
+        if let Some((previous, since)) = self.step_started.replace((step, now)) {
+            consensus
+                .step_duration_seconds
+                .with_label_values(&[&format!("{:?}", previous)])
+                .observe(now.duration_since(since).as_secs_f64());
+        }
+        consensus.height.set(self.round_state.height as i64);
+        consensus.rounds.set(self.round_state.round as i64);
+        consensus.validators.set(self.validators.len() as i64);
+        consensus.validators_power.set(self.validators.total_power() as i64);
+    }
+
+    /// Records the validator set for `height`, logging storage failures.
+    fn save_validators(&mut self, height: u64, validators: ValidatorSet) {
+        if let Err(e) = self.block_store.save_validators(height, validators) {
+            warn!("Failed to store validator set for height {}: {:?}", height, e);
+        }
+    }
+
+    /// Signs a vote of `vote_type` for `hash`, records it and queues it for
+    /// broadcast. Does nothing if we're not a validator.
+    fn cast_vote(&mut self, vote_type: VoteType, hash: String) {
+        if !self.validators.contains(&self.node_id) {
+            return;
+        }
+        let mut vote = Vote::new(
+            vote_type,
+            self.round_state.height,
+            self.round_state.round,
+            hash,
+            self.node_id.clone(),
+        );
+        vote.sign(&self.signing_key);
+        let votes = match vote_type {
+            VoteType::Prevote => &mut self.round_state.prevotes,
+            VoteType::Precommit => &mut self.round_state.precommits,
+        };
+        votes.insert(self.node_id.clone(), vote.clone());
+        self.event_bus.publish(EventData::Vote { vote: vote.clone() });
+        self.outbox.push(P2PMessage::Vote { vote });
+        let node_id = self.node_id.clone();
+        self.announce_has_vote(vote_type, &node_id);
+    }
+
+    // ----- Gossip announcements -----
+
+    /// Tells peers where we are, so the gossip reactor can send us what we lack.
+    fn announce_step(&mut self) {
+        self.record_step();
+        self.outbox.push(P2PMessage::NewRoundStep {
+            height: self.round_state.height,
+            round: self.round_state.round,
+            step: self.round_state.step.clone(),
+        });
+    }
+
+    /// Tells peers we now hold `voter_id`'s vote, so they stop gossiping it to us.
+    fn announce_has_vote(&mut self, vote_type: VoteType, voter_id: &str) {
+        let Some(index) = self.validators.index_of(voter_id) else {
+            return;
+        };
+        self.outbox.push(P2PMessage::HasVote {
+            height: self.round_state.height,
+            round: self.round_state.round,
+            vote_type,
+            index: index as u32,
+        });
+    }
 }
 
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::app::{FinalizeBlockResponse, InitChainResponse};
+    use crate::genesis::GenesisValidator;
+
+    /// Adds `node-b` to the validator set when executing height 1.
+    struct AddValidatorApp(VerifyingKey);
+
+    impl Application for AddValidatorApp {
+        fn finalize_block(&mut self, block: &Block) -> FinalizeBlockResponse {
+            let mut response = FinalizeBlockResponse::default();
+            if block.height == 1 {
+                response.validator_updates.push(ValidatorUpdate {
+                    id: "node-b".into(),
+                    pub_key: self.0,
+                    power: 30,
+                });
+            }
+            response
+        }
+    }
+
+    #[test]
+    fn every_committed_height_is_synced_to_disk() {
+        let dir = std::env::temp_dir().join(format!("tmlike-store-{}", std::process::id()));
+        let mut core = ConsensusCore::new("node-a".into(), "127.0.0.1:0".into());
+        core.set_block_store(BlockStore::open(dir.clone()).unwrap());
+
+        for height in 1..=3 {
+            core.start_new_round(format!("block-{}", height));
+            assert_eq!(core.block_store.height(), height);
+            assert!(core.block_store.is_synced());
+        }
+        assert_eq!(BlockStore::open(dir.clone()).unwrap().height(), 3);
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn failing_to_store_a_block_halts_consensus() {
+        let dir = std::env::temp_dir().join(format!("tmlike-halt-{}", std::process::id()));
+        let mut core = ConsensusCore::new("node-a".into(), "127.0.0.1:0".into());
+        core.set_block_store(BlockStore::open(dir.clone()).unwrap());
+        std::fs::remove_dir_all(&dir).unwrap();
+
+        core.start_new_round("block-1".into());
+        assert!(core.halted().unwrap().starts_with("failed to store block at height 1"));
+        assert_eq!(core.block_store.height(), 0);
+        assert_eq!(core.round_state.height, 1);
+        assert!(!core.take_outbox().iter().any(|m| matches!(m, P2PMessage::Commit { .. })));
+
+        // No further rounds are started.
+        core.start_new_round("block-2".into());
+        assert_eq!(core.round_state.round, 1);
+        assert!(core.take_outbox().is_empty());
+    }
+
+    #[test]
+    fn validator_updates_apply_two_heights_later() {
+        let mut core = ConsensusCore::new("node-a".into(), "127.0.0.1:0".into());
+        core.set_application(Box::new(AddValidatorApp(NodeKey::generate().public_key())));
+
+        // As the only validator, our own votes commit the block.
+        core.start_new_round("block-1".into());
+        assert_eq!(core.block_store.height(), 1);
+        assert_eq!(core.round_state.height, 2);
+
+        // Height 2 still runs with the old set; height 3 has node-b.
+        assert_eq!(core.validators.len(), 1);
+        assert!(!core.block_store.validators_at(2).unwrap().contains("node-b"));
+        let at_3 = core.block_store.validators_at(3).unwrap();
+        assert_eq!(at_3.total_power(), 40);
+        assert_eq!(at_3.get(0).unwrap().id, "node-b");
+
+        core.start_new_round("block-2".into());
+        assert_eq!(core.round_state.height, 3);
+        assert_eq!(core.validators, *core.block_store.validators_at(3).unwrap());
+
+        // From then on, proposals follow the new powers.
+        let proposers: Vec<String> = (1..=4)
+            .map(|round| core.validators.proposer_for_round(round).unwrap())
+            .collect();
+        assert_eq!(proposers.iter().filter(|p| *p == "node-b").count(), 3);
+    }
+
+    #[test]
+    fn proposal_for_a_far_future_round_is_ignored() {
+        let mut core = ConsensusCore::new("node-a".into(), "127.0.0.1:0".into());
+        let header = PartSet::from_data(b"block").header().clone();
+
+        let height = core.round_state.height;
+
+        let started = Instant::now();
+        core.on_proposal("node-b".into(), height, u64::MAX, "hash".into(), header.clone())
+            .unwrap();
+        assert!(started.elapsed() < std::time::Duration::from_secs(1));
+        assert!(core.round_state.proposal_block_hash.is_none());
+
+        // Within reach, the round's proposer is still checked.
+        let round = core.round_state.round + MAX_ROUNDS_AHEAD;
+        assert!(core.on_proposal("node-b".into(), height, round, "hash".into(), header).is_err());
+    }
+
+    #[test]
+    fn proposal_and_parts_of_another_height_are_dropped_unchecked() {
+        let mut core = ConsensusCore::new("node-a".into(), "127.0.0.1:0".into());
+        let parts = PartSet::from_data(b"block");
+        let height = core.round_state.height;
+
+        // Not this height's proposer, and a part that doesn't match anything.
+        core.on_proposal("node-b".into(), height + 1, 1, "hash".into(), parts.header().clone())
+            .unwrap();
+        core.on_block_part(height - 1, 1, parts.part(0).unwrap().clone()).unwrap();
+        assert!(core.round_state.proposal_block_hash.is_none());
+    }
+
+    #[test]
+    fn votes_of_other_heights_are_ignored_before_checking_the_voter() {
+        let mut core = ConsensusCore::new("node-a".into(), "127.0.0.1:0".into());
+        core.start_new_round("block-1".into());
+        let (height, round) = (core.round_state.height, core.round_state.round);
+
+        // A validator of the previous height's set, which we no longer know.
+        let key = NodeKey::generate();
+        let vote = |height| {
+            let mut vote = Vote::new(VoteType::Prevote, height, round, "hash".into(), "node-x".into());
+            vote.sign(&key);
+            vote
+        };
+        core.on_vote(vote(height - 1)).unwrap();
+        let err = core.on_vote(vote(height)).unwrap_err();
+        assert_eq!(err.downcast_ref::<Misbehavior>(), Some(&Misbehavior::InvalidVote));
+    }
+
+    #[test]
+    fn block_gossiped_in_parts_out_of_order_is_reassembled_and_prevoted() {
+        let mut core = ConsensusCore::new("node-a".into(), "127.0.0.1:0".into());
+        let b_key = NodeKey::generate();
+        core.set_validators(ValidatorSet::new(vec![
+            Validator::new("node-a".into(), core.public_key(), 10),
+            Validator::new("node-b".into(), b_key.public_key(), 30),
+        ]));
+        core.start_new_round(String::new());
+        assert_eq!(core.round_state.step, Step::Propose);
+        core.take_outbox();
+
+        // node-b proposes a block spanning several parts.
+        let block = Block::new(1, "x".repeat(3 * BLOCK_PART_SIZE), Vec::new(), Vec::new());
+        let parts = PartSet::from_data(&block.encode());
+        let total = parts.header().total;
+        assert!(total > 2);
+        core.on_proposal("node-b".into(), 1, 1, block.hash(), parts.header().clone())
+            .unwrap();
+
+        for index in (0..total).rev() {
+            assert!(core.round_state.proposal.is_none());
+            core.on_block_part(1, 1, parts.part(index).unwrap().clone()).unwrap();
+        }
+
+        assert_eq!(core.round_state.proposal.as_ref().map(Block::hash), Some(block.hash()));
+        assert_eq!(core.round_state.step, Step::Prevote);
+        let prevote = core.round_state.prevotes["node-a"].clone();
+        assert_eq!(prevote.block_hash, block.hash());
+        assert!(core
+            .take_outbox()
+            .iter()
+            .any(|m| matches!(m, P2PMessage::Vote { vote } if *vote == prevote)));
+    }
+
+    /// Records the app state it was initialized with and doubles every validator's power.
+    struct DoublingApp(std::sync::Arc<std::sync::Mutex<serde_json::Value>>);
+
+    impl Application for DoublingApp {
+        fn init_chain(&mut self, genesis: &Genesis) -> InitChainResponse {
+            *self.0.lock().unwrap() = genesis.app_state.clone();
+            InitChainResponse {
+                validators: genesis
+                    .validators
+                    .iter()
+                    .map(|v| ValidatorUpdate { id: v.id.clone(), pub_key: v.pub_key, power: v.power * 2 })
+                    .collect(),
+            }
+        }
+
+        fn finalize_block(&mut self, _block: &Block) -> FinalizeBlockResponse {
+            FinalizeBlockResponse::default()
+        }
+    }
+
+    #[test]
+    fn init_chain_installs_the_genesis_validators() {
+        let mut core = ConsensusCore::new("node-a".into(), "127.0.0.1:0".into());
+        let seen = std::sync::Arc::default();
+        core.set_application(Box::new(DoublingApp(std::sync::Arc::clone(&seen))));
+        let mut genesis = Genesis::new(
+            "test-chain".into(),
+            ["node-a", "node-b"]
+                .iter()
+                .map(|id| GenesisValidator { id: id.to_string(), pub_key: NodeKey::generate().public_key(), power: 5 })
+                .collect(),
+        );
+        genesis.app_state = serde_json::json!({ "accounts": 3 });
+        genesis.consensus_params.max_evidence_per_block = 7;
+
+        core.init_chain(&genesis).unwrap();
+
+        assert_eq!(*seen.lock().unwrap(), genesis.app_state);
+        assert_eq!(core.params.max_evidence_per_block, 7);
+        assert_eq!(core.validators.total_power(), 20);
+        assert_eq!(core.block_store.validators_at(2).unwrap().len(), 2);
+    }
+}
@@ -0,0 +1,205 @@
+//! The block store: committed blocks and their commits, by height, the
+//! results of their transactions, and the validator set in force at each height.
+//!
+//! Each height is written to its own file in the store directory, if one is
+//! configured (`<height>.json` for blocks, `results/<height>.json` for
+//! transaction results, `validators/<height>.json` for validator sets), and
+//! everything is loaded back on open. Files are written as heights are
+//! committed; [`BlockStore::flush`] makes sure they reached the disk, and is
+//! called once every committed height has been stored.
+
+use std::collections::BTreeMap;
+use std::fs::File;
+use std::path::{Path, PathBuf};
+
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+
+use crate::app::ExecTxResult;
+
+use super::block::{tx_hash, Block};
+use super::commit::Commit;
+use super::validator::ValidatorSet;
+
+/// A committed block together with the commit that finalized it.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct StoredBlock {
+    pub block: Block,
+    pub commit: Commit,
+}
+
+/// Committed blocks, by height.
+#[derive(Debug, Default)]
+pub struct BlockStore {
+    blocks: BTreeMap<u64, StoredBlock>,
+    validators: BTreeMap<u64, ValidatorSet>,
+    tx_results: BTreeMap<u64, Vec<ExecTxResult>>,
+    /// Where blocks are persisted. `None` keeps them in memory only.
+    dir: Option<PathBuf>,
+    /// Files written since the last `flush`.
+    unsynced: Vec<PathBuf>,
+}
+
+impl BlockStore {
+    /// Creates an empty, in-memory store.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Opens the store in `dir`, creating the directory if needed and loading
+    /// every block already stored there.
+    pub fn open(dir: PathBuf) -> Result<Self> {
+        std::fs::create_dir_all(dir.join("validators"))?;
+        std::fs::create_dir_all(dir.join("results"))?;
+        let mut blocks = BTreeMap::new();
+        for path in json_files(&dir)? {
+            let stored: StoredBlock = serde_json::from_slice(&std::fs::read(&path)?)?;
+            blocks.insert(stored.block.height, stored);
+        }
+        Ok(Self {
+            blocks,
+            validators: load_by_height(&dir.join("validators"))?,
+            tx_results: load_by_height(&dir.join("results"))?,
+            dir: Some(dir),
+            unsynced: Vec::new(),
+        })
+    }
+
+    /// Returns the height of the latest stored block (0 if the store is empty).
+    pub fn height(&self) -> u64 {
+        self.blocks.keys().next_back().copied().unwrap_or(0)
+    }
+
+    /// Stores a committed block.
+    pub fn save_block(&mut self, block: Block, commit: Commit) -> Result<()> {
+        let stored = StoredBlock { block, commit };
+        if let Some(dir) = &self.dir {
+            let path = dir.join(format!("{}.json", stored.block.height));
+            std::fs::write(&path, serde_json::to_vec_pretty(&stored)?)?;
+            self.unsynced.push(path);
+        }
+        self.blocks.insert(stored.block.height, stored);
+        Ok(())
+    }
+
+    /// Syncs every file written since the last flush, and the directories
+    /// holding them, to disk.
+    pub fn flush(&mut self) -> Result<()> {
+        let Some(dir) = &self.dir else {
+            return Ok(());
+        };
+        for path in &self.unsynced {
+            File::open(path)?.sync_all()?;
+        }
+        self.unsynced.clear();
+        for sub in [dir.as_path(), &dir.join("validators"), &dir.join("results")] {
+            sync_dir(sub)?;
+        }
+        Ok(())
+    }
+
+    /// Whether every file written so far has been synced to disk.
+    pub fn is_synced(&self) -> bool {
+        self.unsynced.is_empty()
+    }
+
+    /// Returns the block committed at `height`.
+    pub fn load_block(&self, height: u64) -> Option<&Block> {
+        self.blocks.get(&height).map(|s| &s.block)
+    }
+
+    /// Returns the commit for the block at `height`.
+    pub fn load_commit(&self, height: u64) -> Option<&Commit> {
+        self.blocks.get(&height).map(|s| &s.commit)
+    }
+
+    /// Records the validator set in force at `height`.
+    pub fn save_validators(&mut self, height: u64, validators: ValidatorSet) -> Result<()> {
+        if let Some(dir) = &self.dir {
+            let path = dir.join("validators").join(format!("{}.json", height));
+            std::fs::write(&path, serde_json::to_vec_pretty(&validators)?)?;
+            self.unsynced.push(path);
+        }
+        self.validators.insert(height, validators);
+        Ok(())
+    }
+
+    /// Returns the validator set in force at `height`.
+    pub fn validators_at(&self, height: u64) -> Option<&ValidatorSet> {
+        self.validators.get(&height)
+    }
+
+    /// Records the results of the transactions of the block at `height`.
+    pub fn save_tx_results(&mut self, height: u64, results: Vec<ExecTxResult>) -> Result<()> {
+        if let Some(dir) = &self.dir {
+            let path = dir.join("results").join(format!("{}.json", height));
+            std::fs::write(&path, serde_json::to_vec_pretty(&results)?)?;
+            self.unsynced.push(path);
+        }
+        self.tx_results.insert(height, results);
+        Ok(())
+    }
+
+    /// Returns the results of the transactions of the block at `height`.
+    pub fn tx_results(&self, height: u64) -> Option<&[ExecTxResult]> {
+        self.tx_results.get(&height).map(Vec::as_slice)
+    }
+
+    /// Finds the transaction with the given hash in the blocks above
+    /// `after_height`, returning its height and index in the block.
+    pub fn find_tx(&self, hash: &str, after_height: u64) -> Option<(u64, usize)> {
+        self.blocks
+            .range(after_height + 1..)
+            .find_map(|(&height, s)| {
+                let index = s.block.txs.iter().position(|tx| tx_hash(tx) == hash)?;
+                Some((height, index))
+            })
+    }
+
+    /// Returns the block with the given hash, if we committed it.
+    pub fn block_by_hash(&self, hash: &str) -> Option<&Block> {
+        self.blocks
+            .values()
+            .find(|s| s.commit.block_hash == hash)
+            .map(|s| &s.block)
+    }
+}
+
+/// Loads every `<height>.json` file directly inside `dir`.
+fn load_by_height<T: serde::de::DeserializeOwned>(dir: &std::path::Path) -> Result<BTreeMap<u64, T>> {
+    let mut loaded = BTreeMap::new();
+    for path in json_files(dir)? {
+        let height = path
+            .file_stem()
+            .and_then(|s| s.to_str())
+            .and_then(|s| s.parse().ok())
+            .ok_or_else(|| anyhow::anyhow!("unexpected file {}", path.display()))?;
+        loaded.insert(height, serde_json::from_slice(&std::fs::read(&path)?)?);
+    }
+    Ok(loaded)
+}
+
+/// Lists the `.json` files directly inside `dir`.
+fn json_files(dir: &std::path::Path) -> Result<Vec<PathBuf>> {
+    let mut files = Vec::new();
+    for entry in std::fs::read_dir(dir)? {
+        let path = entry?.path();
+        if path.is_file() && path.extension().is_some_and(|ext| ext == "json") {
+            files.push(path);
+        }
+    }
+    Ok(files)
+}
+
+/// Syncs the entries of `dir`, so that new files in it survive a crash.
+#[cfg(unix)]
+fn sync_dir(dir: &Path) -> Result<()> {
+    File::open(dir)?.sync_all()?;
+    Ok(())
+}
+
+/// Directories can't be synced on this platform; syncing the files is all we can do.
+#[cfg(not(unix))]
+fn sync_dir(_dir: &Path) -> Result<()> {
+    Ok(())
+}
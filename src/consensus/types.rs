@@ -1,9 +1,15 @@
 use std::collections::HashMap;
 
+use serde::{Deserialize, Serialize};
+
+use super::block::{Block, PartSet};
+use super::vote::Vote;
+
 /// The consensus steps in a simplified Tendermint-like round.
-#[derive(Debug, Clone, PartialEq)]
+#[derive(Debug, Clone, PartialEq, Default, Serialize, Deserialize)]
 pub enum Step {
     /// Propose: A node proposes a new block.
+    #[default]
     Propose,
     /// Prevote: Nodes broadcast votes after receiving a proposal.
     Prevote,
@@ -13,32 +19,51 @@ pub enum Step {
     Commit,
 }
 
+/// The two kinds of votes cast in each round.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum VoteType {
+    Prevote,
+    Precommit,
+}
+
 /// Holds metadata for the current round, including which step we're on,
 /// the proposed block, and votes (prevotes/precommits).
 #[derive(Debug, Default)]
 pub struct RoundState {
+    /// The height of the block being decided.
+    pub height: u64,
     /// The round number (increases whenever there's a new attempt to agree on a block).
     pub round: u64,
     /// The step within the round (Propose, Prevote, Precommit, or Commit).
     pub step: Step,
-    /// The proposed block for this round (if any).
-    pub proposal: Option<String>,
+    /// The validator that proposed the block for this round (if any).
+    pub proposer_id: Option<String>,
+    /// The proposed block for this round, once all of its parts have arrived.
+    pub proposal: Option<Block>,
+    /// Hash of the proposed block, as announced in the `Proposal` message.
+    pub proposal_block_hash: Option<String>,
+    /// The parts of the proposed block received so far.
+    pub proposal_parts: Option<PartSet>,
     /// If a block is locked, it means we've decided to proceed with that block
     /// unless a higher round decides otherwise (Tendermint's "lock" mechanism).
     pub locked_block_hash: Option<String>,
-    /// Collection of prevotes from validators (maps voter ID to the block hash they voted for).
-    pub prevotes: HashMap<String, String>,
-    /// Collection of precommits from validators (maps voter ID to the block hash they voted for).
-    pub precommits: HashMap<String, String>,
+    /// Collection of prevotes from validators (maps voter ID to their signed vote).
+    pub prevotes: HashMap<String, Vote>,
+    /// Collection of precommits from validators (maps voter ID to their signed vote).
+    pub precommits: HashMap<String, Vote>,
 }
 
 impl RoundState {
-    /// Constructs a new `RoundState` with round = 0, step = Propose, and empty votes.
+    /// Constructs a new `RoundState` with height = 1, round = 0, step = Propose, and empty votes.
     pub fn new() -> Self {
         Self {
+            height: 1,
             round: 0,
             step: Step::Propose,
+            proposer_id: None,
             proposal: None,
+            proposal_block_hash: None,
+            proposal_parts: None,
             locked_block_hash: None,
             prevotes: HashMap::new(),
             precommits: HashMap::new(),
@@ -47,16 +72,28 @@ impl RoundState {
 }
 
 /// Configuration parameters for the consensus protocol, e.g., how many votes are needed, timeouts, etc.
-#[derive(Debug)]
+///
+/// Every node of a chain must use the same values, so they are set by the genesis file.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
 pub struct ConsensusParams {
     /// The fraction of validators needed to reach a quorum (e.g., 2/3).
     pub quorum_threshold: f32,
+    /// Maximum size of a proposed block, in bytes. Bounds how many parts a proposal may announce.
+    pub max_block_bytes: usize,
+    /// Evidence older than this many blocks is no longer accepted.
+    pub evidence_max_age_blocks: u64,
+    /// Maximum number of evidence items a proposer includes in one block.
+    pub max_evidence_per_block: usize,
 }
 
 impl Default for ConsensusParams {
     fn default() -> Self {
         Self {
             quorum_threshold: 0.67,
+            max_block_bytes: 4 * 1024 * 1024,
+            evidence_max_age_blocks: 100_000,
+            max_evidence_per_block: 50,
         }
     }
 }
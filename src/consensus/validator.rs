@@ -1,17 +1,84 @@
-/// A simple placeholder for storing validator identities.
-/// Real Tendermint uses dynamic validator sets, changes, staking, etc.
+//! Validator sets: who votes, with how much power, and who proposes next.
+//!
+//! Votes are weighted by voting power, so every quorum (+2/3, +1/3) is a
+//! fraction of the total power rather than of the number of validators.
+//!
+//! The proposer is chosen by the usual weighted round-robin: every election
+//! adds each validator's power to its proposer priority, elects the validator
+//! with the highest priority (ties go to the smaller ID) and subtracts the
+//! total power from the winner. Over time each validator proposes in
+//! proportion to its power. Rounds are numbered from 1 here, and the proposer
+//! of round `r` is the winner of the `r`-th election from the height's set.
+//! A [`ProposerSchedule`] remembers the elections already run for a height,
+//! so looking up the proposer of a later round only runs the missing ones.
+//!
+//! The application changes the set through [`ValidatorUpdate`]s. New
+//! validators start with a priority of -1.125 times the total power, so that
+//! joining (or leaving and re-joining) never makes a validator propose
+//! straight away, and priorities are re-centered after every change. The
+//! total power is capped at [`MAX_TOTAL_VOTING_POWER`], so this priority
+//! arithmetic can't overflow `i64`.
 
-/// Represents a set of validators, each with an ID.
-/// In real usage, these IDs would be public keys.
-#[derive(Debug)]
+use std::collections::HashSet;
+
+use anyhow::{bail, Result};
+use ed25519_dalek::VerifyingKey;
+use serde::{Deserialize, Serialize};
+
+/// Priorities are scaled down whenever their spread exceeds this many times the total power.
+const PRIORITY_WINDOW_SIZE_FACTOR: i64 = 2;
+
+/// The most voting power a validator set may hold in total. Priorities reach
+/// -1.125 times the total power, and their window twice the total power, so
+/// this leaves room to spare in an `i64`.
+pub const MAX_TOTAL_VOTING_POWER: u64 = i64::MAX as u64 / 8;
+
+/// A single validator.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Validator {
+    pub id: String,
+    /// The key its votes are signed with.
+    #[serde(with = "pub_key_hex")]
+    pub pub_key: VerifyingKey,
+    /// Voting power.
+    pub power: u64,
+    /// Accumulated proposer priority.
+    pub proposer_priority: i64,
+}
+
+impl Validator {
+    /// Creates a validator with zero proposer priority.
+    pub fn new(id: String, pub_key: VerifyingKey, power: u64) -> Self {
+        Self {
+            id,
+            pub_key,
+            power,
+            proposer_priority: 0,
+        }
+    }
+}
+
+/// A change to the validator set requested by the application. A `power` of
+/// zero removes the validator.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ValidatorUpdate {
+    pub id: String,
+    #[serde(with = "pub_key_hex")]
+    pub pub_key: VerifyingKey,
+    pub power: u64,
+}
+
+/// An ordered set of validators: by power (descending), then by ID.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
 pub struct ValidatorSet {
-    /// List of validators (IDs as strings).
-    pub validators: Vec<String>,
+    /// List of validators, in voting-index order.
+    pub validators: Vec<Validator>,
 }
 
 impl ValidatorSet {
-    /// Constructs a validator set from a given vector of IDs.
-    pub fn new_simple(validators: Vec<String>) -> Self {
+    /// Constructs a validator set from the given validators.
+    pub fn new(mut validators: Vec<Validator>) -> Self {
+        sort_validators(&mut validators);
         Self { validators }
     }
 
@@ -20,9 +87,307 @@ impl ValidatorSet {
         self.validators.len()
     }
 
+    /// Returns `true` if the set has no validators.
+    pub fn is_empty(&self) -> bool {
+        self.validators.is_empty()
+    }
+
+    /// Returns the position of validator `id` in the set, if present.
+    pub fn index_of(&self, id: &str) -> Option<usize> {
+        self.validators.iter().position(|v| v.id == id)
+    }
+
+    /// Returns the validator at position `index`.
+    pub fn get(&self, index: usize) -> Option<&Validator> {
+        self.validators.get(index)
+    }
+
+    /// Returns the public key of validator `id`, if it is in the set.
+    pub fn pub_key(&self, id: &str) -> Option<&VerifyingKey> {
+        self.validators.iter().find(|v| v.id == id).map(|v| &v.pub_key)
+    }
+
     /// Checks if the set contains a validator with the specified `id`.
     pub fn contains(&self, id: &str) -> bool {
-        self.validators.contains(&id.to_string())
+        self.index_of(id).is_some()
+    }
+
+    /// Returns the voting power of validator `id` (zero if not in the set).
+    pub fn power_of(&self, id: &str) -> u64 {
+        self.validators.iter().find(|v| v.id == id).map_or(0, |v| v.power)
+    }
+
+    /// Returns the sum of all voting power.
+    pub fn total_power(&self) -> u64 {
+        self.validators.iter().map(|v| v.power).sum()
+    }
+
+    /// Returns the proposer for `round` at this set's height. This runs
+    /// `round` elections on a copy of the set; see [`ProposerSchedule`] for
+    /// repeated lookups.
+    pub fn proposer_for_round(&self, round: u64) -> Option<String> {
+        let mut set = self.clone();
+        let mut proposer = None;
+        for _ in 0..round.max(1) {
+            proposer = set.elect_proposer();
+        }
+        proposer
+    }
+
+    /// Runs `times` proposer elections, updating priorities.
+    pub fn increment_proposer_priority(&mut self, times: u64) {
+        for _ in 0..times {
+            self.elect_proposer();
+        }
+    }
+
+    /// Applies `updates` atomically: either all of them or, on error, none.
+    ///
+    /// Fails if a validator appears twice, an unknown validator is removed,
+    /// a known validator changes key, or the set would become empty or its
+    /// total power exceed [`MAX_TOTAL_VOTING_POWER`].
+    pub fn apply_updates(&mut self, updates: &[ValidatorUpdate]) -> Result<()> {
+        let mut seen = HashSet::new();
+        for u in updates {
+            if !seen.insert(u.id.as_str()) {
+                bail!("duplicate validator update for {}", u.id);
+            }
+            match self.validators.iter().find(|v| v.id == u.id) {
+                None if u.power == 0 => bail!("cannot remove unknown validator {}", u.id),
+                Some(v) if v.pub_key != u.pub_key => bail!("validator {} cannot change its key", u.id),
+                _ => {}
+            }
+        }
+
+        // Existing validators keep their priority; removed ones are dropped.
+        let mut validators = self.validators.clone();
+        validators.retain(|v| updates.iter().all(|u| u.id != v.id || u.power > 0));
+        for u in updates.iter().filter(|u| u.power > 0) {
+            if let Some(v) = validators.iter_mut().find(|v| v.id == u.id) {
+                v.power = u.power;
+            }
+        }
+        let joining: Vec<&ValidatorUpdate> = updates
+            .iter()
+            .filter(|u| u.power > 0 && !self.contains(&u.id))
+            .collect();
+        if validators.is_empty() && joining.is_empty() {
+            bail!("validator updates would remove every validator");
+        }
+
+        let total_after = validators
+            .iter()
+            .map(|v| v.power)
+            .chain(joining.iter().map(|u| u.power))
+            .try_fold(0u64, u64::checked_add)
+            .filter(|&total| total <= MAX_TOTAL_VOTING_POWER);
+        let Some(total_after) = total_after else {
+            bail!("validator updates would raise the total power above {}", MAX_TOTAL_VOTING_POWER);
+        };
+        let join_priority = -((total_after + total_after / 8) as i64);
+        for u in joining {
+            let mut v = Validator::new(u.id.clone(), u.pub_key, u.power);
+            v.proposer_priority = join_priority;
+            validators.push(v);
+        }
+
+        sort_validators(&mut validators);
+        self.validators = validators;
+        self.rescale_priorities();
+        self.center_priorities();
+        Ok(())
+    }
+
+    /// Runs one proposer election and returns the winner's ID.
+    fn elect_proposer(&mut self) -> Option<String> {
+        if self.validators.is_empty() {
+            return None;
+        }
+        self.rescale_priorities();
+        self.center_priorities();
+        for v in &mut self.validators {
+            v.proposer_priority += v.power as i64;
+        }
+        let total = self.total_power() as i64;
+        let proposer = self
+            .validators
+            .iter_mut()
+            .reduce(|best, v| {
+                if (v.proposer_priority, std::cmp::Reverse(&v.id))
+                    > (best.proposer_priority, std::cmp::Reverse(&best.id))
+                {
+                    v
+                } else {
+                    best
+                }
+            })?;
+        proposer.proposer_priority -= total;
+        Some(proposer.id.clone())
+    }
+
+    /// Scales priorities down so their spread stays within the window.
+    fn rescale_priorities(&mut self) {
+        let window = PRIORITY_WINDOW_SIZE_FACTOR * self.total_power() as i64;
+        let max = self.validators.iter().map(|v| v.proposer_priority).max().unwrap_or(0);
+        let min = self.validators.iter().map(|v| v.proposer_priority).min().unwrap_or(0);
+        let spread = max - min;
+        if window > 0 && spread > window {
+            let ratio = (spread + window - 1) / window;
+            for v in &mut self.validators {
+                v.proposer_priority /= ratio;
+            }
+        }
+    }
+
+    /// Shifts priorities so they average to zero.
+    fn center_priorities(&mut self) {
+        if self.validators.is_empty() {
+            return;
+        }
+        let sum: i128 = self.validators.iter().map(|v| v.proposer_priority as i128).sum();
+        let avg = (sum / self.validators.len() as i128) as i64;
+        for v in &mut self.validators {
+            v.proposer_priority -= avg;
+        }
+    }
+}
+
+/// The proposers of successive rounds of one height, each elected once.
+#[derive(Debug, Clone)]
+pub struct ProposerSchedule {
+    /// The height's set the schedule starts from.
+    base: ValidatorSet,
+    /// `base` after the elections so far.
+    set: ValidatorSet,
+    /// The proposers of rounds 1, 2, ... elected so far.
+    proposers: Vec<String>,
+}
+
+impl ProposerSchedule {
+    /// Starts the schedule of the height run by `validators`.
+    pub fn new(validators: &ValidatorSet) -> Self {
+        Self {
+            base: validators.clone(),
+            set: validators.clone(),
+            proposers: Vec::new(),
+        }
+    }
+
+    /// Returns the proposer for `round` at the height run by `validators`,
+    /// as [`ValidatorSet::proposer_for_round`] would. The schedule starts over
+    /// if `validators` isn't the set it was started from.
+    pub fn proposer(&mut self, validators: &ValidatorSet, round: u64) -> Option<String> {
+        if self.base != *validators {
+            *self = Self::new(validators);
+        }
+        let round = round.max(1);
+        while (self.proposers.len() as u64) < round {
+            let proposer = self.set.elect_proposer()?;
+            self.proposers.push(proposer);
+        }
+        self.proposers.get(round as usize - 1).cloned()
+    }
+}
+
+fn sort_validators(validators: &mut [Validator]) {
+    validators.sort_by(|a, b| b.power.cmp(&a.power).then_with(|| a.id.cmp(&b.id)));
+}
+
+/// Serializes public keys as hex strings.
+pub(crate) mod pub_key_hex {
+    use ed25519_dalek::VerifyingKey;
+    use serde::{de::Error, Deserialize, Deserializer, Serializer};
+
+    pub fn serialize<S: Serializer>(key: &VerifyingKey, s: S) -> Result<S::Ok, S::Error> {
+        s.serialize_str(&hex::encode(key.as_bytes()))
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(d: D) -> Result<VerifyingKey, D::Error> {
+        let s = String::deserialize(d)?;
+        let bytes: [u8; 32] = hex::decode(&s)
+            .map_err(D::Error::custom)?
+            .try_into()
+            .map_err(|_| D::Error::custom("public key must be 32 bytes"))?;
+        VerifyingKey::from_bytes(&bytes).map_err(D::Error::custom)
     }
 }
 
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::p2p::key::NodeKey;
+
+    fn validator(id: &str, power: u64) -> Validator {
+        Validator::new(id.into(), NodeKey::generate().public_key(), power)
+    }
+
+    fn proposers(set: &ValidatorSet, rounds: u64) -> Vec<String> {
+        (1..=rounds).map(|r| set.proposer_for_round(r).unwrap()).collect()
+    }
+
+    #[test]
+    fn proposers_rotate_in_proportion_to_power() {
+        let set = ValidatorSet::new(vec![validator("a", 1), validator("b", 2), validator("c", 1)]);
+        let picks = proposers(&set, 8);
+        assert_eq!(picks.iter().filter(|p| *p == "b").count(), 4);
+        assert_eq!(picks.iter().filter(|p| *p == "a").count(), 2);
+        assert_eq!(picks.iter().filter(|p| *p == "c").count(), 2);
+    }
+
+    #[test]
+    fn schedule_matches_direct_elections_and_follows_set_changes() {
+        let set = ValidatorSet::new(vec![validator("a", 1), validator("b", 2), validator("c", 1)]);
+        let mut schedule = ProposerSchedule::new(&set);
+        for round in [3, 1, 8, 5] {
+            assert_eq!(schedule.proposer(&set, round), set.proposer_for_round(round));
+        }
+
+        let mut next = set.clone();
+        next.increment_proposer_priority(1);
+        assert_eq!(schedule.proposer(&next, 2), next.proposer_for_round(2));
+    }
+
+    #[test]
+    fn updates_add_change_and_remove_validators() {
+        let mut set = ValidatorSet::new(vec![validator("a", 10), validator("b", 10)]);
+        set.increment_proposer_priority(3);
+        let d = validator("d", 30);
+        let a_key = set.validators[0].pub_key;
+
+        set.apply_updates(&[
+            ValidatorUpdate { id: "a".into(), pub_key: a_key, power: 5 },
+            ValidatorUpdate { id: "b".into(), pub_key: set.validators[1].pub_key, power: 0 },
+            ValidatorUpdate { id: d.id.clone(), pub_key: d.pub_key, power: d.power },
+        ])
+        .unwrap();
+
+        // Sorted by power; the newcomer starts behind despite its power.
+        assert_eq!(set.validators.iter().map(|v| v.id.as_str()).collect::<Vec<_>>(), ["d", "a"]);
+        assert_eq!(set.total_power(), 35);
+        assert!(set.validators[0].proposer_priority < set.validators[1].proposer_priority);
+        // Priorities are re-centered (up to integer rounding).
+        let sum: i64 = set.validators.iter().map(|v| v.proposer_priority).sum();
+        assert!(sum.abs() < set.len() as i64);
+
+        // Invalid batches change nothing.
+        let before = set.clone();
+        let unknown = validator("x", 0);
+        assert!(set
+            .apply_updates(&[ValidatorUpdate { id: unknown.id, pub_key: unknown.pub_key, power: 0 }])
+            .is_err());
+        assert!(set
+            .apply_updates(&[
+                ValidatorUpdate { id: "a".into(), pub_key: a_key, power: 0 },
+                ValidatorUpdate { id: "d".into(), pub_key: d.pub_key, power: 0 },
+            ])
+            .is_err());
+        let err = set
+            .apply_updates(&[
+                ValidatorUpdate { id: "a".into(), pub_key: a_key, power: MAX_TOTAL_VOTING_POWER },
+                ValidatorUpdate { id: "d".into(), pub_key: d.pub_key, power: u64::MAX },
+            ])
+            .unwrap_err();
+        assert!(err.to_string().contains("total power"));
+        assert_eq!(set, before);
+    }
+}
@@ -0,0 +1,76 @@
+//! Signed prevotes and precommits.
+//!
+//! A vote commits its validator to one block hash at a given height, round and
+//! vote type. The signature covers all of those fields, so two votes from the
+//! same validator for different blocks at the same height/round/type prove that
+//! it equivocated (see `evidence.rs`).
+
+use ed25519_dalek::{Signature, Verifier, VerifyingKey};
+use serde::{Deserialize, Serialize};
+
+use crate::p2p::key::NodeKey;
+
+use super::types::VoteType;
+
+/// A single prevote or precommit.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Vote {
+    pub vote_type: VoteType,
+    pub height: u64,
+    pub round: u64,
+    /// Hash of the block voted for.
+    pub block_hash: String,
+    /// ID of the validator casting the vote.
+    pub validator_id: String,
+    /// Ed25519 signature over [`Vote::sign_bytes`]. Empty until signed.
+    pub signature: Vec<u8>,
+}
+
+impl Vote {
+    /// Creates an unsigned vote.
+    pub fn new(vote_type: VoteType, height: u64, round: u64, block_hash: String, validator_id: String) -> Self {
+        Self {
+            vote_type,
+            height,
+            round,
+            block_hash,
+            validator_id,
+            signature: Vec::new(),
+        }
+    }
+
+    /// Returns the bytes the signature covers: every field except the signature.
+    pub fn sign_bytes(&self) -> Vec<u8> {
+        bincode::serialize(&(
+            self.vote_type,
+            self.height,
+            self.round,
+            &self.block_hash,
+            &self.validator_id,
+        ))
+        .expect("serializing a tuple of plain values cannot fail")
+    }
+
+    /// Signs the vote with `key`, replacing any previous signature.
+    pub fn sign(&mut self, key: &NodeKey) {
+        self.signature = key.sign(&self.sign_bytes()).to_bytes().to_vec();
+    }
+
+    /// Returns `true` if the vote carries a valid signature by `public_key`.
+    pub fn verify(&self, public_key: &VerifyingKey) -> bool {
+        let Ok(signature) = Signature::from_slice(&self.signature) else {
+            return false;
+        };
+        public_key.verify(&self.sign_bytes(), &signature).is_ok()
+    }
+
+    /// Returns `true` if `other` is a vote by the same validator for the same
+    /// height, round and type, but for a different block.
+    pub fn conflicts_with(&self, other: &Vote) -> bool {
+        self.validator_id == other.validator_id
+            && self.vote_type == other.vote_type
+            && self.height == other.height
+            && self.round == other.round
+            && self.block_hash != other.block_hash
+    }
+}
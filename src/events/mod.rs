@@ -0,0 +1,294 @@
+//! The event bus: what consensus reports as it happens, for subscribers such
+//! as WebSocket clients.
+//!
+//! `ConsensusCore` publishes an [`EventData`] whenever a round starts, a vote
+//! is added, a round times out, a block (with each of its transactions) is
+//! committed or the application changes the validator set. Every event comes
+//! with its composite keys (`tm.event`, plus e.g. `tx.hash`, `tx.height` and
+//! `<type>.<key>` for each attribute of the application's events), which
+//! subscribers filter with a [`Query`].
+//!
+//! Each subscription has a bounded buffer. Publishing never blocks: a
+//! subscriber whose buffer is full is not keeping up and is cancelled, so a
+//! slow client can't hold up consensus or grow our memory.
+
+use std::collections::HashMap;
+use std::fmt;
+use std::sync::{Arc, Mutex};
+
+use serde::{Serialize, Serializer};
+use tokio::sync::mpsc;
+use tracing::warn;
+
+use crate::app::{Event, ExecTxResult};
+use crate::consensus::block::{tx_hash, Block, Tx};
+use crate::consensus::types::Step;
+use crate::consensus::validator::ValidatorUpdate;
+use crate::consensus::vote::Vote;
+
+pub mod query;
+
+pub use query::Query;
+
+/// The composite key every event carries its type under.
+pub const EVENT_TYPE_KEY: &str = "tm.event";
+
+/// What happened, with the data subscribers need to act on it.
+#[derive(Debug, Clone, Serialize)]
+#[serde(tag = "type", content = "value")]
+pub enum EventData {
+    /// A block was committed. `events` are the block's own events from the application.
+    NewBlock { block: Block, events: Vec<Event> },
+    /// A round started.
+    NewRound { height: u64, round: u64, proposer: Option<String> },
+    /// A vote was added to the current round.
+    Vote { vote: Vote },
+    /// A committed transaction, with its result.
+    Tx {
+        height: u64,
+        index: usize,
+        #[serde(serialize_with = "hex_bytes")]
+        tx: Tx,
+        result: ExecTxResult,
+    },
+    /// The application changed the validator set; the changes apply from `height`.
+    ValidatorSetUpdates { height: u64, updates: Vec<ValidatorUpdate> },
+    /// A round ended without a decision, at `step`.
+    Timeout { height: u64, round: u64, step: Step },
+}
+
+impl EventData {
+    /// Returns the event's type, the value of its `tm.event` key.
+    pub fn kind(&self) -> &'static str {
+        match self {
+            EventData::NewBlock { .. } => "NewBlock",
+            EventData::NewRound { .. } => "NewRound",
+            EventData::Vote { .. } => "Vote",
+            EventData::Tx { .. } => "Tx",
+            EventData::ValidatorSetUpdates { .. } => "ValidatorSetUpdates",
+            EventData::Timeout { .. } => "Timeout",
+        }
+    }
+
+    /// Returns the composite keys the event is matched on.
+    pub fn composite_keys(&self) -> HashMap<String, Vec<String>> {
+        let mut keys: HashMap<String, Vec<String>> = HashMap::new();
+        keys.insert(EVENT_TYPE_KEY.into(), vec![self.kind().into()]);
+        let mut add_events = |events: &[Event]| {
+            for event in events {
+                for attr in &event.attributes {
+                    keys.entry(format!("{}.{}", event.kind, attr.key))
+                        .or_default()
+                        .push(attr.value.clone());
+                }
+            }
+        };
+        match self {
+            EventData::NewBlock { block, events } => {
+                add_events(events);
+                keys.insert("block.height".into(), vec![block.height.to_string()]);
+            }
+            EventData::Tx { height, tx, result, .. } => {
+                add_events(&result.events);
+                keys.insert("tx.hash".into(), vec![tx_hash(tx)]);
+                keys.insert("tx.height".into(), vec![height.to_string()]);
+            }
+            _ => {}
+        }
+        keys
+    }
+}
+
+fn hex_bytes<S: Serializer>(bytes: &[u8], s: S) -> Result<S::Ok, S::Error> {
+    s.serialize_str(&hex::encode(bytes))
+}
+
+/// An event as delivered to a subscriber.
+#[derive(Debug, Clone, Serialize)]
+pub struct Message {
+    pub data: EventData,
+    /// The composite keys the event was matched on.
+    pub events: HashMap<String, Vec<String>>,
+}
+
+/// Why a subscription was refused.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum SubscribeError {
+    /// The client is already subscribed to this query.
+    AlreadySubscribed,
+    /// The client has reached its maximum number of subscriptions.
+    TooManySubscriptions { max: usize },
+}
+
+impl fmt::Display for SubscribeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SubscribeError::AlreadySubscribed => write!(f, "already subscribed"),
+            SubscribeError::TooManySubscriptions { max } => {
+                write!(f, "max subscriptions per client ({}) reached", max)
+            }
+        }
+    }
+}
+
+impl std::error::Error for SubscribeError {}
+
+/// A client's interest in the events matching a query.
+#[derive(Debug)]
+pub struct Subscription {
+    pub query: Query,
+    /// Matching events. Closed when the subscription ends: after `unsubscribe`,
+    /// or when the bus cancelled it because the buffer filled up.
+    pub events: mpsc::Receiver<Message>,
+}
+
+#[derive(Debug)]
+struct Subscriber {
+    client_id: String,
+    query: Query,
+    sender: mpsc::Sender<Message>,
+}
+
+/// Distributes published events to the subscriptions whose query they match.
+/// Clones share the same subscribers.
+#[derive(Debug, Clone, Default)]
+pub struct EventBus {
+    subscribers: Arc<Mutex<Vec<Subscriber>>>,
+}
+
+impl EventBus {
+    /// Creates a bus without subscribers.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Subscribes `client_id` to the events matching `query`, buffering up
+    /// to `capacity` of them. A client may hold at most `max_per_client`
+    /// subscriptions, with different queries.
+    pub fn subscribe(
+        &self,
+        client_id: &str,
+        query: Query,
+        capacity: usize,
+        max_per_client: usize,
+    ) -> Result<Subscription, SubscribeError> {
+        let mut subscribers = self.subscribers.lock().unwrap();
+        let existing: Vec<&Subscriber> = subscribers.iter().filter(|s| s.client_id == client_id).collect();
+        if existing.iter().any(|s| s.query == query) {
+            return Err(SubscribeError::AlreadySubscribed);
+        }
+        if existing.len() >= max_per_client {
+            return Err(SubscribeError::TooManySubscriptions { max: max_per_client });
+        }
+        let (sender, events) = mpsc::channel(capacity.max(1));
+        subscribers.push(Subscriber {
+            client_id: client_id.to_string(),
+            query: query.clone(),
+            sender,
+        });
+        Ok(Subscription { query, events })
+    }
+
+    /// Ends `client_id`'s subscription to `query`. Returns `false` if there was none.
+    pub fn unsubscribe(&self, client_id: &str, query: &Query) -> bool {
+        let mut subscribers = self.subscribers.lock().unwrap();
+        let before = subscribers.len();
+        subscribers.retain(|s| !(s.client_id == client_id && &s.query == query));
+        subscribers.len() < before
+    }
+
+    /// Ends all of `client_id`'s subscriptions, returning how many there were.
+    pub fn unsubscribe_all(&self, client_id: &str) -> usize {
+        let mut subscribers = self.subscribers.lock().unwrap();
+        let before = subscribers.len();
+        subscribers.retain(|s| s.client_id != client_id);
+        before - subscribers.len()
+    }
+
+    /// Returns the number of active subscriptions.
+    pub fn num_subscriptions(&self) -> usize {
+        self.subscribers.lock().unwrap().len()
+    }
+
+    /// Delivers `data` to every matching subscription. Subscriptions whose
+    /// buffer is full are cancelled; those whose receiver is gone are removed.
+    pub fn publish(&self, data: EventData) {
+        let mut subscribers = self.subscribers.lock().unwrap();
+        if subscribers.is_empty() {
+            return;
+        }
+        let events = data.composite_keys();
+        subscribers.retain(|s| {
+            if !s.query.matches(&events) {
+                return true;
+            }
+            let message = Message {
+                data: data.clone(),
+                events: events.clone(),
+            };
+            match s.sender.try_send(message) {
+                Ok(()) => true,
+                Err(mpsc::error::TrySendError::Full(_)) => {
+                    warn!(
+                        "Cancelling subscription of {} to `{}`: client is not pulling messages fast enough",
+                        s.client_id, s.query
+                    );
+                    false
+                }
+                Err(mpsc::error::TrySendError::Closed(_)) => false,
+            }
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn new_round(round: u64) -> EventData {
+        EventData::NewRound { height: 1, round, proposer: None }
+    }
+
+    #[test]
+    fn delivers_matching_events_and_cancels_slow_subscribers() {
+        let bus = EventBus::new();
+        let mut rounds = bus
+            .subscribe("a", "tm.event='NewRound'".parse().unwrap(), 2, 5)
+            .unwrap();
+        let mut votes = bus.subscribe("b", "tm.event='Vote'".parse().unwrap(), 2, 5).unwrap();
+        assert_eq!(
+            bus.subscribe("a", "tm.event = 'NewRound'".parse().unwrap(), 2, 5).unwrap_err(),
+            SubscribeError::AlreadySubscribed
+        );
+
+        bus.publish(new_round(1));
+        bus.publish(new_round(2));
+        assert!(matches!(rounds.events.try_recv().unwrap().data, EventData::NewRound { round: 1, .. }));
+        assert!(votes.events.try_recv().is_err());
+
+        // One slot left: the second event overflows the buffer and cancels the subscription.
+        bus.publish(new_round(3));
+        bus.publish(new_round(4));
+        assert_eq!(bus.num_subscriptions(), 1);
+        let mut received = 0;
+        while rounds.events.try_recv().is_ok() {
+            received += 1;
+        }
+        assert_eq!(received, 2);
+        assert!(rounds.events.is_closed());
+
+        assert_eq!(bus.unsubscribe_all("b"), 1);
+    }
+
+    #[test]
+    fn tx_events_carry_application_attributes() {
+        let result = ExecTxResult {
+            events: vec![Event::new("transfer", &[("sender", "x"), ("amount", "10")])],
+            ..ExecTxResult::default()
+        };
+        let data = EventData::Tx { height: 3, index: 0, tx: b"tx".to_vec(), result };
+        let query: Query = "tm.event='Tx' AND transfer.sender='x' AND transfer.amount > 5".parse().unwrap();
+        assert!(query.matches(&data.composite_keys()));
+        assert_eq!(data.composite_keys()["tx.hash"], [tx_hash(b"tx")]);
+    }
+}
@@ -0,0 +1,302 @@
+//! Queries selecting events by their attributes.
+//!
+//! A query is one or more conditions joined by `AND`:
+//!
+//! ```text
+//! tm.event='Tx' AND transfer.sender='alice' AND tx.height >= 5
+//! ```
+//!
+//! Each condition names a composite key (`<event type>.<attribute key>`, or
+//! one of the keys the node adds itself, such as `tm.event` and `tx.hash`),
+//! an operator and an operand:
+//!
+//! - `=` matches a string (`'alice'`) exactly, or a number numerically;
+//! - `<`, `<=`, `>`, `>=` compare numbers;
+//! - `CONTAINS` matches a substring of a string;
+//! - `EXISTS` (without operand) matches any event with the key.
+//!
+//! A condition holds if any value of its key satisfies it.
+
+use std::collections::HashMap;
+use std::fmt;
+use std::str::FromStr;
+
+/// A comparison in a [`Condition`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Operator {
+    Eq,
+    Lt,
+    Le,
+    Gt,
+    Ge,
+    Contains,
+    Exists,
+}
+
+/// The value a key is compared with.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Operand {
+    String(String),
+    Number(f64),
+}
+
+/// One `key op operand` term of a query.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Condition {
+    pub key: String,
+    pub op: Operator,
+    /// `None` for `EXISTS`.
+    pub operand: Option<Operand>,
+}
+
+impl Condition {
+    /// Returns whether `value` satisfies the condition.
+    pub fn matches_value(&self, value: &str) -> bool {
+        match (&self.operand, self.op) {
+            (_, Operator::Exists) => true,
+            (Some(Operand::String(s)), Operator::Eq) => value == s,
+            (Some(Operand::String(s)), Operator::Contains) => value.contains(s.as_str()),
+            (Some(Operand::Number(n)), op) => {
+                let Ok(v) = value.parse::<f64>() else {
+                    return false;
+                };
+                match op {
+                    Operator::Eq => v == *n,
+                    Operator::Lt => v < *n,
+                    Operator::Le => v <= *n,
+                    Operator::Gt => v > *n,
+                    Operator::Ge => v >= *n,
+                    Operator::Contains | Operator::Exists => false,
+                }
+            }
+            _ => false,
+        }
+    }
+
+    /// Returns whether any value of the condition's key in `events` satisfies it.
+    pub fn matches(&self, events: &HashMap<String, Vec<String>>) -> bool {
+        events
+            .get(&self.key)
+            .is_some_and(|values| values.iter().any(|v| self.matches_value(v)))
+    }
+}
+
+/// Why a query could not be parsed.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct QueryError(String);
+
+impl fmt::Display for QueryError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "invalid query: {}", self.0)
+    }
+}
+
+impl std::error::Error for QueryError {}
+
+/// A parsed query: all of its conditions must hold.
+///
+/// Queries are equal if their conditions are, however they were written.
+#[derive(Debug, Clone)]
+pub struct Query {
+    source: String,
+    conditions: Vec<Condition>,
+}
+
+impl PartialEq for Query {
+    fn eq(&self, other: &Self) -> bool {
+        self.conditions == other.conditions
+    }
+}
+
+impl Query {
+    /// Returns the conditions, in the order they were written.
+    pub fn conditions(&self) -> &[Condition] {
+        &self.conditions
+    }
+
+    /// Returns whether `events` (composite key -> values) satisfy every condition.
+    pub fn matches(&self, events: &HashMap<String, Vec<String>>) -> bool {
+        self.conditions.iter().all(|c| c.matches(events))
+    }
+}
+
+impl fmt::Display for Query {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.source)
+    }
+}
+
+impl FromStr for Query {
+    type Err = QueryError;
+
+    fn from_str(source: &str) -> Result<Self, QueryError> {
+        let mut parser = Parser { rest: source };
+        let mut conditions = vec![parser.condition()?];
+        while !parser.at_end() {
+            parser.keyword("AND")?;
+            conditions.push(parser.condition()?);
+        }
+        Ok(Self {
+            source: source.trim().to_string(),
+            conditions,
+        })
+    }
+}
+
+/// A cursor over the unparsed rest of a query.
+struct Parser<'a> {
+    rest: &'a str,
+}
+
+impl Parser<'_> {
+    fn skip_whitespace(&mut self) {
+        self.rest = self.rest.trim_start();
+    }
+
+    fn at_end(&mut self) -> bool {
+        self.skip_whitespace();
+        self.rest.is_empty()
+    }
+
+    fn error(&self, expected: &str) -> QueryError {
+        if self.rest.is_empty() {
+            QueryError(format!("expected {} at end of query", expected))
+        } else {
+            QueryError(format!("expected {} at `{}`", expected, self.rest))
+        }
+    }
+
+    /// Consumes `word` if the rest starts with it as a whole word.
+    fn try_keyword(&mut self, word: &str) -> bool {
+        self.skip_whitespace();
+        let Some(after) = self.rest.strip_prefix(word) else {
+            return false;
+        };
+        if after.starts_with(|c: char| is_key_char(c)) {
+            return false;
+        }
+        self.rest = after;
+        true
+    }
+
+    fn keyword(&mut self, word: &str) -> Result<(), QueryError> {
+        if self.try_keyword(word) {
+            Ok(())
+        } else {
+            Err(self.error(word))
+        }
+    }
+
+    fn condition(&mut self) -> Result<Condition, QueryError> {
+        let key = self.key()?;
+        if self.try_keyword("EXISTS") {
+            return Ok(Condition { key, op: Operator::Exists, operand: None });
+        }
+        let op = self.operator()?;
+        let operand = self.operand()?;
+        match (&operand, op) {
+            (Operand::String(_), Operator::Lt | Operator::Le | Operator::Gt | Operator::Ge) => {
+                return Err(QueryError(format!("{} compares numbers, not strings", key)));
+            }
+            (Operand::Number(_), Operator::Contains) => {
+                return Err(QueryError(format!("{} CONTAINS needs a string", key)));
+            }
+            _ => {}
+        }
+        Ok(Condition { key, op, operand: Some(operand) })
+    }
+
+    fn key(&mut self) -> Result<String, QueryError> {
+        self.skip_whitespace();
+        let len = self.rest.find(|c: char| !is_key_char(c)).unwrap_or(self.rest.len());
+        if len == 0 {
+            return Err(self.error("a key"));
+        }
+        let (key, rest) = self.rest.split_at(len);
+        self.rest = rest;
+        Ok(key.to_string())
+    }
+
+    fn operator(&mut self) -> Result<Operator, QueryError> {
+        self.skip_whitespace();
+        for (token, op) in [
+            ("<=", Operator::Le),
+            (">=", Operator::Ge),
+            ("=", Operator::Eq),
+            ("<", Operator::Lt),
+            (">", Operator::Gt),
+        ] {
+            if let Some(rest) = self.rest.strip_prefix(token) {
+                self.rest = rest;
+                return Ok(op);
+            }
+        }
+        if self.try_keyword("CONTAINS") {
+            return Ok(Operator::Contains);
+        }
+        Err(self.error("an operator"))
+    }
+
+    fn operand(&mut self) -> Result<Operand, QueryError> {
+        self.skip_whitespace();
+        if let Some(quoted) = self.rest.strip_prefix('\'') {
+            let end = quoted.find('\'').ok_or_else(|| QueryError("unterminated string".into()))?;
+            self.rest = &quoted[end + 1..];
+            return Ok(Operand::String(quoted[..end].to_string()));
+        }
+        let len = self
+            .rest
+            .find(|c: char| !(c.is_ascii_digit() || c == '.' || c == '-'))
+            .unwrap_or(self.rest.len());
+        let number = self.rest[..len].parse().map_err(|_| self.error("a 'string' or a number"))?;
+        self.rest = &self.rest[len..];
+        Ok(Operand::Number(number))
+    }
+}
+
+fn is_key_char(c: char) -> bool {
+    c.is_ascii_alphanumeric() || matches!(c, '.' | '_' | '-')
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn events(pairs: &[(&str, &str)]) -> HashMap<String, Vec<String>> {
+        let mut events: HashMap<String, Vec<String>> = HashMap::new();
+        for (k, v) in pairs {
+            events.entry(k.to_string()).or_default().push(v.to_string());
+        }
+        events
+    }
+
+    #[test]
+    fn matches_conditions_against_any_value() {
+        let q: Query = "tm.event='Tx' AND transfer.sender='x' AND tx.height>=5 AND memo CONTAINS 'hi'"
+            .parse()
+            .unwrap();
+        assert_eq!(q.conditions().len(), 4);
+        let tx = events(&[
+            ("tm.event", "Tx"),
+            ("transfer.sender", "y"),
+            ("transfer.sender", "x"),
+            ("tx.height", "7"),
+            ("memo", "oh hi"),
+        ]);
+        assert!(q.matches(&tx));
+
+        let early = events(&[("tm.event", "Tx"), ("transfer.sender", "x"), ("tx.height", "4"), ("memo", "hi")]);
+        assert!(!q.matches(&early));
+        let exists: Query = "transfer.sender EXISTS".parse().unwrap();
+        assert!(exists.matches(&tx));
+        assert!(!exists.matches(&events(&[("tm.event", "NewBlock")])));
+    }
+
+    #[test]
+    fn rejects_malformed_queries() {
+        for bad in ["", "tm.event", "tm.event = ", "a='x' OR b='y'", "a < 'x'", "a = 'open", "a='x' AND"] {
+            assert!(bad.parse::<Query>().is_err(), "{:?} should not parse", bad);
+        }
+        assert_eq!("  a.b = 'x'  ".parse::<Query>().unwrap().to_string(), "a.b = 'x'");
+    }
+}
@@ -0,0 +1,210 @@
+//! The genesis file: everything that defines a chain before its first block.
+//!
+//! All nodes of a network start from the same `genesis.json`. It names the
+//! chain, fixes the initial validators and consensus parameters, and carries
+//! the application's initial state as opaque JSON. Nodes compare the hash of
+//! their genesis during the handshake and refuse peers from other chains.
+//!
+//! ```json
+//! {
+//!   "chain_id": "test-chain",
+//!   "genesis_time": 1700000000,
+//!   "validators": [{ "id": "node-a", "pub_key": "<hex>", "power": 10 }],
+//!   "consensus_params": { "max_block_bytes": 4194304 },
+//!   "app_state": {}
+//! }
+//! ```
+
+use std::collections::HashSet;
+use std::path::Path;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use anyhow::{bail, Context, Result};
+use ed25519_dalek::VerifyingKey;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+
+use crate::consensus::types::ConsensusParams;
+use crate::consensus::validator::{pub_key_hex, Validator, ValidatorSet, MAX_TOTAL_VOTING_POWER};
+
+/// Chain IDs longer than this are rejected.
+pub const MAX_CHAIN_ID_LEN: usize = 50;
+
+/// A validator of the initial validator set.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct GenesisValidator {
+    /// The validator's node ID.
+    pub id: String,
+    /// The key the validator signs votes with.
+    #[serde(with = "pub_key_hex")]
+    pub pub_key: VerifyingKey,
+    /// Initial voting power.
+    pub power: u64,
+}
+
+/// The contents of a `genesis.json` file.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Genesis {
+    /// Identifies the chain; nodes with different chain IDs never talk to each other.
+    pub chain_id: String,
+    /// When the chain started, in seconds since the Unix epoch.
+    pub genesis_time: u64,
+    /// The validators of the first height.
+    pub validators: Vec<GenesisValidator>,
+    /// Consensus parameters shared by all nodes. Missing fields take their defaults.
+    #[serde(default)]
+    pub consensus_params: ConsensusParams,
+    /// The application's initial state, passed to `Application::init_chain` as is.
+    #[serde(default)]
+    pub app_state: serde_json::Value,
+}
+
+impl Genesis {
+    /// Creates a genesis starting now, with default parameters and empty app state.
+    pub fn new(chain_id: String, validators: Vec<GenesisValidator>) -> Self {
+        let genesis_time = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_secs())
+            .unwrap_or(0);
+        Self {
+            chain_id,
+            genesis_time,
+            validators,
+            consensus_params: ConsensusParams::default(),
+            app_state: serde_json::Value::Object(Default::default()),
+        }
+    }
+
+    /// Reads and validates the genesis file at `path`.
+    pub fn load(path: impl AsRef<Path>) -> Result<Self> {
+        let path = path.as_ref();
+        let data = std::fs::read(path)
+            .with_context(|| format!("reading genesis file {}", path.display()))?;
+        let genesis: Genesis = serde_json::from_slice(&data)
+            .with_context(|| format!("parsing genesis file {}", path.display()))?;
+        genesis
+            .validate()
+            .with_context(|| format!("invalid genesis file {}", path.display()))?;
+        Ok(genesis)
+    }
+
+    /// Writes the genesis to `path` as pretty-printed JSON.
+    pub fn save(&self, path: impl AsRef<Path>) -> Result<()> {
+        let path = path.as_ref();
+        std::fs::write(path, serde_json::to_vec_pretty(self)?)
+            .with_context(|| format!("writing genesis file {}", path.display()))
+    }
+
+    /// Checks that the genesis describes a chain that can make progress.
+    pub fn validate(&self) -> Result<()> {
+        if self.chain_id.is_empty() {
+            bail!("chain_id must not be empty");
+        }
+        if self.chain_id.len() > MAX_CHAIN_ID_LEN {
+            bail!("chain_id is longer than {} characters", MAX_CHAIN_ID_LEN);
+        }
+        if self.validators.is_empty() {
+            bail!("validators must not be empty");
+        }
+        let mut ids = HashSet::new();
+        let mut total_power: u64 = 0;
+        for v in &self.validators {
+            if v.id.is_empty() {
+                bail!("validators: a validator has an empty id");
+            }
+            if !ids.insert(v.id.as_str()) {
+                bail!("validators: duplicate validator {}", v.id);
+            }
+            if v.power == 0 {
+                bail!("validators: validator {} has zero power", v.id);
+            }
+            total_power = match total_power.checked_add(v.power) {
+                Some(total) if total <= MAX_TOTAL_VOTING_POWER => total,
+                _ => bail!("validators: total power exceeds {}", MAX_TOTAL_VOTING_POWER),
+            };
+        }
+        let params = &self.consensus_params;
+        if !(params.quorum_threshold > 0.5 && params.quorum_threshold <= 1.0) {
+            bail!("consensus_params.quorum_threshold must be in (0.5, 1]");
+        }
+        if params.max_block_bytes == 0 {
+            bail!("consensus_params.max_block_bytes must be positive");
+        }
+        Ok(())
+    }
+
+    /// Returns the hex-encoded SHA-256 hash of the genesis.
+    ///
+    /// The hash covers the canonical JSON encoding (struct fields in
+    /// declaration order, object keys sorted), so formatting differences
+    /// between copies of the same file don't matter.
+    pub fn hash(&self) -> String {
+        let encoded = serde_json::to_vec(self).expect("genesis always serializes");
+        hex::encode(Sha256::digest(&encoded))
+    }
+
+    /// Returns the initial validator set.
+    pub fn validator_set(&self) -> ValidatorSet {
+        ValidatorSet::new(
+            self.validators
+                .iter()
+                .map(|v| Validator::new(v.id.clone(), v.pub_key, v.power))
+                .collect(),
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::p2p::key::NodeKey;
+
+    fn genesis() -> Genesis {
+        Genesis::new(
+            "test-chain".into(),
+            vec![GenesisValidator {
+                id: "node-a".into(),
+                pub_key: NodeKey::generate().public_key(),
+                power: 10,
+            }],
+        )
+    }
+
+    #[test]
+    fn round_trips_and_hashes_independently_of_formatting() {
+        let mut g = genesis();
+        g.app_state = serde_json::json!({ "b": 1, "a": [true] });
+        let compact: Genesis = serde_json::from_str(&serde_json::to_string(&g).unwrap()).unwrap();
+        let pretty: Genesis = serde_json::from_str(&serde_json::to_string_pretty(&g).unwrap()).unwrap();
+        assert_eq!(compact, g);
+        assert_eq!(compact.hash(), pretty.hash());
+
+        let mut other = g.clone();
+        other.chain_id = "other-chain".into();
+        assert_ne!(other.hash(), g.hash());
+    }
+
+    #[test]
+    fn validation_names_the_offending_field() {
+        let mut g = genesis();
+        g.validators.push(g.validators[0].clone());
+        assert!(g.validate().unwrap_err().to_string().contains("duplicate validator node-a"));
+
+        let mut g = genesis();
+        g.chain_id.clear();
+        assert!(g.validate().unwrap_err().to_string().starts_with("chain_id"));
+
+        let mut g = genesis();
+        g.validators[0].power = MAX_TOTAL_VOTING_POWER;
+        g.validators.push(GenesisValidator {
+            id: "node-b".into(),
+            pub_key: NodeKey::generate().public_key(),
+            power: 1,
+        });
+        assert!(g.validate().unwrap_err().to_string().starts_with("validators: total power"));
+
+        let mut g = genesis();
+        g.consensus_params.quorum_threshold = 0.3;
+        assert!(g.validate().unwrap_err().to_string().starts_with("consensus_params.quorum_threshold"));
+    }
+}
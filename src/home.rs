@@ -0,0 +1,313 @@
+//! Creating and resetting a node's home directory.
+//!
+//! See [`crate::config`] for the layout. `init` fills in whatever is missing
+//! and never overwrites existing files; the reset functions delete data but
+//! leave the configuration, genesis and node key alone. `testnet` creates the
+//! homes of a whole local network at once.
+
+use std::path::{Path, PathBuf};
+
+use anyhow::{bail, Result};
+
+use crate::config::Config;
+use crate::genesis::{Genesis, GenesisValidator};
+use crate::p2p::key::NodeKey;
+
+/// Voting power of the validator in a genesis created by [`init`].
+pub const DEFAULT_POWER: u64 = 10;
+
+/// What [`init`] did.
+#[derive(Debug)]
+pub struct Initialized {
+    /// The node's ID (new or existing).
+    pub node_id: String,
+    /// Files that were created.
+    pub created: Vec<PathBuf>,
+}
+
+/// Creates the config file, node key and a genesis with this node as the only
+/// validator of `chain_id`, skipping any that already exist.
+pub fn init(config: &Config, chain_id: &str) -> Result<Initialized> {
+    let mut created = Vec::new();
+
+    let config_file = config.resolve(crate::config::CONFIG_FILE);
+    if !config_file.exists() {
+        config.save()?;
+        created.push(config_file);
+    }
+
+    let key_file = config.node_key_file();
+    if !key_file.exists() {
+        created.push(key_file.clone());
+    }
+    let key = NodeKey::load_or_generate(&key_file)?;
+    let node_id = key.node_id();
+
+    let genesis_file = config.genesis_file();
+    if !genesis_file.exists() {
+        let validator = GenesisValidator {
+            id: node_id.clone(),
+            pub_key: key.public_key(),
+            power: DEFAULT_POWER,
+        };
+        let genesis = Genesis::new(chain_id.to_string(), vec![validator]);
+        genesis.validate()?;
+        genesis.save(&genesis_file)?;
+        created.push(genesis_file);
+    }
+
+    Ok(Initialized { node_id, created })
+}
+
+/// One node of a network created by [`testnet`].
+#[derive(Debug)]
+pub struct TestnetNode {
+    /// The node's home directory.
+    pub home: PathBuf,
+    pub node_id: String,
+    /// The node's P2P address.
+    pub listen_addr: String,
+}
+
+/// Creates home directories `node0` .. `node{n-1}` in `output_dir` for a
+/// local network of `n` validators of `chain_id`.
+///
+/// Each node gets its own key and listens on localhost: node `i` uses port
+/// `starting_port + 2i` for P2P and the port after it for RPC. All nodes share
+/// one genesis listing every node as a validator of equal power, and each
+/// node's `persistent_peers` lists all the others. Localhost is added to
+/// `unconditional_peers` so the nodes aren't limited by sharing an IP. Other
+/// settings are taken from `template`.
+///
+/// Fails without writing anything if one of the home directories already exists.
+pub fn testnet(
+    output_dir: &Path,
+    n: usize,
+    chain_id: &str,
+    starting_port: u16,
+    template: &Config,
+) -> Result<Vec<TestnetNode>> {
+    if n == 0 {
+        bail!("a testnet needs at least one validator");
+    }
+    let port = |i: usize, offset: usize| -> Result<u16> {
+        let port = starting_port as usize + 2 * i + offset;
+        u16::try_from(port).map_err(|_| anyhow::anyhow!("port {} is out of range", port))
+    };
+    let homes: Vec<PathBuf> = (0..n).map(|i| output_dir.join(format!("node{}", i))).collect();
+    if let Some(existing) = homes.iter().find(|h| h.exists()) {
+        bail!("{} already exists", existing.display());
+    }
+    let addrs = (0..n)
+        .map(|i| Ok(format!("127.0.0.1:{}", port(i, 0)?)))
+        .collect::<Result<Vec<_>>>()?;
+
+    let mut nodes = Vec::new();
+    let mut validators = Vec::new();
+    for (i, home) in homes.into_iter().enumerate() {
+        let mut config = template.clone();
+        config.home = home.clone();
+        config.moniker = format!("node{}", i);
+        config.p2p.listen_addr = addrs[i].clone();
+        config.p2p.persistent_peers = addrs.iter().filter(|a| **a != addrs[i]).cloned().collect();
+        config.rpc.listen_addr = format!("127.0.0.1:{}", port(i, 1)?);
+        // Every node shares one IP, which the per-IP and per-subnet limits would otherwise throttle.
+        if !config.p2p.unconditional_peers.iter().any(|ip| ip == "127.0.0.1") {
+            config.p2p.unconditional_peers.push("127.0.0.1".into());
+        }
+        config.validate()?;
+        config.save()?;
+
+        let key = NodeKey::generate();
+        key.save(config.node_key_file())?;
+        let node_id = key.node_id();
+        validators.push(GenesisValidator {
+            id: node_id.clone(),
+            pub_key: key.public_key(),
+            power: DEFAULT_POWER,
+        });
+        nodes.push(TestnetNode {
+            home,
+            node_id,
+            listen_addr: addrs[i].clone(),
+        });
+    }
+
+    let genesis = Genesis::new(chain_id.to_string(), validators);
+    genesis.validate()?;
+    for node in &nodes {
+        genesis.save(node.home.join(crate::config::GENESIS_FILE))?;
+    }
+    Ok(nodes)
+}
+
+/// Deletes the block store (with the validator set history), the evidence
+/// pool and the indexes, so the node replays the chain from genesis. Peer
+/// bans are kept.
+///
+/// Returns the paths that were removed.
+pub fn reset_state(config: &Config) -> Result<Vec<PathBuf>> {
+    let storage = &config.storage;
+    remove_all([
+        config.resolve(&storage.blocks_dir),
+        config.resolve(&storage.evidence_file),
+        config.resolve(&storage.index_dir),
+    ])
+}
+
+/// Like [`reset_state`], but also forgets peer bans.
+pub fn unsafe_reset_all(config: &Config) -> Result<Vec<PathBuf>> {
+    let mut removed = reset_state(config)?;
+    removed.extend(remove_all([config.resolve(&config.storage.ban_file)])?);
+    Ok(removed)
+}
+
+/// Removes each existing file or directory in `paths`, returning those that existed.
+fn remove_all(paths: impl IntoIterator<Item = PathBuf>) -> Result<Vec<PathBuf>> {
+    let mut removed = Vec::new();
+    for path in paths {
+        if path.is_dir() {
+            std::fs::remove_dir_all(&path)?;
+        } else if path.exists() {
+            std::fs::remove_file(&path)?;
+        } else {
+            continue;
+        }
+        removed.push(path);
+    }
+    Ok(removed)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::collections::HashSet;
+    use std::net::SocketAddr;
+    use std::sync::Arc;
+    use std::time::Duration;
+
+    use crate::consensus::reactor::run_gossip_loop;
+    use crate::consensus::{run_consensus_loop, ConsensusState};
+    use crate::p2p::memory::MemoryNetwork;
+    use crate::p2p::peer::PeerManager;
+    use crate::p2p::score::ScoreConfig;
+    use crate::p2p::start_outbound_connections;
+    use crate::p2p::transport::accept_loop;
+
+    #[test]
+    fn init_is_idempotent_and_resets_keep_identity() {
+        let home = std::env::temp_dir().join(format!("tmlike-home-{}", std::process::id()));
+        let config = Config {
+            home: home.clone(),
+            ..Config::default()
+        };
+
+        let first = init(&config, "test-chain").unwrap();
+        assert_eq!(first.created.len(), 3);
+        let genesis = Genesis::load(config.genesis_file()).unwrap();
+        assert_eq!(genesis.validators[0].id, first.node_id);
+
+        let second = init(&config, "other-chain").unwrap();
+        assert!(second.created.is_empty());
+        assert_eq!(second.node_id, first.node_id);
+
+        std::fs::create_dir_all(config.resolve(&config.storage.blocks_dir)).unwrap();
+        std::fs::write(config.resolve(&config.storage.ban_file), "[]").unwrap();
+        assert_eq!(reset_state(&config).unwrap().len(), 1);
+        assert!(config.resolve(&config.storage.ban_file).exists());
+        assert_eq!(unsafe_reset_all(&config).unwrap().len(), 1);
+        assert!(config.node_key_file().exists() && config.genesis_file().exists());
+
+        std::fs::remove_dir_all(&home).unwrap();
+    }
+
+    #[test]
+    fn testnet_nodes_share_genesis_and_know_each_other() {
+        let dir = std::env::temp_dir().join(format!("tmlike-testnet-{}", std::process::id()));
+        let nodes = testnet(&dir, 3, "test-chain", 36656, &Config::default()).unwrap();
+
+        let genesis = Genesis::load(nodes[0].home.join(crate::config::GENESIS_FILE)).unwrap();
+        assert_eq!(genesis.validators.len(), 3);
+        for (i, node) in nodes.iter().enumerate() {
+            let config = Config::load(&node.home, Vec::new()).unwrap();
+            assert_eq!(config.p2p.listen_addr, format!("127.0.0.1:{}", 36656 + 2 * i));
+            assert_eq!(config.rpc.listen_addr, format!("127.0.0.1:{}", 36657 + 2 * i));
+            assert_eq!(config.p2p.persistent_peers.len(), 2);
+            assert_eq!(config.p2p.unconditional_peers, ["127.0.0.1"]);
+            assert!(!config.p2p.persistent_peers.contains(&node.listen_addr));
+            assert_eq!(Genesis::load(config.genesis_file()).unwrap().hash(), genesis.hash());
+            let key = NodeKey::load(config.node_key_file()).unwrap();
+            assert_eq!(key.node_id(), node.node_id);
+            assert!(genesis.validators.iter().any(|v| v.id == node.node_id && v.pub_key == key.public_key()));
+        }
+
+        assert!(testnet(&dir, 3, "test-chain", 36656, &Config::default()).is_err());
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    /// Runs every node of a generated testnet in this process, over an
+    /// in-memory network at the nodes' configured addresses.
+    ///
+    /// Nodes don't sync blocks they missed, so a node whose rounds drift from
+    /// the others' can be left behind for good; catching up is out of scope
+    /// here. The test therefore asks for a +2/3 quorum of the nodes to reach
+    /// height 2, and for every node to agree on the blocks it did commit.
+    #[tokio::test]
+    async fn testnet_nodes_commit_blocks_together() {
+        let dir = std::env::temp_dir().join(format!("tmlike-testnet-run-{}", std::process::id()));
+        let homes = testnet(&dir, 4, "test-chain", 46656, &Config::default()).unwrap();
+        let network = MemoryNetwork::new();
+
+        let mut nodes = Vec::new();
+        for home in &homes {
+            let config = Config::load(&home.home, Vec::new()).unwrap();
+            let key = NodeKey::load(config.node_key_file()).unwrap();
+            let addr: SocketAddr = config.p2p.listen_addr.parse().unwrap();
+            let peers = PeerManager::with_config(config.connection_limits(), ScoreConfig::default());
+            let mut cs = ConsensusState::with_peer_manager(key.node_id(), addr.to_string(), peers);
+            cs.transport = Arc::new(network.transport(addr));
+            cs.set_signing_key(key);
+            cs.init_chain(&Genesis::load(config.genesis_file()).unwrap()).unwrap();
+            cs.spawn(accept_loop(cs.clone(), addr));
+            nodes.push((cs, config));
+        }
+        for (cs, config) in &nodes {
+            let persistent_peers = config.p2p.persistent_peers.iter().map(String::as_str).collect();
+            start_outbound_connections(cs.clone(), Vec::new(), persistent_peers, config.reconnect_config()).await;
+        }
+        let connected = tokio::time::timeout(Duration::from_secs(10), async {
+            while nodes.iter().any(|(cs, _)| cs.peer_manager().get_all_peers().len() < homes.len() - 1) {
+                tokio::time::sleep(Duration::from_millis(10)).await;
+            }
+        })
+        .await;
+        assert!(connected.is_ok(), "every node connects to every other");
+        for (cs, _) in &nodes {
+            cs.spawn(run_gossip_loop(cs.clone()));
+            cs.spawn(run_consensus_loop(cs.clone(), Duration::from_millis(200)));
+        }
+
+        let height = |cs: &ConsensusState| cs.inspect(|core| core.block_store.height());
+        let reached = tokio::time::timeout(Duration::from_secs(20), async {
+            while nodes.iter().filter(|(cs, _)| height(cs) >= 2).count() < 3 {
+                tokio::time::sleep(Duration::from_millis(20)).await;
+            }
+        })
+        .await;
+        let heights: Vec<u64> = nodes.iter().map(|(cs, _)| height(cs)).collect();
+        for (cs, _) in &nodes {
+            cs.shutdown(Duration::from_secs(1)).await;
+        }
+        std::fs::remove_dir_all(&dir).unwrap();
+        assert!(reached.is_ok(), "3 of 4 nodes reach height 2, got {:?}", heights);
+
+        for h in 1..=2 {
+            let hashes: HashSet<String> = nodes
+                .iter()
+                .filter_map(|(cs, _)| cs.inspect(|core| core.block_store.load_block(h).map(|b| b.hash())))
+                .collect();
+            assert_eq!(hashes.len(), 1, "nodes disagree on the block at height {}", h);
+        }
+    }
+}
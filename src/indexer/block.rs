@@ -0,0 +1,90 @@
+//! The block event index.
+//!
+//! Keys:
+//! - `block.height/<height>/<height>`;
+//! - `<type>.<key>/<value>/<height>`, for every attribute of the block's
+//!   events (those the application returned for the block as a whole).
+//!
+//! Values are empty: the position is all a search needs.
+
+use anyhow::Result;
+
+use crate::app::Event;
+use crate::events::query::{Condition, Operator};
+use crate::events::{Query, EVENT_TYPE_KEY};
+
+use super::{intersect, KvStore};
+
+/// Committed heights, by the events of their blocks.
+#[derive(Debug, Default)]
+pub struct BlockIndexer {
+    store: KvStore,
+}
+
+impl BlockIndexer {
+    /// Creates an index kept in `store`.
+    pub fn new(store: KvStore) -> Self {
+        Self { store }
+    }
+
+    /// Syncs the index to disk.
+    pub fn flush(&self) -> Result<()> {
+        self.store.flush()
+    }
+
+    /// Indexes the events of the block committed at `height`.
+    pub fn index(&mut self, height: u64, events: &[Event]) -> Result<()> {
+        self.store.set(format!("block.height/{}/{}", height, height), String::new())?;
+        for event in events {
+            for attr in &event.attributes {
+                let key = format!("{}.{}/{}/{}", event.kind, attr.key, attr.value, height);
+                self.store.set(key, String::new())?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Returns the heights whose block matches `query`, in ascending order.
+    ///
+    /// `tm.event` conditions are ignored, and a query with no other
+    /// condition matches every indexed height.
+    pub fn search(&self, query: &Query) -> Vec<u64> {
+        let mut conditions: Vec<_> = query
+            .conditions()
+            .iter()
+            .filter(|c| c.key != EVENT_TYPE_KEY)
+            .collect();
+        let all = Condition {
+            key: "block.height".into(),
+            op: Operator::Exists,
+            operand: None,
+        };
+        if conditions.is_empty() {
+            conditions.push(&all);
+        }
+        intersect(&self.store, &conditions, 1)
+            .into_iter()
+            .map(|position| position[0])
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn searches_heights_by_block_events() {
+        let mut indexer = BlockIndexer::default();
+        for height in 1..=5 {
+            let proposer = if height % 2 == 0 { "a" } else { "b" };
+            indexer
+                .index(height, &[Event::new("rewards", &[("proposer", proposer)])])
+                .unwrap();
+        }
+        let search = |q: &str| indexer.search(&q.parse().unwrap());
+        assert_eq!(search("rewards.proposer='a'"), [2, 4]);
+        assert_eq!(search("tm.event='NewBlock' AND block.height > 2 AND rewards.proposer='b'"), [3, 5]);
+        assert_eq!(search("tm.event='NewBlock'"), [1, 2, 3, 4, 5]);
+    }
+}
@@ -0,0 +1,162 @@
+//! Indexes of committed transactions and blocks, searchable by the events
+//! the application attached to them.
+//!
+//! Both indexes are kept in a [`KvStore`]: a sorted map of string keys,
+//! persisted as an append-only log of JSON `[key, value]` lines. Events are
+//! indexed under `<composite key>/<value>/<position>`, where the position is
+//! `<height>/<index>` for a transaction and `<height>` for a block, so a
+//! condition of a [`Query`](crate::events::Query) is answered by scanning the
+//! keys under `<composite key>/` (or `<composite key>/<value>/` for string
+//! equality).
+
+use std::collections::{BTreeMap, BTreeSet};
+use std::fs::{File, OpenOptions};
+use std::io::{BufRead, BufReader, Write};
+use std::ops::Bound;
+use std::path::{Path, PathBuf};
+
+use anyhow::{Context, Result};
+
+use crate::events::query::{Condition, Operand, Operator};
+
+pub mod block;
+pub mod tx;
+
+pub use block::BlockIndexer;
+pub use tx::{TxIndexer, TxRecord};
+
+/// A sorted string map, optionally persisted to an append-only log.
+#[derive(Debug, Default)]
+pub struct KvStore {
+    entries: BTreeMap<String, String>,
+    /// Where new entries are appended. `None` keeps them in memory only.
+    log: Option<File>,
+}
+
+impl KvStore {
+    /// Creates an empty, in-memory store.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Opens the store logged at `path`, replaying the entries already there.
+    pub fn open(path: &Path) -> Result<Self> {
+        let mut entries = BTreeMap::new();
+        if path.exists() {
+            let file = File::open(path)?;
+            for (n, line) in BufReader::new(file).lines().enumerate() {
+                let (key, value): (String, String) = serde_json::from_str(&line?)
+                    .with_context(|| format!("{} line {}", path.display(), n + 1))?;
+                entries.insert(key, value);
+            }
+        }
+        let log = OpenOptions::new().create(true).append(true).open(path)?;
+        Ok(Self {
+            entries,
+            log: Some(log),
+        })
+    }
+
+    /// Returns the value of `key`.
+    pub fn get(&self, key: &str) -> Option<&str> {
+        self.entries.get(key).map(String::as_str)
+    }
+
+    /// Sets `key` to `value`.
+    pub fn set(&mut self, key: String, value: String) -> Result<()> {
+        if let Some(log) = &mut self.log {
+            writeln!(log, "{}", serde_json::to_string(&(&key, &value))?)?;
+        }
+        self.entries.insert(key, value);
+        Ok(())
+    }
+
+    /// Syncs the log to disk.
+    pub fn flush(&self) -> Result<()> {
+        if let Some(log) = &self.log {
+            log.sync_all()?;
+        }
+        Ok(())
+    }
+
+    /// Returns the entries whose key starts with `prefix`, in key order.
+    pub fn scan_prefix<'a>(&'a self, prefix: &'a str) -> impl Iterator<Item = (&'a str, &'a str)> + 'a {
+        self.entries
+            .range::<str, _>((Bound::Included(prefix), Bound::Unbounded))
+            .take_while(move |(k, _)| k.starts_with(prefix))
+            .map(|(k, v)| (k.as_str(), v.as_str()))
+    }
+
+    /// Returns the positions (the last `parts` `/`-separated numbers of the
+    /// key) of the event entries of `condition`'s key whose value satisfies it.
+    fn positions(&self, condition: &Condition, parts: usize) -> BTreeSet<Vec<u64>> {
+        let prefix = match (&condition.op, &condition.operand) {
+            (Operator::Eq, Some(Operand::String(value))) => format!("{}/{}/", condition.key, value),
+            _ => format!("{}/", condition.key),
+        };
+        self.scan_prefix(&prefix)
+            .filter_map(|(key, _)| {
+                let rest = &key[condition.key.len() + 1..];
+                let mut fields = rest.rsplitn(parts + 1, '/');
+                let mut position: Vec<u64> = fields
+                    .by_ref()
+                    .take(parts)
+                    .map(|p| p.parse().ok())
+                    .collect::<Option<_>>()?;
+                let value = fields.next()?;
+                if !condition.matches_value(value) {
+                    return None;
+                }
+                position.reverse();
+                Some(position)
+            })
+            .collect()
+    }
+}
+
+/// Returns the positions satisfying every condition in `conditions` (an
+/// empty list matches nothing).
+fn intersect(store: &KvStore, conditions: &[&Condition], parts: usize) -> BTreeSet<Vec<u64>> {
+    let mut matched: Option<BTreeSet<Vec<u64>>> = None;
+    for condition in conditions {
+        let found = store.positions(condition, parts);
+        matched = Some(match matched {
+            None => found,
+            Some(m) => m.intersection(&found).cloned().collect(),
+        });
+        if matched.as_ref().is_some_and(BTreeSet::is_empty) {
+            break;
+        }
+    }
+    matched.unwrap_or_default()
+}
+
+/// The transaction and block indexes of a node.
+#[derive(Debug, Default)]
+pub struct Indexer {
+    pub txs: TxIndexer,
+    pub blocks: BlockIndexer,
+}
+
+impl Indexer {
+    /// Creates empty, in-memory indexes.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Opens the indexes stored in `dir` (`tx.log` and `block.log`),
+    /// creating the directory if needed.
+    pub fn open(dir: PathBuf) -> Result<Self> {
+        std::fs::create_dir_all(&dir)?;
+        Ok(Self {
+            txs: TxIndexer::new(KvStore::open(&dir.join("tx.log"))?),
+            blocks: BlockIndexer::new(KvStore::open(&dir.join("block.log"))?),
+        })
+    }
+
+    /// Syncs both indexes to disk.
+    pub fn flush(&self) -> Result<()> {
+        self.txs.flush()?;
+        self.blocks.flush()
+    }
+}
@@ -0,0 +1,152 @@
+//! The transaction index.
+//!
+//! Keys:
+//! - `tx/<hash>`: the [`TxRecord`], as JSON;
+//! - `tx.height/<height>/<height>/<index>`: the hash;
+//! - `<type>.<key>/<value>/<height>/<index>`: the hash, for every attribute
+//!   of the events in the transaction's result.
+
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+
+use crate::app::ExecTxResult;
+use crate::consensus::block::{tx_hash, Tx};
+use crate::events::query::{Condition, Operand, Operator};
+use crate::events::{Query, EVENT_TYPE_KEY};
+
+use super::{intersect, KvStore};
+
+/// A committed transaction with its result.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct TxRecord {
+    pub height: u64,
+    /// Position of the transaction in its block.
+    pub index: usize,
+    pub tx: Tx,
+    pub result: ExecTxResult,
+}
+
+/// Committed transactions, by hash and by event attributes.
+#[derive(Debug, Default)]
+pub struct TxIndexer {
+    store: KvStore,
+}
+
+impl TxIndexer {
+    /// Creates an index kept in `store`.
+    pub fn new(store: KvStore) -> Self {
+        Self { store }
+    }
+
+    /// Syncs the index to disk.
+    pub fn flush(&self) -> Result<()> {
+        self.store.flush()
+    }
+
+    /// Indexes a committed transaction.
+    pub fn index(&mut self, record: &TxRecord) -> Result<()> {
+        let hash = tx_hash(&record.tx);
+        let position = format!("{}/{}", record.height, record.index);
+        self.store.set(format!("tx/{}", hash), serde_json::to_string(record)?)?;
+        self.store
+            .set(format!("tx.height/{}/{}", record.height, position), hash.clone())?;
+        for event in &record.result.events {
+            for attr in &event.attributes {
+                let key = format!("{}.{}/{}/{}", event.kind, attr.key, attr.value, position);
+                self.store.set(key, hash.clone())?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Returns the transaction with `hash`.
+    pub fn get(&self, hash: &str) -> Option<TxRecord> {
+        serde_json::from_str(self.store.get(&format!("tx/{}", hash))?).ok()
+    }
+
+    /// Returns the transactions matching `query`, ordered by height and index.
+    ///
+    /// `tm.event` conditions are ignored (every indexed event is a `Tx`), and
+    /// a query with no other condition matches every transaction.
+    pub fn search(&self, query: &Query) -> Vec<TxRecord> {
+        let mut conditions: Vec<_> = query
+            .conditions()
+            .iter()
+            .filter(|c| c.key != EVENT_TYPE_KEY)
+            .collect();
+        // A hash identifies a single transaction; no need to scan.
+        if let Some(by_hash) = conditions.iter().position(|c| c.key == "tx.hash" && c.op == Operator::Eq) {
+            let Some(Operand::String(hash)) = &conditions.remove(by_hash).operand else {
+                return Vec::new();
+            };
+            let Some(record) = self.get(hash) else {
+                return Vec::new();
+            };
+            let position = vec![record.height, record.index as u64];
+            if conditions.is_empty() || intersect(&self.store, &conditions, 2).contains(&position) {
+                return vec![record];
+            }
+            return Vec::new();
+        }
+        let all = Condition {
+            key: "tx.height".into(),
+            op: Operator::Exists,
+            operand: None,
+        };
+        if conditions.is_empty() {
+            conditions.push(&all);
+        }
+        intersect(&self.store, &conditions, 2)
+            .into_iter()
+            .filter_map(|position| {
+                let key = format!("tx.height/{}/{}/{}", position[0], position[0], position[1]);
+                self.get(self.store.get(&key)?)
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::app::Event;
+
+    fn record(height: u64, index: usize, sender: &str, amount: u64) -> TxRecord {
+        TxRecord {
+            height,
+            index,
+            tx: format!("{}-{}", height, index).into_bytes(),
+            result: ExecTxResult {
+                events: vec![Event::new("transfer", &[("sender", sender), ("amount", &amount.to_string())])],
+                ..ExecTxResult::default()
+            },
+        }
+    }
+
+    #[test]
+    fn searches_by_hash_equality_and_ranges() {
+        let path = std::env::temp_dir().join(format!("tmlike-txindex-{}.log", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        let mut indexer = TxIndexer::new(KvStore::open(&path).unwrap());
+        let records = [record(1, 0, "x", 5), record(1, 1, "y", 50), record(2, 0, "x", 500), record(3, 0, "x/y", 1)];
+        for r in &records {
+            indexer.index(r).unwrap();
+        }
+
+        let search = |q: &str| -> Vec<(u64, usize)> {
+            indexer.search(&q.parse().unwrap()).iter().map(|r| (r.height, r.index)).collect()
+        };
+        assert_eq!(search("tm.event='Tx' AND transfer.sender='x'"), [(1, 0), (2, 0)]);
+        assert_eq!(search("transfer.amount >= 50 AND tx.height <= 2"), [(1, 1), (2, 0)]);
+        assert_eq!(search("transfer.sender CONTAINS '/'"), [(3, 0)]);
+        assert_eq!(search("tm.event='Tx'").len(), 4);
+        let hash = tx_hash(&records[2].tx);
+        assert_eq!(search(&format!("tx.hash='{}' AND transfer.amount > 100", hash)), [(2, 0)]);
+        assert!(search(&format!("tx.hash='{}' AND transfer.amount < 100", hash)).is_empty());
+
+        // The log is replayed on open.
+        let reopened = TxIndexer::new(KvStore::open(&path).unwrap());
+        assert_eq!(reopened.get(&hash), Some(records[2].clone()));
+        std::fs::remove_file(&path).unwrap();
+    }
+}
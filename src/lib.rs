@@ -0,0 +1,15 @@
+//! A simplified Tendermint-like node: round-based consensus on top of a
+//! small TCP peer-to-peer layer, driving a pluggable application.
+
+pub mod app;
+pub mod config;
+pub mod consensus;
+pub mod events;
+pub mod genesis;
+pub mod home;
+pub mod indexer;
+pub mod logging;
+pub mod mempool;
+pub mod metrics;
+pub mod p2p;
+pub mod rpc;
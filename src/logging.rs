@@ -0,0 +1,86 @@
+//! Log and trace output.
+//!
+//! Logs are written to stdout as text or JSON lines (`logging.format`),
+//! filtered per module by `logging.level`. Consensus logs carry the `height`
+//! and `round` spans they happen in, and connection logs the `peer` span of
+//! their connection; in JSON, span fields are included in every line.
+//!
+//! With `instrumentation.otlp_endpoint` set, spans are also exported over
+//! OTLP/gRPC to an OpenTelemetry collector.
+
+use anyhow::Result;
+use opentelemetry::trace::TracerProvider;
+use opentelemetry::KeyValue;
+use opentelemetry_otlp::{SpanExporter, WithExportConfig};
+use opentelemetry_sdk::trace::SdkTracerProvider;
+use opentelemetry_sdk::Resource;
+use tracing::warn;
+use tracing_subscriber::layer::SubscriberExt;
+use tracing_subscriber::util::SubscriberInitExt;
+use tracing_subscriber::{fmt, Layer};
+
+use crate::config::{Config, LogFormat};
+
+/// The service name spans are exported under.
+const SERVICE_NAME: &str = "tendermint-like";
+
+/// Keeps span export running. Dropping it flushes the spans not yet exported.
+#[derive(Debug)]
+pub struct LogGuard {
+    provider: Option<SdkTracerProvider>,
+}
+
+impl Drop for LogGuard {
+    fn drop(&mut self) {
+        if let Some(provider) = self.provider.take() {
+            if let Err(e) = provider.shutdown() {
+                warn!("Failed to flush spans: {}", e);
+            }
+        }
+    }
+}
+
+/// Installs the global subscriber described by `config`.
+///
+/// Must be called from within a Tokio runtime if spans are exported.
+pub fn init(config: &Config) -> Result<LogGuard> {
+    let filter = config.log_filter()?;
+    let output = match config.logging.format {
+        LogFormat::Text => fmt::layer().boxed(),
+        LogFormat::Json => fmt::layer()
+            .json()
+            .with_current_span(true)
+            .with_span_list(true)
+            .boxed(),
+    };
+
+    let endpoint = &config.instrumentation.otlp_endpoint;
+    let provider = if endpoint.is_empty() {
+        None
+    } else {
+        let exporter = SpanExporter::builder()
+            .with_tonic()
+            .with_endpoint(endpoint)
+            .build()?;
+        let resource = Resource::builder()
+            .with_service_name(SERVICE_NAME)
+            .with_attribute(KeyValue::new("service.instance.id", config.moniker.clone()))
+            .build();
+        Some(
+            SdkTracerProvider::builder()
+                .with_batch_exporter(exporter)
+                .with_resource(resource)
+                .build(),
+        )
+    };
+    let export = provider
+        .as_ref()
+        .map(|p| tracing_opentelemetry::layer().with_tracer(p.tracer(SERVICE_NAME)));
+
+    tracing_subscriber::registry()
+        .with(filter)
+        .with(output)
+        .with(export)
+        .try_init()?;
+    Ok(LogGuard { provider })
+}
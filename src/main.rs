@@ -1,72 +1,364 @@
-use anyhow::Result;
-use tracing::{info, Level};
-use tracing_subscriber::FmtSubscriber;
-use uuid::Uuid;
+use std::path::PathBuf;
+use std::sync::Arc;
 
-mod p2p;
-mod consensus;
+use anyhow::{bail, Result};
+use clap::{Args, Parser, Subcommand};
+use tracing::{error, info};
 
-use p2p::{start_listening, start_outbound_connections};
-use consensus::{ConsensusState, run_consensus_loop};
+use tendermint_like::config::{Config, TransportKind};
+use tendermint_like::genesis::Genesis;
+use tendermint_like::home;
+use tendermint_like::logging;
+use tendermint_like::metrics::{self, Metrics};
+use tendermint_like::p2p::key::NodeKey;
+use tendermint_like::p2p::peer::PeerManager;
+use tendermint_like::p2p::quic::QuicTransport;
+use tendermint_like::p2p::{start_listening, start_outbound_connections};
+use tendermint_like::rpc::{self, RpcContext};
+use tendermint_like::consensus::reactor::run_gossip_loop;
+use tendermint_like::consensus::{ConsensusState, run_consensus_loop};
 
-/// Main entry point of our Tendermint-like node.
+/// A Tendermint-like consensus node.
+#[derive(Debug, Parser)]
+#[command(version, about = "A Tendermint-like consensus node")]
+struct Cli {
+    /// Directory holding `config/` and `data/`.
+    #[arg(long, global = true, env = "TMLIKE_HOME", default_value_os_t = default_home())]
+    home: PathBuf,
+    #[command(subcommand)]
+    command: Command,
+}
+
+#[derive(Debug, Subcommand)]
+enum Command {
+    /// Create the config file, node key and a single-validator genesis in the home directory.
+    Init {
+        /// Chain ID of the new genesis.
+        #[arg(long, default_value = "test-chain")]
+        chain_id: String,
+        #[command(flatten)]
+        overrides: ConfigOverrides,
+    },
+    /// Create the home directories of a local network of validators.
+    Testnet {
+        /// Number of validators.
+        #[arg(long, default_value_t = 4)]
+        validators: usize,
+        /// Directory the `node<i>` home directories are created in.
+        #[arg(long, default_value = "./testnet")]
+        output_dir: PathBuf,
+        /// Chain ID of the shared genesis.
+        #[arg(long, default_value = "test-chain")]
+        chain_id: String,
+        /// P2P port of `node0`; node `i` uses this plus `2i` for P2P and the next port for RPC.
+        #[arg(long, default_value_t = 26656)]
+        starting_port: u16,
+    },
+    /// Run the node.
+    Start {
+        #[command(flatten)]
+        overrides: ConfigOverrides,
+    },
+    /// Print this node's ID.
+    ShowNodeId,
+    /// Print this node's validator ID and public key, as listed in a genesis.
+    ShowValidator,
+    /// Delete the block store, evidence and indexes, so the node replays the chain from genesis.
+    ResetState,
+    /// Delete all data, including peer bans. Keys, config and genesis are kept.
+    UnsafeResetAll,
+}
+
+/// Flags that override the matching field of `config.toml` and its
+/// `TMLIKE_*` environment variable.
+#[derive(Debug, Default, Args)]
+struct ConfigOverrides {
+    /// Human-readable node name (`moniker`).
+    #[arg(long)]
+    moniker: Option<String>,
+    /// Address to listen on for peers (`p2p.listen_addr`).
+    #[arg(long)]
+    listen_addr: Option<String>,
+    /// Comma-separated peers to dial at startup (`p2p.peers`).
+    #[arg(long, value_delimiter = ',')]
+    peers: Option<Vec<String>>,
+    /// Comma-separated peers to stay connected to (`p2p.persistent_peers`).
+    #[arg(long, value_delimiter = ',')]
+    persistent_peers: Option<Vec<String>>,
+    /// Address to serve RPC on, empty to disable (`rpc.listen_addr`).
+    #[arg(long)]
+    rpc_addr: Option<String>,
+    /// Log level, optionally with per-module levels (`logging.level`).
+    #[arg(long)]
+    log_level: Option<String>,
+}
+
+impl ConfigOverrides {
+    /// Applies the flags that were given on top of `config`.
+    fn apply(self, config: &mut Config) {
+        if let Some(v) = self.moniker {
+            config.moniker = v;
+        }
+        if let Some(v) = self.listen_addr {
+            config.p2p.listen_addr = v;
+        }
+        if let Some(v) = self.peers {
+            config.p2p.peers = v;
+        }
+        if let Some(v) = self.persistent_peers {
+            config.p2p.persistent_peers = v;
+        }
+        if let Some(v) = self.rpc_addr {
+            config.rpc.listen_addr = v;
+        }
+        if let Some(v) = self.log_level {
+            config.logging.level = v;
+        }
+    }
+}
+
+/// Loads the configuration of `home`: defaults, then `config.toml`, then
+/// `TMLIKE_*` variables, then `overrides`.
+fn load_config(home: PathBuf, overrides: ConfigOverrides) -> Result<Config> {
+    let mut config = Config::load(home, std::env::vars())?;
+    overrides.apply(&mut config);
+    config.validate()?;
+    Ok(config)
+}
+
+/// Loads the node key, pointing at `init` if there is none.
+fn load_node_key(config: &Config) -> Result<NodeKey> {
+    let path = config.node_key_file();
+    if !path.exists() {
+        bail!("{} not found; run `init` first", path.display());
+    }
+    NodeKey::load(path)
+}
+
+/// `$HOME/.tendermint-like`, or `.tendermint-like` in the working directory if `HOME` is unset.
+fn default_home() -> PathBuf {
+    std::env::var_os("HOME")
+        .map(PathBuf::from)
+        .unwrap_or_default()
+        .join(".tendermint-like")
+}
+
+/// Main entry point of our Tendermint-like node: runs the requested subcommand.
+fn main() -> Result<()> {
+    let cli = Cli::parse();
+    match cli.command {
+        Command::Init { chain_id, overrides } => {
+            let config = load_config(cli.home, overrides)?;
+            let initialized = home::init(&config, &chain_id)?;
+            for path in &initialized.created {
+                println!("Created {}", path.display());
+            }
+            println!("Node ID: {}", initialized.node_id);
+        }
+        Command::Testnet { validators, output_dir, chain_id, starting_port } => {
+            // Settings other than addresses come from the usual defaults and `TMLIKE_*` variables.
+            let template = Config::load(output_dir.clone(), std::env::vars())?;
+            let nodes = home::testnet(&output_dir, validators, &chain_id, starting_port, &template)?;
+            for node in &nodes {
+                println!("{} {} {}", node.node_id, node.listen_addr, node.home.display());
+            }
+            let exe = std::env::args().next().unwrap_or_else(|| "tendermint-like".into());
+            println!("Start each node with `{} --home <dir> start`.", exe);
+        }
+        Command::Start { overrides } => {
+            let config = load_config(cli.home, overrides)?;
+            tokio::runtime::Runtime::new()?.block_on(start(config))?;
+        }
+        Command::ShowNodeId => {
+            let config = load_config(cli.home, ConfigOverrides::default())?;
+            println!("{}", load_node_key(&config)?.node_id());
+        }
+        Command::ShowValidator => {
+            let config = load_config(cli.home, ConfigOverrides::default())?;
+            let key = load_node_key(&config)?;
+            let validator = serde_json::json!({
+                "id": key.node_id(),
+                "pub_key": hex::encode(key.public_key().as_bytes()),
+            });
+            println!("{}", serde_json::to_string_pretty(&validator)?);
+        }
+        Command::ResetState => {
+            let config = load_config(cli.home, ConfigOverrides::default())?;
+            for path in home::reset_state(&config)? {
+                println!("Removed {}", path.display());
+            }
+        }
+        Command::UnsafeResetAll => {
+            let config = load_config(cli.home, ConfigOverrides::default())?;
+            for path in home::unsafe_reset_all(&config)? {
+                println!("Removed {}", path.display());
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Runs the node described by `config` (the `start` command).
 ///
-/// This sets up logging, initializes the consensus state,
-/// spawns tasks for P2P inbound/outbound connections,
-/// and runs the main consensus loop.
-#[tokio::main]
-async fn main() -> Result<()> {
-    // Initialize logging (tracing)
-    let subscriber = FmtSubscriber::builder()
-        .with_max_level(Level::DEBUG)
-        .finish();
-    tracing::subscriber::set_global_default(subscriber)?;
-
-    // Generate a unique node ID for demonstration:
-    let node_id = Uuid::new_v4().to_string();
-    // The TCP address on which this node will listen for inbound connections
-    let listen_addr = "127.0.0.1:7000";
-
-    // Known peers. In a real system, these might come from config files, seeds,
-    // or peer discovery. For demonstration, keep it empty or specify known addresses.
-    let known_peers = vec![
-        // e.g. "127.0.0.1:7001", "127.0.0.1:7002", ...
-    ];
+/// This sets up logging, initializes the consensus state from the home directory,
+/// spawns tasks for P2P inbound/outbound connections and the RPC server,
+/// and runs the main consensus loop until SIGINT or SIGTERM, when every task is
+/// stopped and the stores are flushed (see [`ConsensusState::shutdown`]).
+async fn start(config: Config) -> Result<()> {
+    // Initialize logging (tracing); spans not yet exported are flushed when `_log_guard` drops
+    let _log_guard = logging::init(&config)?;
+    info!("Using home directory {}", config.home.display());
+
+    // The node ID is derived from the node key, so it stays the same across restarts.
+    let node_key = load_node_key(&config)?;
+    let node_id = node_key.node_id();
+    let genesis_path = config.genesis_file();
+    if !genesis_path.exists() {
+        bail!("{} not found; run `init` first", genesis_path.display());
+    }
+    let listen_addr = config.p2p.listen_addr.clone();
+
+    for path in [&config.storage.evidence_file, &config.storage.ban_file, &config.storage.blocks_dir] {
+        if let Some(dir) = config.resolve(path).parent() {
+            std::fs::create_dir_all(dir)?;
+        }
+    }
+
+    // Connection limits and peer scoring; bans are persisted so misbehaving peers stay out across restarts.
+    let peer_manager = PeerManager::with_config(config.connection_limits(), config.score_config());
+    let active_bans = peer_manager.load_bans()?;
+    if active_bans > 0 {
+        info!("Loaded {} active peer bans", active_bans);
+    }
 
     // Create the main consensus state object
-    let consensus_state = ConsensusState::new(node_id.clone(), listen_addr.to_string());
+    let mut consensus_state = ConsensusState::with_peer_manager(
+        node_id.clone(),
+        listen_addr.clone(),
+        peer_manager,
+    );
+    consensus_state.set_metrics(Metrics::new(&config.instrumentation.namespace)?);
+    // Committed blocks; consensus resumes at the height after the latest one.
+    let last_height = consensus_state.open_block_store(config.resolve(&config.storage.blocks_dir))?;
+    info!("Block store at height {}", last_height);
+    // Committed transactions and block events, for `tx_search` and `block_search`.
+    consensus_state.open_indexer(config.resolve(&config.storage.index_dir))?;
+    // Votes are signed with the node key, so we are the validator `init` put in the genesis.
+    consensus_state.set_signing_key(node_key.clone());
+    // The genesis file defines the chain.
+    let genesis = Genesis::load(&genesis_path)?;
+    consensus_state.init_chain(&genesis)?;
+    info!("Chain {} (genesis {})", genesis.chain_id, consensus_state.genesis_hash);
+    // Pending and committed evidence is persisted so it isn't lost or re-included after a restart.
+    let pending_evidence = consensus_state.load_evidence(config.resolve(&config.storage.evidence_file))?;
+    if pending_evidence > 0 {
+        info!("Loaded {} pending evidence items", pending_evidence);
+    }
+    consensus_state.set_mempool_config(config.mempool.clone());
+    // `json` makes outgoing traffic human-readable while debugging.
+    consensus_state.wire_format = config.p2p.wire_format;
+    // QUIC connections are authenticated with the node key.
+    if config.p2p.transport == TransportKind::Quic {
+        consensus_state.transport = Arc::new(QuicTransport::new(&node_key)?);
+    }
 
-    info!("Node {} starting up on {}...", node_id, listen_addr);
+    info!("Node {} ({}) starting up on {}...", config.moniker, node_id, listen_addr);
 
-    // Spawn a task to listen for inbound P2P connections
-    tokio::spawn({
+    // Spawn a task to listen for inbound P2P connections. Tasks that touch the node's state
+    // are spawned through the consensus state, so that shutdown waits for them before flushing.
+    consensus_state.spawn({
         let cs = consensus_state.clone();
         async move {
-            if let Err(e) = start_listening(cs, listen_addr).await {
-                eprintln!("P2P listener error: {:?}", e);
+            if let Err(e) = start_listening(cs, &listen_addr).await {
+                error!("P2P listener error: {:?}", e);
             }
         }
     });
 
     // Spawn a task to attempt outbound connections to known peers
-    tokio::spawn({
+    consensus_state.spawn({
         let cs = consensus_state.clone();
+        let peers = config.p2p.peers.clone();
+        let persistent_peers = config.p2p.persistent_peers.clone();
+        let reconnect = config.reconnect_config();
         async move {
-            start_outbound_connections(cs, known_peers).await;
+            start_outbound_connections(
+                cs,
+                peers.iter().map(String::as_str).collect(),
+                persistent_peers.iter().map(String::as_str).collect(),
+                reconnect,
+            )
+            .await;
         }
     });
 
+    // Spawn the RPC server, unless disabled
+    if !config.rpc.listen_addr.is_empty() {
+        let ctx = RpcContext {
+            cs: consensus_state.clone(),
+            moniker: config.moniker.clone(),
+            chain_id: genesis.chain_id.clone(),
+            config: config.rpc.clone(),
+        };
+        let rpc_addr = config.rpc.listen_addr.clone();
+        tokio::spawn(async move {
+            if let Err(e) = rpc::serve(ctx, &rpc_addr).await {
+                error!("RPC server error: {:?}", e);
+            }
+        });
+    }
+
+    // Spawn the Prometheus metrics server, if enabled
+    if config.instrumentation.prometheus {
+        let metrics = consensus_state.metrics().clone();
+        let metrics_addr = config.instrumentation.prometheus_listen_addr.clone();
+        let shutdown = consensus_state.shutdown_token();
+        tokio::spawn(async move {
+            if let Err(e) = metrics::serve(metrics, &metrics_addr, shutdown).await {
+                error!("Metrics server error: {:?}", e);
+            }
+        });
+    }
+
+    // Spawn the gossip reactor, which keeps peers supplied with proposals, parts and votes
+    consensus_state.spawn(run_gossip_loop(consensus_state.clone()));
+
     // Spawn the main consensus loop
-    tokio::spawn({
+    consensus_state.spawn({
         let cs = consensus_state.clone();
+        let interval = config.block_interval();
         async move {
-            run_consensus_loop(cs).await;
+            run_consensus_loop(cs, interval).await;
         }
     });
 
-    // Keep the main function alive
-    loop {
-        tokio::time::sleep(std::time::Duration::from_secs(3600)).await;
+    // Run until asked to stop (or consensus halts), then stop every task and flush what they left behind
+    let halted = consensus_state.shutdown_token();
+    tokio::select! {
+        result = shutdown_signal() => result?,
+        _ = halted.cancelled() => {}
+    }
+    info!("Shutting down...");
+    consensus_state.shutdown(config.shutdown_timeout()).await;
+    info!("Shutdown complete");
+    if let Some(reason) = consensus_state.inspect(|core| core.halted().map(str::to_owned)) {
+        bail!("consensus halted: {}", reason);
     }
+    Ok(())
 }
 
+/// Waits for SIGINT (Ctrl-C) or, on Unix, SIGTERM.
+async fn shutdown_signal() -> Result<()> {
+    #[cfg(unix)]
+    {
+        use tokio::signal::unix::{signal, SignalKind};
+        let mut terminate = signal(SignalKind::terminate())?;
+        tokio::select! {
+            result = tokio::signal::ctrl_c() => result?,
+            _ = terminate.recv() => {}
+        }
+    }
+    #[cfg(not(unix))]
+    tokio::signal::ctrl_c().await?;
+    Ok(())
+}
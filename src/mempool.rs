@@ -0,0 +1,275 @@
+//! The mempool: transactions waiting to be included in a block.
+//!
+//! A transaction enters the mempool if
+//! - it is no larger than `MempoolConfig::max_tx_bytes`,
+//! - the mempool holds fewer than `MempoolConfig::size` transactions,
+//! - we haven't seen it recently (a cache of the last
+//!   `MempoolConfig::cache_size` transaction hashes drops duplicates, so
+//!   gossip between peers stops at nodes that already have it),
+//! - and `Application::check_tx` accepts it.
+//!
+//! Proposers reap transactions in arrival order. After each block, the
+//! transactions it included are removed and the rest are checked again,
+//! since the block may have made them invalid; those the application now
+//! rejects are dropped (and forgotten by the cache, so they can be resent).
+
+use std::collections::{HashSet, VecDeque};
+use std::fmt;
+
+use crate::app::{Application, CheckTxResponse};
+use crate::config::MempoolConfig;
+use crate::consensus::block::{tx_hash, Tx};
+
+/// Why a transaction was not added to the mempool.
+///
+/// A transaction the application rejects is not an error: `check_tx`
+/// returns the application's response.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum MempoolError {
+    /// The transaction is larger than `max_tx_bytes`.
+    TxTooLarge { size: usize, max: usize },
+    /// The mempool already holds `size` transactions.
+    Full { size: usize },
+    /// The transaction is in the mempool or was seen recently.
+    AlreadySeen,
+}
+
+impl fmt::Display for MempoolError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            MempoolError::TxTooLarge { size, max } => {
+                write!(f, "tx of {} bytes exceeds limit of {} bytes", size, max)
+            }
+            MempoolError::Full { size } => write!(f, "mempool is full ({} txs)", size),
+            MempoolError::AlreadySeen => write!(f, "tx already exists in cache"),
+        }
+    }
+}
+
+impl std::error::Error for MempoolError {}
+
+/// Hashes of recently seen transactions, forgetting the oldest once full.
+#[derive(Debug, Default)]
+struct TxCache {
+    capacity: usize,
+    order: VecDeque<String>,
+    hashes: HashSet<String>,
+}
+
+impl TxCache {
+    /// Remembers `hash`. Returns `false` if it was already known.
+    fn push(&mut self, hash: &str) -> bool {
+        if self.capacity == 0 {
+            return true;
+        }
+        if !self.hashes.insert(hash.to_string()) {
+            return false;
+        }
+        self.order.push_back(hash.to_string());
+        if self.order.len() > self.capacity {
+            if let Some(oldest) = self.order.pop_front() {
+                self.hashes.remove(&oldest);
+            }
+        }
+        true
+    }
+
+    /// Forgets `hash`.
+    fn remove(&mut self, hash: &str) {
+        if self.hashes.remove(hash) {
+            self.order.retain(|h| h != hash);
+        }
+    }
+}
+
+/// Transactions that passed `check_tx`, in arrival order.
+#[derive(Debug)]
+pub struct Mempool {
+    config: MempoolConfig,
+    /// (hash, transaction), oldest first.
+    txs: VecDeque<(String, Tx)>,
+    /// Total size of `txs`, in bytes.
+    bytes: usize,
+    cache: TxCache,
+}
+
+impl Mempool {
+    /// Creates an empty mempool with the given limits.
+    pub fn new(config: MempoolConfig) -> Self {
+        Self {
+            cache: TxCache {
+                capacity: config.cache_size,
+                ..TxCache::default()
+            },
+            config,
+            txs: VecDeque::new(),
+            bytes: 0,
+        }
+    }
+
+    /// Returns the number of transactions held.
+    pub fn len(&self) -> usize {
+        self.txs.len()
+    }
+
+    /// Returns `true` if the mempool holds no transactions.
+    pub fn is_empty(&self) -> bool {
+        self.txs.is_empty()
+    }
+
+    /// Returns the total size of the transactions held, in bytes.
+    pub fn size_bytes(&self) -> usize {
+        self.bytes
+    }
+
+    /// Returns whether the transaction with `hash` is in the mempool.
+    pub fn contains(&self, hash: &str) -> bool {
+        self.txs.iter().any(|(h, _)| h == hash)
+    }
+
+    /// Checks `tx` with the application and, if it accepts, adds it.
+    ///
+    /// Returns the application's response; the transaction was added only if
+    /// it is OK.
+    pub fn check_tx(
+        &mut self,
+        tx: Tx,
+        app: &mut dyn Application,
+    ) -> Result<CheckTxResponse, MempoolError> {
+        if tx.len() > self.config.max_tx_bytes {
+            return Err(MempoolError::TxTooLarge {
+                size: tx.len(),
+                max: self.config.max_tx_bytes,
+            });
+        }
+        if self.txs.len() >= self.config.size {
+            return Err(MempoolError::Full { size: self.txs.len() });
+        }
+        let hash = tx_hash(&tx);
+        if !self.cache.push(&hash) {
+            return Err(MempoolError::AlreadySeen);
+        }
+        let response = app.check_tx(&tx);
+        if response.is_ok() {
+            self.bytes += tx.len();
+            self.txs.push_back((hash, tx));
+        } else {
+            self.cache.remove(&hash);
+        }
+        Ok(response)
+    }
+
+    /// Returns the oldest transactions whose encoding in a block fits in
+    /// `max_bytes`, stopping at the first one that doesn't.
+    pub fn reap(&self, max_bytes: usize) -> Vec<Tx> {
+        let mut total = 0;
+        let mut reaped = Vec::new();
+        for (_, tx) in &self.txs {
+            // Each transaction is length-prefixed in the block encoding.
+            total += tx.len() + 8;
+            if total > max_bytes {
+                break;
+            }
+            reaped.push(tx.clone());
+        }
+        reaped
+    }
+
+    /// Removes the transactions a committed block included and checks the
+    /// rest again, dropping those the application now rejects. Returns the
+    /// number of transactions dropped by the recheck.
+    pub fn update(&mut self, committed: &[Tx], app: &mut dyn Application) -> usize {
+        let included: HashSet<String> = committed.iter().map(|tx| tx_hash(tx)).collect();
+        for hash in &included {
+            // Committed transactions stay in the cache, so they aren't accepted twice.
+            self.cache.push(hash);
+        }
+        let mut dropped = 0;
+        let mut kept = VecDeque::with_capacity(self.txs.len());
+        for (hash, tx) in std::mem::take(&mut self.txs) {
+            if included.contains(&hash) {
+                continue;
+            }
+            if app.check_tx(&tx).is_ok() {
+                kept.push_back((hash, tx));
+            } else {
+                self.cache.remove(&hash);
+                dropped += 1;
+            }
+        }
+        self.bytes = kept.iter().map(|(_, tx)| tx.len()).sum();
+        self.txs = kept;
+        dropped
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::app::FinalizeBlockResponse;
+    use crate::consensus::block::Block;
+
+    /// Rejects transactions starting with the `banned` byte.
+    #[derive(Default)]
+    struct PickyApp {
+        banned: Option<u8>,
+    }
+
+    impl Application for PickyApp {
+        fn check_tx(&mut self, tx: &[u8]) -> CheckTxResponse {
+            if self.banned.is_some() && tx.first() == self.banned.as_ref() {
+                return CheckTxResponse { code: 1, log: "banned".into() };
+            }
+            CheckTxResponse::default()
+        }
+
+        fn finalize_block(&mut self, _block: &Block) -> FinalizeBlockResponse {
+            FinalizeBlockResponse::default()
+        }
+    }
+
+    fn mempool(size: usize) -> Mempool {
+        Mempool::new(MempoolConfig {
+            size,
+            max_tx_bytes: 16,
+            cache_size: 100,
+        })
+    }
+
+    #[test]
+    fn rejects_duplicates_oversized_txs_and_overflow() {
+        let mut app = PickyApp::default();
+        let mut pool = mempool(2);
+
+        assert!(pool.check_tx(b"a1".to_vec(), &mut app).unwrap().is_ok());
+        assert_eq!(pool.check_tx(b"a1".to_vec(), &mut app), Err(MempoolError::AlreadySeen));
+        assert!(matches!(
+            pool.check_tx(vec![0; 17], &mut app),
+            Err(MempoolError::TxTooLarge { size: 17, max: 16 })
+        ));
+        assert!(pool.check_tx(b"b1".to_vec(), &mut app).unwrap().is_ok());
+        assert_eq!(pool.check_tx(b"c1".to_vec(), &mut app), Err(MempoolError::Full { size: 2 }));
+        assert_eq!(pool.len(), 2);
+        assert_eq!(pool.size_bytes(), 4);
+        assert_eq!(pool.reap(10), vec![b"a1".to_vec()]);
+    }
+
+    #[test]
+    fn update_removes_committed_and_rechecks_the_rest() {
+        let mut app = PickyApp::default();
+        let mut pool = mempool(10);
+        for tx in [&b"a1"[..], b"b1", b"b2", b"c1"] {
+            pool.check_tx(tx.to_vec(), &mut app).unwrap();
+        }
+
+        app.banned = Some(b'b');
+        let dropped = pool.update(&[b"a1".to_vec()], &mut app);
+        assert_eq!(dropped, 2);
+        assert_eq!(pool.reap(usize::MAX), vec![b"c1".to_vec()]);
+
+        // A committed tx stays known; a dropped one may be resent.
+        assert_eq!(pool.check_tx(b"a1".to_vec(), &mut app), Err(MempoolError::AlreadySeen));
+        app.banned = None;
+        assert!(pool.check_tx(b"b1".to_vec(), &mut app).unwrap().is_ok());
+    }
+}
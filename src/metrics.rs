@@ -0,0 +1,241 @@
+//! Prometheus metrics, served as text on `GET /metrics`.
+//!
+//! Every metric name starts with the configured namespace and its subsystem,
+//! e.g. `tendermint_consensus_height`:
+//!
+//! | Subsystem   | Metric                          | Type      | Meaning                                          |
+//! |-------------|---------------------------------|-----------|--------------------------------------------------|
+//! | `consensus` | `height`                        | gauge     | height being decided                             |
+//! | `consensus` | `rounds`                        | gauge     | round of that height                             |
+//! | `consensus` | `step_duration_seconds{step}`   | histogram | time spent in each step                          |
+//! | `consensus` | `validators`, `validators_power` | gauge    | size of the validator set                        |
+//! | `consensus` | `missing_validators`, `missing_validators_power` | gauge | validators absent from the last commit |
+//! | `consensus` | `byzantine_validators`, `byzantine_validators_power` | gauge | validators punished by the last block's evidence |
+//! | `consensus` | `block_interval_seconds`        | histogram | time between our last two commits                |
+//! | `consensus` | `num_txs`, `block_size_bytes`   | gauge     | transactions in, and encoded size of, the last block |
+//! | `consensus` | `total_txs`                     | counter   | transactions committed                           |
+//! | `p2p`       | `peers`                         | gauge     | known peers                                      |
+//! | `p2p`       | `peer_receive_bytes_total{peer, channel}` | counter | bytes of valid messages received       |
+//! | `p2p`       | `peer_send_bytes_total{peer, channel}`    | counter | bytes of messages sent                 |
+//! | `mempool`   | `size`, `size_bytes`            | gauge     | transactions waiting, and their total size       |
+//! | `mempool`   | `failed_txs`                    | counter   | transactions refused (duplicates aside)          |
+//! | `mempool`   | `recheck_times`                 | counter   | transactions checked again after a block         |
+//!
+//! Peers are labelled by IP address, like their scores.
+
+use std::net::SocketAddr;
+
+use anyhow::Result;
+use axum::http::header::CONTENT_TYPE;
+use axum::routing::get;
+use axum::Router;
+use prometheus::core::Collector;
+use prometheus::{
+    Encoder, Histogram, HistogramOpts, HistogramVec, IntCounter, IntCounterVec, IntGauge, Opts, Registry,
+    TextEncoder,
+};
+use tokio::net::TcpListener;
+use tokio_util::sync::CancellationToken;
+use tracing::info;
+
+/// The namespace used when none is configured.
+pub const DEFAULT_NAMESPACE: &str = "tendermint";
+
+/// Buckets of the duration histograms, in seconds.
+const DURATION_BUCKETS: &[f64] = &[0.01, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0, 30.0, 60.0];
+
+/// All metrics of a node, registered in one registry. Clones share the same metrics.
+#[derive(Debug, Clone)]
+pub struct Metrics {
+    registry: Registry,
+    pub consensus: ConsensusMetrics,
+    pub p2p: P2PMetrics,
+    pub mempool: MempoolMetrics,
+}
+
+/// Metrics updated by `ConsensusCore`.
+#[derive(Debug, Clone)]
+pub struct ConsensusMetrics {
+    pub height: IntGauge,
+    pub rounds: IntGauge,
+    pub step_duration_seconds: HistogramVec,
+    pub validators: IntGauge,
+    pub validators_power: IntGauge,
+    pub missing_validators: IntGauge,
+    pub missing_validators_power: IntGauge,
+    pub byzantine_validators: IntGauge,
+    pub byzantine_validators_power: IntGauge,
+    pub block_interval_seconds: Histogram,
+    pub num_txs: IntGauge,
+    pub block_size_bytes: IntGauge,
+    pub total_txs: IntCounter,
+}
+
+/// Metrics updated by the connection handling.
+#[derive(Debug, Clone)]
+pub struct P2PMetrics {
+    pub peers: IntGauge,
+    pub peer_receive_bytes_total: IntCounterVec,
+    pub peer_send_bytes_total: IntCounterVec,
+}
+
+/// Metrics of the mempool.
+#[derive(Debug, Clone)]
+pub struct MempoolMetrics {
+    pub size: IntGauge,
+    pub size_bytes: IntGauge,
+    pub failed_txs: IntCounter,
+    pub recheck_times: IntCounter,
+}
+
+impl Metrics {
+    /// Creates the metrics, named `<namespace>_<subsystem>_<name>`.
+    pub fn new(namespace: &str) -> Result<Self> {
+        let registry = Registry::new_custom(Some(namespace.to_string()), None)?;
+        let consensus = Subsystem { registry: &registry, name: "consensus" };
+        let p2p = Subsystem { registry: &registry, name: "p2p" };
+        let mempool = Subsystem { registry: &registry, name: "mempool" };
+        Ok(Self {
+            consensus: ConsensusMetrics {
+                height: consensus.gauge("height", "Height being decided.")?,
+                rounds: consensus.gauge("rounds", "Round of the current height.")?,
+                step_duration_seconds: consensus.register(HistogramVec::new(
+                    consensus.histogram_opts("step_duration_seconds", "Time spent in each step."),
+                    &["step"],
+                )?)?,
+                validators: consensus.gauge("validators", "Number of validators.")?,
+                validators_power: consensus.gauge("validators_power", "Total voting power of the validators.")?,
+                missing_validators: consensus
+                    .gauge("missing_validators", "Validators whose precommit is missing from the last commit.")?,
+                missing_validators_power: consensus
+                    .gauge("missing_validators_power", "Voting power of the missing validators.")?,
+                byzantine_validators: consensus
+                    .gauge("byzantine_validators", "Validators accused by the evidence in the last block.")?,
+                byzantine_validators_power: consensus
+                    .gauge("byzantine_validators_power", "Voting power of the byzantine validators.")?,
+                block_interval_seconds: consensus.register(Histogram::with_opts(
+                    consensus.histogram_opts("block_interval_seconds", "Time between the last two commits."),
+                )?)?,
+                num_txs: consensus.gauge("num_txs", "Number of transactions in the last block.")?,
+                block_size_bytes: consensus.gauge("block_size_bytes", "Encoded size of the last block.")?,
+                total_txs: consensus.counter("total_txs", "Transactions committed.")?,
+            },
+            p2p: P2PMetrics {
+                peers: p2p.gauge("peers", "Number of known peers.")?,
+                peer_receive_bytes_total: p2p.counter_vec(
+                    "peer_receive_bytes_total",
+                    "Bytes of valid messages received, per peer and channel.",
+                )?,
+                peer_send_bytes_total: p2p
+                    .counter_vec("peer_send_bytes_total", "Bytes of messages sent, per peer and channel.")?,
+            },
+            mempool: MempoolMetrics {
+                size: mempool.gauge("size", "Number of transactions in the mempool.")?,
+                size_bytes: mempool.gauge("size_bytes", "Total size of the transactions in the mempool.")?,
+                failed_txs: mempool.counter("failed_txs", "Transactions refused by the mempool or the application.")?,
+                recheck_times: mempool.counter("recheck_times", "Transactions checked again after a block.")?,
+            },
+            registry,
+        })
+    }
+
+    /// Returns every metric in the Prometheus text format.
+    pub fn render(&self) -> String {
+        let mut buffer = Vec::new();
+        TextEncoder::new()
+            .encode(&self.registry.gather(), &mut buffer)
+            .expect("encoding metrics into memory cannot fail");
+        String::from_utf8(buffer).expect("metrics text is UTF-8")
+    }
+}
+
+impl Default for Metrics {
+    fn default() -> Self {
+        Self::new(DEFAULT_NAMESPACE).expect("the default namespace is valid")
+    }
+}
+
+/// Registers the metrics of one subsystem.
+struct Subsystem<'a> {
+    registry: &'a Registry,
+    name: &'static str,
+}
+
+impl Subsystem<'_> {
+    fn register<M: Collector + Clone + 'static>(&self, metric: M) -> prometheus::Result<M> {
+        self.registry.register(Box::new(metric.clone()))?;
+        Ok(metric)
+    }
+
+    fn opts(&self, name: &str, help: &str) -> Opts {
+        Opts::new(name, help).subsystem(self.name)
+    }
+
+    fn histogram_opts(&self, name: &str, help: &str) -> HistogramOpts {
+        HistogramOpts::from(self.opts(name, help)).buckets(DURATION_BUCKETS.to_vec())
+    }
+
+    fn gauge(&self, name: &str, help: &str) -> prometheus::Result<IntGauge> {
+        self.register(IntGauge::with_opts(self.opts(name, help))?)
+    }
+
+    fn counter(&self, name: &str, help: &str) -> prometheus::Result<IntCounter> {
+        self.register(IntCounter::with_opts(self.opts(name, help))?)
+    }
+
+    /// A counter labelled by peer and channel.
+    fn counter_vec(&self, name: &str, help: &str) -> prometheus::Result<IntCounterVec> {
+        self.register(IntCounterVec::new(self.opts(name, help), &["peer", "channel"])?)
+    }
+}
+
+/// Serves `metrics` on `GET /metrics` at `listen_addr` until `shutdown` is
+/// cancelled or the server fails.
+pub async fn serve(metrics: Metrics, listen_addr: &str, shutdown: CancellationToken) -> Result<()> {
+    let addr: SocketAddr = listen_addr.parse()?;
+    let listener = TcpListener::bind(addr).await?;
+    info!("Metrics server listening on {}", listener.local_addr()?);
+    let app = Router::new().route(
+        "/metrics",
+        get(move || async move { ([(CONTENT_TYPE, TextEncoder::new().format_type().to_string())], metrics.render()) }),
+    );
+    axum::serve(listener, app)
+        .with_graceful_shutdown(shutdown.cancelled_owned())
+        .await?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::consensus::ConsensusState;
+
+    /// Returns the value of the sample `name` (with its labels) in `text`.
+    fn sample(text: &str, name: &str) -> Option<f64> {
+        text.lines()
+            .find_map(|line| line.strip_prefix(name)?.strip_prefix(' ')?.parse().ok())
+    }
+
+    #[tokio::test]
+    async fn consensus_and_mempool_report_their_progress() {
+        let mut cs = ConsensusState::new("node-a".into(), "127.0.0.1:0".into());
+        cs.set_metrics(Metrics::new("test").unwrap());
+        cs.check_tx(b"a".to_vec()).await.unwrap();
+        cs.check_tx(b"b".to_vec()).await.unwrap();
+        assert!(cs.check_tx(b"a".to_vec()).await.is_err());
+        let text = cs.metrics().render();
+        assert_eq!(sample(&text, "test_mempool_size"), Some(2.0));
+        assert_eq!(sample(&text, "test_mempool_failed_txs"), Some(0.0));
+
+        cs.start_new_round("block".into()).await;
+        cs.start_new_round("block".into()).await;
+        let text = cs.metrics().render();
+        assert_eq!(sample(&text, "test_consensus_height"), Some(3.0));
+        assert_eq!(sample(&text, "test_consensus_total_txs"), Some(2.0));
+        assert_eq!(sample(&text, "test_consensus_num_txs"), Some(0.0));
+        assert_eq!(sample(&text, "test_consensus_missing_validators"), Some(0.0));
+        assert_eq!(sample(&text, "test_consensus_block_interval_seconds_count"), Some(1.0));
+        assert_eq!(sample(&text, "test_consensus_step_duration_seconds_count{step=\"Precommit\"}"), Some(2.0));
+        assert_eq!(sample(&text, "test_mempool_size"), Some(0.0));
+    }
+}
@@ -0,0 +1,404 @@
+//! Wire encoding for `P2PMessage`.
+//!
+//! Every frame starts with a two-byte envelope:
+//!
+//! ```text
+//! +---------+----------+------------------+
+//! | version | encoding | payload ...      |
+//! +---------+----------+------------------+
+//! ```
+//!
+//! - `version` is the envelope version, currently [`WIRE_VERSION`]. Peers reject
+//!   frames with any other version.
+//! - `encoding` selects how the payload is serialized (see [`WireFormat`]).
+//!
+//! The default binary encoding is bincode with varint integers and trailing
+//! bytes rejected, which is compact and deterministic for our message types
+//! (they contain no maps). JSON is kept as a debug option; the decoder always
+//! accepts both, so nodes with different settings can still talk to each other.
+
+use std::collections::HashMap;
+use std::fmt;
+use std::io;
+
+use bincode::Options;
+use serde::{Deserialize, Serialize};
+use tokio_util::codec::LengthDelimitedCodecError;
+
+use crate::config::MempoolConfig;
+use crate::consensus::block::BLOCK_PART_SIZE;
+
+use super::message::{Channel, P2PMessage};
+
+/// Current envelope version.
+pub const WIRE_VERSION: u8 = 1;
+
+/// How the payload of a frame is serialized.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum WireFormat {
+    /// Compact, deterministic binary encoding (the default).
+    #[default]
+    Binary,
+    /// Human-readable JSON, for debugging.
+    Json,
+}
+
+impl WireFormat {
+    fn tag(self) -> u8 {
+        match self {
+            WireFormat::Binary => 0,
+            WireFormat::Json => 1,
+        }
+    }
+
+    fn from_tag(tag: u8) -> Option<Self> {
+        match tag {
+            0 => Some(WireFormat::Binary),
+            1 => Some(WireFormat::Json),
+            _ => None,
+        }
+    }
+}
+
+/// Why a frame could not be decoded.
+#[derive(Debug)]
+pub enum DecodeError {
+    /// The frame is shorter than the envelope header.
+    Truncated,
+    /// The envelope version is not one we understand.
+    UnsupportedVersion(u8),
+    /// The encoding tag is unknown.
+    UnknownEncoding(u8),
+    /// The payload doesn't deserialize into a `P2PMessage`.
+    Payload(String),
+}
+
+impl fmt::Display for DecodeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            DecodeError::Truncated => write!(f, "frame shorter than envelope header"),
+            DecodeError::UnsupportedVersion(v) => write!(f, "unsupported wire version {}", v),
+            DecodeError::UnknownEncoding(t) => write!(f, "unknown payload encoding {}", t),
+            DecodeError::Payload(e) => write!(f, "invalid payload: {}", e),
+        }
+    }
+}
+
+impl std::error::Error for DecodeError {}
+
+fn bincode_options() -> impl Options {
+    bincode::DefaultOptions::new()
+        .with_varint_encoding()
+        .reject_trailing_bytes()
+}
+
+/// Serializes `msg` into a complete frame (envelope + payload).
+pub fn encode(msg: &P2PMessage, format: WireFormat) -> anyhow::Result<Vec<u8>> {
+    let mut frame = vec![WIRE_VERSION, format.tag()];
+    match format {
+        WireFormat::Binary => bincode_options().serialize_into(&mut frame, msg)?,
+        WireFormat::Json => serde_json::to_writer(&mut frame, msg)?,
+    }
+    Ok(frame)
+}
+
+/// Parses a frame produced by [`encode`], in either encoding.
+pub fn decode(frame: &[u8]) -> Result<P2PMessage, DecodeError> {
+    let (&version, rest) = frame.split_first().ok_or(DecodeError::Truncated)?;
+    let (&tag, payload) = rest.split_first().ok_or(DecodeError::Truncated)?;
+
+    if version != WIRE_VERSION {
+        return Err(DecodeError::UnsupportedVersion(version));
+    }
+    match WireFormat::from_tag(tag).ok_or(DecodeError::UnknownEncoding(tag))? {
+        WireFormat::Binary => bincode_options()
+            .deserialize(payload)
+            .map_err(|e| DecodeError::Payload(e.to_string())),
+        WireFormat::Json => {
+            serde_json::from_slice(payload).map_err(|e| DecodeError::Payload(e.to_string()))
+        }
+    }
+}
+
+/// Maximum encoded size (envelope included) of a message on each channel.
+///
+/// The largest per-channel limit also bounds the length-delimited framing, so
+/// the transport never buffers a frame that no channel could accept.
+#[derive(Debug, Clone)]
+pub struct FrameLimits {
+    per_channel: HashMap<Channel, usize>,
+}
+
+impl Default for FrameLimits {
+    fn default() -> Self {
+        let per_channel = Channel::ALL
+            .iter()
+            .map(|&ch| {
+                let max = match ch {
+                    Channel::Peer => 4 * 1024,
+                    Channel::Consensus => 4 * 1024,
+                    Channel::Data => BLOCK_PART_SIZE + 16 * 1024,
+                    Channel::Vote => 4 * 1024,
+                    Channel::VoteSetBits => 4 * 1024,
+                    Channel::Evidence => 64 * 1024,
+                    Channel::Mempool => MempoolConfig::default().max_tx_bytes + 1024,
+                };
+                (ch, max)
+            })
+            .collect();
+        Self { per_channel }
+    }
+}
+
+impl FrameLimits {
+    /// Overrides the limit for `channel`.
+    pub fn set(&mut self, channel: Channel, max: usize) {
+        self.per_channel.insert(channel, max);
+    }
+
+    /// Returns the limit for `channel`.
+    pub fn max_size(&self, channel: Channel) -> usize {
+        self.per_channel.get(&channel).copied().unwrap_or(0)
+    }
+
+    /// Returns the largest limit across all channels, used as the framing limit.
+    pub fn max_frame_len(&self) -> usize {
+        self.per_channel.values().copied().max().unwrap_or(0)
+    }
+}
+
+/// Everything that can go wrong while reading a frame from a peer.
+#[derive(Debug)]
+pub enum FrameError {
+    /// The underlying stream failed.
+    Io(io::Error),
+    /// The frame exceeded the framing limit, or the limit of its message's channel
+    /// (`channel` is `None` when the frame was rejected before decoding).
+    Oversized {
+        len: Option<usize>,
+        max: usize,
+        channel: Option<Channel>,
+    },
+    /// The frame could not be decoded into a `P2PMessage`.
+    Decode(DecodeError),
+}
+
+impl FrameError {
+    /// Classifies an error from the length-delimited codec.
+    pub fn from_codec(err: io::Error, max_frame_len: usize) -> Self {
+        let too_big = err
+            .get_ref()
+            .is_some_and(|inner| inner.is::<LengthDelimitedCodecError>());
+        if too_big {
+            FrameError::Oversized {
+                len: None,
+                max: max_frame_len,
+                channel: None,
+            }
+        } else {
+            FrameError::Io(err)
+        }
+    }
+
+    /// Returns a short name for the error class, for structured logs.
+    pub fn kind(&self) -> &'static str {
+        match self {
+            FrameError::Io(_) => "io",
+            FrameError::Oversized { .. } => "oversized",
+            FrameError::Decode(_) => "decode",
+        }
+    }
+}
+
+impl fmt::Display for FrameError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            FrameError::Io(e) => write!(f, "i/o error: {}", e),
+            FrameError::Oversized { len, max, channel } => {
+                write!(f, "frame ")?;
+                if let Some(len) = len {
+                    write!(f, "of {} bytes ", len)?;
+                }
+                write!(f, "exceeds limit of {} bytes", max)?;
+                if let Some(ch) = channel {
+                    write!(f, " on {} channel", ch.name())?;
+                }
+                Ok(())
+            }
+            FrameError::Decode(e) => write!(f, "decode error: {}", e),
+        }
+    }
+}
+
+impl std::error::Error for FrameError {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use bytes::BytesMut;
+    use tokio_util::codec::{Decoder, LengthDelimitedCodec};
+
+    use crate::consensus::bits::BitArray;
+    use crate::consensus::block::PartSet;
+    use crate::consensus::commit::Commit;
+    use crate::consensus::evidence::{DuplicateVoteEvidence, Evidence};
+    use crate::consensus::types::{Step, VoteType};
+    use crate::consensus::vote::Vote;
+
+    /// Position of `msg`'s variant; a new variant won't compile until it is
+    /// given a sample in [`samples`].
+    fn variant(msg: &P2PMessage) -> usize {
+        match msg {
+            P2PMessage::PeerInfo { .. } => 0,
+            P2PMessage::Proposal { .. } => 1,
+            P2PMessage::BlockPart { .. } => 2,
+            P2PMessage::Vote { .. } => 3,
+            P2PMessage::Commit { .. } => 4,
+            P2PMessage::NewRoundStep { .. } => 5,
+            P2PMessage::HasVote { .. } => 6,
+            P2PMessage::VoteSetMaj23 { .. } => 7,
+            P2PMessage::VoteSetBits { .. } => 8,
+            P2PMessage::Evidence { .. } => 9,
+            P2PMessage::Tx { .. } => 10,
+            P2PMessage::Goodbye => 11,
+        }
+    }
+
+    const VARIANTS: usize = 12;
+
+    /// One message of every variant.
+    fn samples() -> Vec<P2PMessage> {
+        let parts = PartSet::from_data(b"block");
+        let vote = |block_hash: &str| Vote::new(VoteType::Precommit, 3, 1, block_hash.into(), "node-a".into());
+        let mut votes = BitArray::new(4);
+        votes.set(2);
+        vec![
+            P2PMessage::PeerInfo {
+                node_id: "node-a".into(),
+                listen_addr: "127.0.0.1:26656".into(),
+                genesis_hash: "abcd".into(),
+            },
+            P2PMessage::Proposal {
+                proposer_id: "node-a".into(),
+                height: 3,
+                round: 1,
+                block_hash: "hash".into(),
+                parts_header: parts.header().clone(),
+            },
+            P2PMessage::BlockPart {
+                height: 3,
+                round: 1,
+                part: parts.part(0).unwrap().clone(),
+            },
+            P2PMessage::Vote { vote: vote("hash") },
+            P2PMessage::Commit {
+                commit: Commit {
+                    height: 3,
+                    round: 1,
+                    block_hash: "hash".into(),
+                    signatures: vec![vote("hash")],
+                },
+            },
+            P2PMessage::NewRoundStep {
+                height: 3,
+                round: 1,
+                step: Step::Prevote,
+            },
+            P2PMessage::HasVote {
+                height: 3,
+                round: 1,
+                vote_type: VoteType::Prevote,
+                index: 2,
+            },
+            P2PMessage::VoteSetMaj23 {
+                height: 3,
+                round: 1,
+                vote_type: VoteType::Precommit,
+                block_hash: "hash".into(),
+            },
+            P2PMessage::VoteSetBits {
+                height: 3,
+                round: 1,
+                vote_type: VoteType::Precommit,
+                block_hash: "hash".into(),
+                votes,
+            },
+            P2PMessage::Evidence {
+                evidence: Evidence::DuplicateVote(DuplicateVoteEvidence::new(vote("a"), vote("b")).unwrap()),
+            },
+            P2PMessage::Tx { tx: b"k=v".to_vec() },
+            P2PMessage::Goodbye,
+        ]
+    }
+
+    #[test]
+    fn every_message_round_trips_in_both_formats() {
+        let samples = samples();
+        let mut seen = [false; VARIANTS];
+        for msg in &samples {
+            seen[variant(msg)] = true;
+            for format in [WireFormat::Binary, WireFormat::Json] {
+                let frame = encode(msg, format).unwrap();
+                assert_eq!(&frame[..2], &[WIRE_VERSION, format.tag()]);
+                let decoded = decode(&frame).unwrap();
+                assert_eq!(variant(&decoded), variant(msg));
+                assert_eq!(encode(&decoded, format).unwrap(), frame, "{} in {:?}", msg.msg_type(), format);
+            }
+        }
+        assert!(seen.iter().all(|&s| s), "a variant has no sample");
+    }
+
+    #[test]
+    fn unknown_version_and_encoding_are_rejected() {
+        let mut frame = encode(&P2PMessage::Goodbye, WireFormat::Binary).unwrap();
+        frame[0] = WIRE_VERSION + 1;
+        assert!(matches!(decode(&frame), Err(DecodeError::UnsupportedVersion(v)) if v == WIRE_VERSION + 1));
+        frame[0] = WIRE_VERSION;
+        frame[1] = 7;
+        assert!(matches!(decode(&frame), Err(DecodeError::UnknownEncoding(7))));
+        assert!(matches!(decode(&[WIRE_VERSION]), Err(DecodeError::Truncated)));
+    }
+
+    #[test]
+    fn trailing_bytes_are_rejected() {
+        for format in [WireFormat::Binary, WireFormat::Json] {
+            for msg in samples() {
+                let mut frame = encode(&msg, format).unwrap();
+                frame.push(1);
+                assert!(
+                    matches!(decode(&frame), Err(DecodeError::Payload(_))),
+                    "{} in {:?}",
+                    msg.msg_type(),
+                    format
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn frames_over_the_largest_channel_limit_are_oversized() {
+        let limits = FrameLimits::default();
+        for msg in samples() {
+            let len = encode(&msg, WireFormat::Json).unwrap().len();
+            assert!(len <= limits.max_size(msg.channel()), "{} exceeds its limit", msg.msg_type());
+        }
+
+        let max = limits.max_frame_len();
+        assert_eq!(Some(max), Channel::ALL.iter().map(|&ch| limits.max_size(ch)).max());
+        let mut framing = LengthDelimitedCodec::builder().max_frame_length(max).new_codec();
+        let mut buf = BytesMut::new();
+        buf.extend_from_slice(&(max as u32 + 1).to_be_bytes());
+        let err = framing.decode(&mut buf).unwrap_err();
+        assert!(matches!(
+            FrameError::from_codec(err, max),
+            FrameError::Oversized { len: None, max: m, channel: None } if m == max
+        ));
+
+        let mut buf = BytesMut::new();
+        buf.extend_from_slice(&(max as u32).to_be_bytes());
+        buf.extend_from_slice(&vec![0; max]);
+        assert_eq!(framing.decode(&mut buf).unwrap().unwrap().len(), max);
+    }
+}
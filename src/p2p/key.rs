@@ -0,0 +1,138 @@
+//! The node's long-term identity key.
+//!
+//! Each node owns an Ed25519 key pair. Transports that authenticate peers (such
+//! as QUIC/TLS) use it as their identity, so the key a peer presents is the
+//! node's own key rather than a throwaway certificate key.
+//!
+//! The node's ID is derived from the public key (see [`NodeKey::node_id`]), so
+//! it can't be claimed without the key. The key is created once and kept in the
+//! home directory, which gives a node the same ID across restarts.
+
+use std::path::Path;
+
+use anyhow::{Context, Result};
+use ed25519_dalek::pkcs8::EncodePrivateKey;
+use ed25519_dalek::{Signature, Signer, SigningKey, VerifyingKey};
+use rand::rngs::OsRng;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+
+/// Number of public-key hash bytes that make up a node ID.
+const NODE_ID_BYTES: usize = 20;
+
+/// The contents of a node key file.
+#[derive(Serialize, Deserialize)]
+struct KeyFile {
+    /// Hex-encoded 32-byte Ed25519 secret key.
+    priv_key: String,
+}
+
+/// An Ed25519 key pair identifying this node on the network.
+#[derive(Clone)]
+pub struct NodeKey {
+    signing_key: SigningKey,
+}
+
+impl NodeKey {
+    /// Generates a fresh random key.
+    pub fn generate() -> Self {
+        Self {
+            signing_key: SigningKey::generate(&mut OsRng),
+        }
+    }
+
+    /// Returns the public half of the key.
+    pub fn public_key(&self) -> VerifyingKey {
+        self.signing_key.verifying_key()
+    }
+
+    /// Returns the node's ID: the first 20 bytes of the SHA-256 hash of the
+    /// public key, hex-encoded.
+    pub fn node_id(&self) -> String {
+        node_id(&self.public_key())
+    }
+
+    /// Signs `message` with the private key.
+    pub fn sign(&self, message: &[u8]) -> Signature {
+        self.signing_key.sign(message)
+    }
+
+    /// Reads a key file written by [`NodeKey::save`].
+    pub fn load(path: impl AsRef<Path>) -> Result<Self> {
+        let path = path.as_ref();
+        let data = std::fs::read(path).with_context(|| format!("reading node key {}", path.display()))?;
+        let file: KeyFile =
+            serde_json::from_slice(&data).with_context(|| format!("parsing node key {}", path.display()))?;
+        let bytes: [u8; 32] = hex::decode(&file.priv_key)
+            .ok()
+            .and_then(|b| b.try_into().ok())
+            .with_context(|| format!("{}: priv_key must be 32 hex-encoded bytes", path.display()))?;
+        Ok(Self {
+            signing_key: SigningKey::from_bytes(&bytes),
+        })
+    }
+
+    /// Loads the key at `path`, first generating and saving one if there is none.
+    pub fn load_or_generate(path: impl AsRef<Path>) -> Result<Self> {
+        let path = path.as_ref();
+        if path.exists() {
+            return Self::load(path);
+        }
+        let key = Self::generate();
+        key.save(path)?;
+        Ok(key)
+    }
+
+    /// Writes the key to `path`, readable only by the owner.
+    pub fn save(&self, path: impl AsRef<Path>) -> Result<()> {
+        let path = path.as_ref();
+        let file = KeyFile {
+            priv_key: hex::encode(self.signing_key.to_bytes()),
+        };
+        std::fs::write(path, serde_json::to_vec_pretty(&file)?)
+            .with_context(|| format!("writing node key {}", path.display()))?;
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            std::fs::set_permissions(path, std::fs::Permissions::from_mode(0o600))?;
+        }
+        Ok(())
+    }
+
+    /// Encodes the private key as PKCS#8 DER, the format TLS libraries expect.
+    pub fn to_pkcs8_der(&self) -> Result<Vec<u8>> {
+        Ok(self.signing_key.to_pkcs8_der()?.as_bytes().to_vec())
+    }
+}
+
+impl std::fmt::Debug for NodeKey {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        // Never print the private key.
+        f.debug_struct("NodeKey")
+            .field("public_key", &self.public_key())
+            .finish()
+    }
+}
+
+/// Returns the node ID belonging to `public_key` (see [`NodeKey::node_id`]).
+pub fn node_id(public_key: &VerifyingKey) -> String {
+    hex::encode(&Sha256::digest(public_key.as_bytes())[..NODE_ID_BYTES])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn saved_key_keeps_its_node_id() {
+        let path = std::env::temp_dir().join(format!("tmlike-node-key-{}.json", std::process::id()));
+        let key = NodeKey::load_or_generate(&path).unwrap();
+        let reloaded = NodeKey::load_or_generate(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        assert_eq!(reloaded.public_key(), key.public_key());
+        assert_eq!(reloaded.node_id(), key.node_id());
+        assert_eq!(key.node_id().len(), 2 * NODE_ID_BYTES);
+        assert_ne!(NodeKey::generate().node_id(), key.node_id());
+    }
+}
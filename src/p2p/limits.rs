@@ -0,0 +1,336 @@
+//! Connection limits enforced before a connection is handed to the protocol.
+//!
+//! A `ConnectionTracker` counts live inbound and outbound connections and how
+//! many of them come from each IP address and subnet (/24 for IPv4, /64 for IPv6).
+//! Every accepted or dialed socket must first obtain a `ConnectionGuard`; the
+//! guard releases its slot when dropped, i.e. when the connection task ends.
+//!
+//! Addresses on the `unconditional_peers` allowlist bypass every limit and are
+//! not counted against them.
+
+use std::collections::HashMap;
+use std::fmt;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+use std::sync::{Arc, Mutex};
+
+/// Whether a connection was accepted by us or dialed by us.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Direction {
+    Inbound,
+    Outbound,
+}
+
+/// Upper bounds on concurrent connections.
+#[derive(Debug, Clone)]
+pub struct ConnectionLimits {
+    /// Maximum number of connections accepted from remote peers.
+    pub max_inbound: usize,
+    /// Maximum number of connections we dial ourselves.
+    pub max_outbound: usize,
+    /// Maximum number of connections (either direction) to a single IP address.
+    pub max_per_ip: usize,
+    /// Maximum number of connections (either direction) to a single subnet.
+    pub max_per_subnet: usize,
+    /// IP addresses that are always allowed to connect, regardless of the limits above.
+    pub unconditional_peers: Vec<IpAddr>,
+}
+
+impl Default for ConnectionLimits {
+    fn default() -> Self {
+        Self {
+            max_inbound: 40,
+            max_outbound: 10,
+            max_per_ip: 3,
+            max_per_subnet: 8,
+            unconditional_peers: Vec::new(),
+        }
+    }
+}
+
+/// The reason a connection was refused.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum LimitExceeded {
+    Inbound(usize),
+    Outbound(usize),
+    PerIp(IpAddr, usize),
+    PerSubnet(IpAddr, usize),
+}
+
+impl fmt::Display for LimitExceeded {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            LimitExceeded::Inbound(max) => write!(f, "inbound connection limit ({}) reached", max),
+            LimitExceeded::Outbound(max) => write!(f, "outbound connection limit ({}) reached", max),
+            LimitExceeded::PerIp(ip, max) => {
+                write!(f, "per-IP connection limit ({}) reached for {}", max, ip)
+            }
+            LimitExceeded::PerSubnet(net, max) => {
+                write!(f, "per-subnet connection limit ({}) reached for {}", max, net)
+            }
+        }
+    }
+}
+
+impl std::error::Error for LimitExceeded {}
+
+#[derive(Debug, Default)]
+struct Counts {
+    inbound: usize,
+    outbound: usize,
+    per_ip: HashMap<IpAddr, usize>,
+    per_subnet: HashMap<IpAddr, usize>,
+}
+
+/// Thread-safe bookkeeping of live connections against `ConnectionLimits`.
+#[derive(Debug, Clone)]
+pub struct ConnectionTracker {
+    limits: Arc<ConnectionLimits>,
+    counts: Arc<Mutex<Counts>>,
+}
+
+impl ConnectionTracker {
+    /// Creates a tracker with no live connections.
+    pub fn new(limits: ConnectionLimits) -> Self {
+        Self {
+            limits: Arc::new(limits),
+            counts: Arc::new(Mutex::new(Counts::default())),
+        }
+    }
+
+    /// Returns the configured limits.
+    pub fn limits(&self) -> &ConnectionLimits {
+        &self.limits
+    }
+
+    /// Returns `true` if `ip` is on the unconditional allowlist.
+    pub fn is_unconditional(&self, ip: IpAddr) -> bool {
+        self.limits.unconditional_peers.contains(&ip)
+    }
+
+    /// Reserves a slot for a connection to or from `addr`.
+    ///
+    /// Returns a guard that holds the slot until dropped, or the first limit
+    /// that would be exceeded.
+    pub fn try_acquire(&self, addr: SocketAddr, direction: Direction) -> Result<ConnectionGuard, LimitExceeded> {
+        let ip = addr.ip();
+        if self.is_unconditional(ip) {
+            return Ok(ConnectionGuard { slot: None });
+        }
+
+        let subnet = subnet_of(ip);
+        let mut counts = self.counts.lock().unwrap();
+
+        match direction {
+            Direction::Inbound if counts.inbound >= self.limits.max_inbound => {
+                return Err(LimitExceeded::Inbound(self.limits.max_inbound));
+            }
+            Direction::Outbound if counts.outbound >= self.limits.max_outbound => {
+                return Err(LimitExceeded::Outbound(self.limits.max_outbound));
+            }
+            _ => {}
+        }
+        if counts.per_ip.get(&ip).copied().unwrap_or(0) >= self.limits.max_per_ip {
+            return Err(LimitExceeded::PerIp(ip, self.limits.max_per_ip));
+        }
+        if counts.per_subnet.get(&subnet).copied().unwrap_or(0) >= self.limits.max_per_subnet {
+            return Err(LimitExceeded::PerSubnet(subnet, self.limits.max_per_subnet));
+        }
+
+        match direction {
+            Direction::Inbound => counts.inbound += 1,
+            Direction::Outbound => counts.outbound += 1,
+        }
+        *counts.per_ip.entry(ip).or_insert(0) += 1;
+        *counts.per_subnet.entry(subnet).or_insert(0) += 1;
+
+        Ok(ConnectionGuard {
+            slot: Some(Slot {
+                counts: self.counts.clone(),
+                ip,
+                subnet,
+                direction,
+            }),
+        })
+    }
+
+    /// Returns the number of live (inbound, outbound) connections that count against the limits.
+    pub fn connection_counts(&self) -> (usize, usize) {
+        let counts = self.counts.lock().unwrap();
+        (counts.inbound, counts.outbound)
+    }
+}
+
+#[derive(Debug)]
+struct Slot {
+    counts: Arc<Mutex<Counts>>,
+    ip: IpAddr,
+    subnet: IpAddr,
+    direction: Direction,
+}
+
+/// Holds a reserved connection slot; the slot is released on drop.
+#[derive(Debug)]
+pub struct ConnectionGuard {
+    /// `None` for unconditional peers, which don't occupy a slot.
+    slot: Option<Slot>,
+}
+
+impl Drop for ConnectionGuard {
+    fn drop(&mut self) {
+        let Some(slot) = self.slot.take() else {
+            return;
+        };
+        let mut counts = slot.counts.lock().unwrap();
+        match slot.direction {
+            Direction::Inbound => counts.inbound -= 1,
+            Direction::Outbound => counts.outbound -= 1,
+        }
+        decrement(&mut counts.per_ip, slot.ip);
+        decrement(&mut counts.per_subnet, slot.subnet);
+    }
+}
+
+fn decrement(map: &mut HashMap<IpAddr, usize>, key: IpAddr) {
+    if let Some(n) = map.get_mut(&key) {
+        *n -= 1;
+        if *n == 0 {
+            map.remove(&key);
+        }
+    }
+}
+
+/// Masks `ip` down to its /24 (IPv4) or /64 (IPv6) network address.
+fn subnet_of(ip: IpAddr) -> IpAddr {
+    match ip {
+        IpAddr::V4(v4) => {
+            let [a, b, c, _] = v4.octets();
+            IpAddr::V4(Ipv4Addr::new(a, b, c, 0))
+        }
+        IpAddr::V6(v6) => {
+            let s = v6.segments();
+            IpAddr::V6(Ipv6Addr::new(s[0], s[1], s[2], s[3], 0, 0, 0, 0))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn addr(s: &str) -> SocketAddr {
+        s.parse().unwrap()
+    }
+
+    fn tracker(limits: ConnectionLimits) -> ConnectionTracker {
+        ConnectionTracker::new(ConnectionLimits {
+            max_per_ip: 100,
+            max_per_subnet: 100,
+            ..limits
+        })
+    }
+
+    #[test]
+    fn inbound_and_outbound_are_capped_separately() {
+        let tracker = tracker(ConnectionLimits {
+            max_inbound: 2,
+            max_outbound: 1,
+            ..ConnectionLimits::default()
+        });
+        let _in1 = tracker.try_acquire(addr("10.0.1.1:1"), Direction::Inbound).unwrap();
+        let _in2 = tracker.try_acquire(addr("10.0.2.1:1"), Direction::Inbound).unwrap();
+        assert_eq!(
+            tracker.try_acquire(addr("10.0.3.1:1"), Direction::Inbound).unwrap_err(),
+            LimitExceeded::Inbound(2)
+        );
+        let _out = tracker.try_acquire(addr("10.0.3.1:1"), Direction::Outbound).unwrap();
+        assert_eq!(
+            tracker.try_acquire(addr("10.0.4.1:1"), Direction::Outbound).unwrap_err(),
+            LimitExceeded::Outbound(1)
+        );
+        assert_eq!(tracker.connection_counts(), (2, 1));
+    }
+
+    #[test]
+    fn per_ip_cap_counts_both_directions() {
+        let tracker = ConnectionTracker::new(ConnectionLimits {
+            max_per_ip: 2,
+            ..ConnectionLimits::default()
+        });
+        let ip = "10.0.0.1".parse().unwrap();
+        let _a = tracker.try_acquire(addr("10.0.0.1:1"), Direction::Inbound).unwrap();
+        let _b = tracker.try_acquire(addr("10.0.0.1:2"), Direction::Outbound).unwrap();
+        assert_eq!(
+            tracker.try_acquire(addr("10.0.0.1:3"), Direction::Inbound).unwrap_err(),
+            LimitExceeded::PerIp(ip, 2)
+        );
+        // Another address in the same subnet is still welcome.
+        tracker.try_acquire(addr("10.0.0.2:1"), Direction::Inbound).unwrap();
+    }
+
+    #[test]
+    fn ipv4_subnets_are_24_bits() {
+        let tracker = ConnectionTracker::new(ConnectionLimits {
+            max_per_subnet: 2,
+            ..ConnectionLimits::default()
+        });
+        let _a = tracker.try_acquire(addr("192.168.5.1:1"), Direction::Inbound).unwrap();
+        let _b = tracker.try_acquire(addr("192.168.5.254:1"), Direction::Inbound).unwrap();
+        assert_eq!(
+            tracker.try_acquire(addr("192.168.5.77:1"), Direction::Inbound).unwrap_err(),
+            LimitExceeded::PerSubnet("192.168.5.0".parse().unwrap(), 2)
+        );
+        tracker.try_acquire(addr("192.168.6.1:1"), Direction::Inbound).unwrap();
+    }
+
+    #[test]
+    fn ipv6_subnets_are_64_bits() {
+        let tracker = ConnectionTracker::new(ConnectionLimits {
+            max_per_subnet: 1,
+            ..ConnectionLimits::default()
+        });
+        let _a = tracker.try_acquire(addr("[2001:db8:1:2::1]:1"), Direction::Inbound).unwrap();
+        assert_eq!(
+            tracker
+                .try_acquire(addr("[2001:db8:1:2:ffff:ffff:ffff:ffff]:1"), Direction::Inbound)
+                .unwrap_err(),
+            LimitExceeded::PerSubnet("2001:db8:1:2::".parse().unwrap(), 1)
+        );
+        tracker.try_acquire(addr("[2001:db8:1:3::1]:1"), Direction::Inbound).unwrap();
+    }
+
+    #[test]
+    fn dropping_the_guard_releases_its_slot() {
+        let tracker = ConnectionTracker::new(ConnectionLimits {
+            max_inbound: 1,
+            max_per_ip: 1,
+            max_per_subnet: 1,
+            ..ConnectionLimits::default()
+        });
+        let guard = tracker.try_acquire(addr("10.0.0.1:1"), Direction::Inbound).unwrap();
+        assert!(tracker.try_acquire(addr("10.0.0.1:2"), Direction::Inbound).is_err());
+        drop(guard);
+        assert_eq!(tracker.connection_counts(), (0, 0));
+        assert!(tracker.counts.lock().unwrap().per_ip.is_empty());
+        assert!(tracker.counts.lock().unwrap().per_subnet.is_empty());
+        tracker.try_acquire(addr("10.0.0.1:2"), Direction::Inbound).unwrap();
+    }
+
+    #[test]
+    fn unconditional_peers_bypass_and_dont_count() {
+        let ip: IpAddr = "10.0.0.1".parse().unwrap();
+        let tracker = ConnectionTracker::new(ConnectionLimits {
+            max_inbound: 1,
+            max_per_ip: 1,
+            max_per_subnet: 1,
+            unconditional_peers: vec![ip],
+            ..ConnectionLimits::default()
+        });
+        let guards: Vec<_> = (1..=5)
+            .map(|port| tracker.try_acquire(SocketAddr::new(ip, port), Direction::Inbound).unwrap())
+            .collect();
+        assert_eq!(tracker.connection_counts(), (0, 0));
+        // Its slots are still free for everyone else.
+        tracker.try_acquire(addr("10.0.0.2:1"), Direction::Inbound).unwrap();
+        drop(guards);
+    }
+}
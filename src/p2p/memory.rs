@@ -0,0 +1,260 @@
+//! In-memory implementation of the [`Transport`] trait.
+//!
+//! All transports created from the same [`MemoryNetwork`] can reach each other
+//! through tokio duplex pipes, so many nodes can run inside one process (e.g. a
+//! test) without binding any OS ports. Addresses are ordinary `SocketAddr`s that
+//! only have meaning within the network.
+
+use std::collections::HashMap;
+use std::io;
+use std::net::SocketAddr;
+use std::sync::atomic::{AtomicU16, Ordering};
+use std::sync::{Arc, Mutex};
+
+use anyhow::Result;
+use async_trait::async_trait;
+use tokio::io::duplex;
+use tokio::sync::mpsc;
+
+use super::transport::{Accepted, BoxConnection, Listener, Transport};
+
+/// Buffer size of each direction of an in-memory pipe.
+const PIPE_CAPACITY: usize = 64 * 1024;
+
+type Incoming = (BoxConnection, SocketAddr);
+
+/// A shared registry of in-memory listeners.
+#[derive(Clone, Default)]
+pub struct MemoryNetwork {
+    listeners: Arc<Mutex<HashMap<SocketAddr, mpsc::UnboundedSender<Incoming>>>>,
+    /// Source port handed to the next dialed connection.
+    next_port: Arc<AtomicU16>,
+}
+
+impl MemoryNetwork {
+    /// Creates an empty network.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Returns a transport attached to this network. Connections it dials
+    /// appear to come from `local_addr`'s IP.
+    pub fn transport(&self, local_addr: SocketAddr) -> MemoryTransport {
+        MemoryTransport {
+            network: self.clone(),
+            local_addr,
+        }
+    }
+}
+
+/// A transport whose connections are in-memory pipes within a [`MemoryNetwork`].
+#[derive(Clone)]
+pub struct MemoryTransport {
+    network: MemoryNetwork,
+    local_addr: SocketAddr,
+}
+
+#[async_trait]
+impl Transport for MemoryTransport {
+    async fn listen(&self, addr: SocketAddr) -> Result<Box<dyn Listener>> {
+        let mut listeners = self.network.listeners.lock().unwrap();
+        if listeners.get(&addr).is_some_and(|tx| !tx.is_closed()) {
+            return Err(io::Error::new(io::ErrorKind::AddrInUse, addr.to_string()).into());
+        }
+        let (tx, rx) = mpsc::unbounded_channel();
+        listeners.insert(addr, tx);
+        Ok(Box::new(MemoryListener {
+            network: self.network.clone(),
+            addr,
+            incoming: rx,
+        }))
+    }
+
+    async fn dial(&self, addr: SocketAddr) -> Result<BoxConnection> {
+        let tx = self
+            .network
+            .listeners
+            .lock()
+            .unwrap()
+            .get(&addr)
+            .cloned()
+            .ok_or_else(|| io::Error::new(io::ErrorKind::ConnectionRefused, addr.to_string()))?;
+
+        // Give every dialed connection a distinct source port, like a real socket would.
+        let port = self.network.next_port.fetch_add(1, Ordering::Relaxed).max(1);
+        let source = SocketAddr::new(self.local_addr.ip(), port);
+
+        let (local, remote) = duplex(PIPE_CAPACITY);
+        tx.send((Box::new(remote), source))
+            .map_err(|_| io::Error::new(io::ErrorKind::ConnectionRefused, addr.to_string()))?;
+        Ok(Box::new(local))
+    }
+}
+
+/// A listener registered in a [`MemoryNetwork`]; unregisters itself on drop.
+struct MemoryListener {
+    network: MemoryNetwork,
+    addr: SocketAddr,
+    incoming: mpsc::UnboundedReceiver<Incoming>,
+}
+
+#[async_trait]
+impl Listener for MemoryListener {
+    async fn accept(&mut self) -> Result<Accepted> {
+        let (conn, remote_addr) = self
+            .incoming
+            .recv()
+            .await
+            .ok_or_else(|| io::Error::new(io::ErrorKind::NotConnected, "listener closed"))?;
+        Ok(Accepted {
+            conn,
+            remote_addr,
+            substream: false,
+        })
+    }
+
+    fn local_addr(&self) -> SocketAddr {
+        self.addr
+    }
+}
+
+impl Drop for MemoryListener {
+    fn drop(&mut self) {
+        self.network.listeners.lock().unwrap().remove(&self.addr);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::time::Duration;
+
+    use crate::consensus::{run_consensus_loop, ConsensusState};
+    use crate::p2p::message::P2PMessage;
+    use crate::p2p::peer::Peer;
+    use crate::p2p::transport::{accept_loop, send_message};
+
+    fn node(network: &MemoryNetwork, id: &str, addr: &str) -> ConsensusState {
+        let mut cs = ConsensusState::new(id.into(), addr.into());
+        cs.transport = Arc::new(network.transport(addr.parse().unwrap()));
+        cs
+    }
+
+    #[tokio::test]
+    async fn nodes_exchange_messages_without_ports() {
+        let network = MemoryNetwork::new();
+        let nodes: Vec<_> = (0..4)
+            .map(|i| node(&network, &format!("node-{}", i), &format!("10.0.0.{}:26656", i + 1)))
+            .collect();
+        for cs in &nodes {
+            let addr = cs.listen_addr.parse().unwrap();
+            tokio::spawn(accept_loop(cs.clone(), addr));
+        }
+        tokio::task::yield_now().await;
+
+        // Every node announces itself to every other node.
+        for from in &nodes {
+            let announce = from.peer_info();
+            for to in nodes.iter().filter(|n| n.node_id != from.node_id) {
+                let addr = to.listen_addr.parse().unwrap();
+                send_message(from, addr, &announce).await.unwrap();
+            }
+        }
+
+        tokio::time::timeout(Duration::from_secs(5), async {
+            while nodes.iter().any(|n| n.peer_manager().get_all_peers().len() < 3) {
+                tokio::time::sleep(Duration::from_millis(5)).await;
+            }
+        })
+        .await
+        .expect("all nodes learn about each other");
+    }
+
+    #[tokio::test]
+    async fn messages_to_a_peer_share_one_connection() {
+        let network = MemoryNetwork::new();
+        let a = node(&network, "node-a", "10.0.0.1:26656");
+        let b = node(&network, "node-b", "10.0.0.2:26656");
+        tokio::spawn(accept_loop(b.clone(), b.listen_addr.parse().unwrap()));
+        tokio::task::yield_now().await;
+        a.peer_manager().add_peer(Peer::new(b.node_id.clone(), b.listen_addr.clone()));
+
+        let b_peer = a.peer_manager().get_peer("node-b").unwrap();
+        for i in 0..20u8 {
+            a.send_to_peer(&b_peer, &P2PMessage::Tx { tx: vec![i] });
+        }
+        tokio::time::timeout(Duration::from_secs(5), async {
+            while b.inspect(|core| core.mempool.len()) < 20 {
+                tokio::time::sleep(Duration::from_millis(5)).await;
+            }
+        })
+        .await
+        .expect("every transaction arrives");
+
+        assert_eq!(a.peer_manager().connections().connection_counts(), (0, 1));
+        assert_eq!(b.peer_manager().connections().connection_counts(), (1, 0));
+        // B relays the transactions back over the same connection.
+        tokio::time::timeout(Duration::from_secs(5), async {
+            while a.inspect(|core| core.mempool.len()) < 20 {
+                tokio::time::sleep(Duration::from_millis(5)).await;
+            }
+        })
+        .await
+        .expect("B answers on A's connection");
+        assert_eq!(b.peer_manager().connections().connection_counts(), (1, 0));
+    }
+
+    #[tokio::test]
+    async fn shutdown_stops_listening_and_says_goodbye() {
+        let network = MemoryNetwork::new();
+        let a = node(&network, "node-a", "10.0.0.1:26656");
+        let b = node(&network, "node-b", "10.0.0.2:26656");
+        let a_addr = a.listen_addr.parse().unwrap();
+        let b_addr = b.listen_addr.parse().unwrap();
+        let a_listener = a.spawn(accept_loop(a.clone(), a_addr));
+        tokio::spawn(accept_loop(b.clone(), b_addr));
+        tokio::task::yield_now().await;
+        send_message(&a, b_addr, &a.peer_info()).await.unwrap();
+        send_message(&b, a_addr, &b.peer_info()).await.unwrap();
+        tokio::time::timeout(Duration::from_secs(5), async {
+            while a.peer_manager().get_all_peers().is_empty() || b.peer_manager().get_all_peers().is_empty() {
+                tokio::time::sleep(Duration::from_millis(5)).await;
+            }
+        })
+        .await
+        .expect("nodes learn about each other");
+
+        let a_rounds = a.spawn(run_consensus_loop(a.clone(), Duration::from_millis(50)));
+        tokio::time::sleep(Duration::from_millis(20)).await;
+
+        a.shutdown(Duration::from_secs(1)).await;
+        assert!(a_listener.is_finished(), "shutdown waits for the accept loop");
+        assert!(a_rounds.is_finished(), "shutdown waits for the consensus loop");
+        a_listener.await.unwrap().unwrap();
+        let height = a.inspect(|core| core.block_store.height());
+        tokio::time::sleep(Duration::from_millis(20)).await;
+        assert_eq!(a.inspect(|core| core.block_store.height()), height);
+        assert!(b.transport.dial(a_addr).await.is_err());
+        tokio::time::timeout(Duration::from_secs(5), async {
+            while !b.peer_manager().get_all_peers().is_empty() {
+                tokio::time::sleep(Duration::from_millis(5)).await;
+            }
+        })
+        .await
+        .expect("B forgets A after its goodbye");
+    }
+
+    #[tokio::test]
+    async fn dialing_unknown_address_is_refused() {
+        let network = MemoryNetwork::new();
+        let transport = network.transport("10.0.0.1:26656".parse().unwrap());
+        assert!(transport.dial("10.0.0.2:26656".parse().unwrap()).await.is_err());
+
+        // Once a listener is bound (and then dropped), the address is taken and freed again.
+        let listener = transport.listen("10.0.0.2:26656".parse().unwrap()).await.unwrap();
+        assert!(transport.listen(listener.local_addr()).await.is_err());
+        drop(listener);
+        assert!(transport.dial("10.0.0.2:26656".parse().unwrap()).await.is_err());
+    }
+}
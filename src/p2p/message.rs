@@ -1,37 +1,131 @@
 use serde::{Deserialize, Serialize};
 
+use crate::consensus::bits::BitArray;
+use crate::consensus::block::{Part, PartSetHeader, Tx};
+use crate::consensus::commit::Commit;
+use crate::consensus::evidence::Evidence;
+use crate::consensus::types::{Step, VoteType};
+use crate::consensus::vote::Vote;
+
+/// Logical channel a message travels on. Channels group messages with similar
+/// size and priority so that limits can be tuned per group.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Channel {
+    /// Peer handshakes, goodbyes and address exchange.
+    Peer,
+    /// Proposals and round-level consensus announcements.
+    Consensus,
+    /// Block parts and commits.
+    Data,
+    /// Individual prevotes and precommits.
+    Vote,
+    /// Vote-set reconciliation (`VoteSetMaj23` / `VoteSetBits`).
+    VoteSetBits,
+    /// Evidence of validator misbehavior.
+    Evidence,
+    /// Transactions waiting for a block.
+    Mempool,
+}
+
+impl Channel {
+    /// All channels, in a stable order.
+    pub const ALL: [Channel; 7] = [
+        Channel::Peer,
+        Channel::Consensus,
+        Channel::Data,
+        Channel::Vote,
+        Channel::VoteSetBits,
+        Channel::Evidence,
+        Channel::Mempool,
+    ];
+
+    /// Returns a short lowercase name, for logs and metrics.
+    pub fn name(&self) -> &'static str {
+        match self {
+            Channel::Peer => "peer",
+            Channel::Consensus => "consensus",
+            Channel::Data => "data",
+            Channel::Vote => "vote",
+            Channel::VoteSetBits => "vote_set_bits",
+            Channel::Evidence => "evidence",
+            Channel::Mempool => "mempool",
+        }
+    }
+}
+
 /// `P2PMessage` defines the types of messages that can be exchanged
 /// between nodes in this simplified Tendermint-like protocol.
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub enum P2PMessage {
-    /// Basic handshake or peer info message. Used to announce a node's ID and address.
+    /// Basic handshake or peer info message. Used to announce a node's ID and address,
+    /// and the hash of its genesis so that nodes of different chains refuse each other.
     PeerInfo {
         node_id: String,
         listen_addr: String,
+        genesis_hash: String,
     },
-    /// A block proposal for a given round.
+    /// A block proposal for a given height and round. The block itself follows as `BlockPart`s.
     Proposal {
         proposer_id: String,
-        round: u64,
-        block: String,
-    },
-    /// A prevote message for a given round/block.
-    Prevote {
-        voter_id: String,
+        height: u64,
         round: u64,
         block_hash: String,
+        parts_header: PartSetHeader,
     },
-    /// A precommit message for a given round/block.
-    Precommit {
-        voter_id: String,
+    /// One Merkle-proven part of the block proposed at `height` in `round`.
+    BlockPart {
+        height: u64,
         round: u64,
-        block_hash: String,
+        part: Part,
     },
-    /// A final commit announcement for a block at a specific round.
+    /// A signed prevote or precommit.
+    Vote {
+        vote: Vote,
+    },
+    /// A committed block's hash with the precommits that finalized it.
     Commit {
+        commit: Commit,
+    },
+    /// The sender announces the height/round/step it has reached.
+    NewRoundStep {
+        height: u64,
+        round: u64,
+        step: Step,
+    },
+    /// The sender announces it holds the vote of the validator at position `index`.
+    HasVote {
+        height: u64,
+        round: u64,
+        vote_type: VoteType,
+        index: u32,
+    },
+    /// The sender claims to have seen +2/3 of `vote_type` votes for `block_hash`,
+    /// and asks which of those votes the recipient holds.
+    VoteSetMaj23 {
+        height: u64,
+        round: u64,
+        vote_type: VoteType,
         block_hash: String,
+    },
+    /// Answer to `VoteSetMaj23`: the votes for `block_hash` the sender holds,
+    /// indexed by validator position.
+    VoteSetBits {
+        height: u64,
         round: u64,
-    }
+        vote_type: VoteType,
+        block_hash: String,
+        votes: BitArray,
+    },
+    /// Evidence of validator misbehavior, gossiped until it is committed.
+    Evidence {
+        evidence: Evidence,
+    },
+    /// A transaction for the mempool, gossiped until it is in a block.
+    Tx {
+        tx: Tx,
+    },
+    /// The sender is shutting down; peers forget it until it announces itself again.
+    Goodbye,
 }
 
 impl P2PMessage {
@@ -41,9 +135,32 @@ impl P2PMessage {
         match self {
             P2PMessage::PeerInfo { .. } => "PeerInfo",
             P2PMessage::Proposal { .. } => "Proposal",
-            P2PMessage::Prevote { .. } => "Prevote",
-            P2PMessage::Precommit { .. } => "Precommit",
+            P2PMessage::BlockPart { .. } => "BlockPart",
+            P2PMessage::Vote { .. } => "Vote",
             P2PMessage::Commit { .. } => "Commit",
+            P2PMessage::NewRoundStep { .. } => "NewRoundStep",
+            P2PMessage::HasVote { .. } => "HasVote",
+            P2PMessage::VoteSetMaj23 { .. } => "VoteSetMaj23",
+            P2PMessage::VoteSetBits { .. } => "VoteSetBits",
+            P2PMessage::Evidence { .. } => "Evidence",
+            P2PMessage::Tx { .. } => "Tx",
+            P2PMessage::Goodbye => "Goodbye",
+        }
+    }
+
+    /// Returns the channel this message is sent on.
+    pub fn channel(&self) -> Channel {
+        match self {
+            P2PMessage::PeerInfo { .. } | P2PMessage::Goodbye => Channel::Peer,
+            P2PMessage::Proposal { .. } | P2PMessage::NewRoundStep { .. } | P2PMessage::HasVote { .. } => {
+                Channel::Consensus
+            }
+            // A commit carries a signature per validator, so it travels with the block data
+            P2PMessage::BlockPart { .. } | P2PMessage::Commit { .. } => Channel::Data,
+            P2PMessage::Vote { .. } => Channel::Vote,
+            P2PMessage::VoteSetMaj23 { .. } | P2PMessage::VoteSetBits { .. } => Channel::VoteSetBits,
+            P2PMessage::Evidence { .. } => Channel::Evidence,
+            P2PMessage::Tx { .. } => Channel::Mempool,
         }
     }
 }
@@ -1,18 +1,29 @@
-/// The P2P module contains functionality for peer management, message definitions,
-/// and transport (TCP) logic. It exposes high-level functions for starting
-/// listeners and making outbound connections.
+//! The P2P module contains functionality for peer management, message definitions,
+//! and transport logic (TCP, QUIC, or in-memory for tests). It exposes high-level functions for starting
+//! listeners and making outbound connections.
 
 use anyhow::Result;
 use std::net::SocketAddr;
 
+use tracing::warn;
+
 use crate::consensus::ConsensusState;
 
+pub mod codec;
+pub mod key;
+pub mod limits;
+pub mod memory;
 pub mod message;
 pub mod peer;
+pub mod queues;
+pub mod quic;
+pub mod reconnect;
+pub mod score;
+pub mod tcp;
 pub mod transport;
 
-use peer::{Peer};
-use transport::{accept_loop, connect_to_peer};
+use reconnect::{supervise_peer, ReconnectConfig};
+use transport::accept_loop;
 
 /// Start listening for inbound connections using a TCP listener.
 /// Spawns an `accept_loop` to handle connections as they arrive.
@@ -28,29 +39,40 @@ pub async fn start_listening(cs: ConsensusState, listen_addr: &str) -> Result<()
 
 /// Attempt outbound connections to a list of known peer addresses.
 ///
+/// Each address gets its own reconnect supervisor (see [`reconnect`]), which
+/// shutdown waits for.
+/// Addresses in `persistent_peers` are re-dialed forever; the rest are
+/// retried with backoff up to `config.max_attempts` times. An address listed
+/// in both is treated as persistent.
+///
 /// # Arguments
 ///
 /// * `cs` - The shared consensus state.
 /// * `peers` - A list of string addresses of known peers (host:port).
-pub async fn start_outbound_connections(cs: ConsensusState, peers: Vec<&str>) {
-    for peer_addr in peers {
+/// * `persistent_peers` - Addresses (host:port) that must always stay connected.
+/// * `config` - Backoff settings shared by all supervisors.
+pub async fn start_outbound_connections(
+    cs: ConsensusState,
+    peers: Vec<&str>,
+    persistent_peers: Vec<&str>,
+    config: ReconnectConfig,
+) {
+    let persistent = persistent_peers.iter().map(|a| (*a, true));
+    let others = peers
+        .iter()
+        .filter(|a| !persistent_peers.contains(a))
+        .map(|a| (*a, false));
+
+    for (peer_addr, is_persistent) in persistent.chain(others) {
         let addr: SocketAddr = match peer_addr.parse() {
             Ok(a) => a,
             Err(e) => {
-                eprintln!("Invalid peer address {}: {:?}", peer_addr, e);
+                warn!("Invalid peer address {}: {:?}", peer_addr, e);
                 continue;
             }
         };
 
-        // Spawn a task to connect to this peer
-        tokio::spawn({
-            let cs_clone = cs.clone();
-            async move {
-                if let Err(e) = connect_to_peer(cs_clone, addr).await {
-                    eprintln!("Failed to connect to {}: {:?}", addr, e);
-                }
-            }
-        });
+        // Spawn a supervisor task for this peer
+        cs.spawn(supervise_peer(cs.clone(), addr, is_persistent, config.clone()));
     }
 }
-
@@ -1,7 +1,15 @@
 use std::collections::HashMap;
+use std::future::Future;
+use std::net::IpAddr;
 use std::sync::{Arc, Mutex};
 
-use super::message::P2PMessage;
+use anyhow::Result;
+use tokio::sync::broadcast;
+use tokio::sync::broadcast::error::RecvError;
+use tracing::{info, warn};
+
+use super::limits::{ConnectionLimits, ConnectionTracker};
+use super::score::{Misbehavior, PeerScores, ScoreConfig};
 
 /// Represents a peer in the network, storing an ID (often a public key or unique string)
 /// and the address at which the peer listens for inbound connections.
@@ -23,24 +31,68 @@ impl Peer {
     }
 }
 
-/// `PeerManager` holds a collection of known peers (by ID).
+/// `PeerManager` holds a collection of known peers (by ID), the
+/// connection limits that every new connection is checked against,
+/// and a reputation score for every remote address.
 ///
-/// In a real system, you'd also track connection states,
-/// availability, and more advanced metadata about each peer.
+/// In a real system, you'd also track availability and more advanced
+/// metadata about each peer.
 #[derive(Clone)]
 pub struct PeerManager {
     /// A thread-safe map of peer_id -> Peer
     inner: Arc<Mutex<HashMap<String, Peer>>>,
+    /// Live connection counts, checked before a connection is handed to the protocol.
+    connections: ConnectionTracker,
+    /// Reputation scores and active bans, keyed by remote IP.
+    scores: Arc<Mutex<PeerScores>>,
+    /// Scoring thresholds and ban settings.
+    scoring: Arc<ScoreConfig>,
+    /// Announces every new ban to the connections of the banned address.
+    bans: broadcast::Sender<IpAddr>,
+}
+
+impl Default for PeerManager {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl PeerManager {
-    /// Constructs a new, empty peer manager.
+    /// Constructs a new, empty peer manager with the default connection limits
+    /// and scoring settings.
     pub fn new() -> Self {
+        Self::with_config(ConnectionLimits::default(), ScoreConfig::default())
+    }
+
+    /// Constructs a new, empty peer manager enforcing the given connection limits
+    /// and scoring settings.
+    ///
+    /// Persisted bans are not read here; call [`PeerManager::load_bans`] for that.
+    pub fn with_config(limits: ConnectionLimits, scoring: ScoreConfig) -> Self {
         Self {
             inner: Arc::new(Mutex::new(HashMap::new())),
+            connections: ConnectionTracker::new(limits),
+            scores: Arc::new(Mutex::new(PeerScores::new(scoring.decay_interval))),
+            scoring: Arc::new(scoring),
+            bans: broadcast::channel(64).0,
+        }
+    }
+
+    /// Loads persisted bans from the configured ban file, if any.
+    ///
+    /// Returns the number of bans that are still active.
+    pub fn load_bans(&self) -> Result<usize> {
+        match &self.scoring.ban_file {
+            Some(path) => self.scores.lock().unwrap().load_bans(path),
+            None => Ok(0),
         }
     }
 
+    /// Returns the tracker used to reserve connection slots.
+    pub fn connections(&self) -> &ConnectionTracker {
+        &self.connections
+    }
+
     /// Inserts or updates a peer in the internal map.
     ///
     /// # Arguments
@@ -50,6 +102,18 @@ impl PeerManager {
         map.insert(peer.id.clone(), peer);
     }
 
+    /// Forgets the peer with the given ID, returning it if it was known.
+    pub fn remove_peer(&self, id: &str) -> Option<Peer> {
+        let mut map = self.inner.lock().unwrap();
+        map.remove(id)
+    }
+
+    /// Retrieves a **copy** of the peer with the given ID, if known.
+    pub fn get_peer(&self, id: &str) -> Option<Peer> {
+        let map = self.inner.lock().unwrap();
+        map.get(id).cloned()
+    }
+
     /// Retrieves a **copy** of all currently known peers.
     ///
     /// Returns a vector of `Peer` structs.
@@ -57,5 +121,75 @@ impl PeerManager {
         let map = self.inner.lock().unwrap();
         map.values().cloned().collect()
     }
-}
 
+    /// Returns the current reputation score of the peer at `ip`.
+    pub fn score(&self, ip: IpAddr) -> i64 {
+        self.scores.lock().unwrap().score(ip)
+    }
+
+    /// Returns `true` if connections from/to `ip` must be refused.
+    ///
+    /// Unconditional peers are never considered banned.
+    pub fn is_banned(&self, ip: IpAddr) -> bool {
+        !self.connections.is_unconditional(ip) && self.scores.lock().unwrap().is_banned(ip)
+    }
+
+    /// Returns a future that resolves once `ip` gets banned, so that every
+    /// connection to it can be closed, not just the one that caused the ban.
+    ///
+    /// Only bans issued after this call are noticed.
+    pub fn banned(&self, ip: IpAddr) -> impl Future<Output = ()> + Send + 'static {
+        let mut bans = self.bans.subscribe();
+        let peers = self.clone();
+        async move {
+            loop {
+                match bans.recv().await {
+                    Ok(banned) if banned == ip => return,
+                    Ok(_) => {}
+                    // Too many bans at once to see them all; check ours directly.
+                    Err(RecvError::Lagged(_)) if peers.is_banned(ip) => return,
+                    Err(RecvError::Lagged(_)) => {}
+                    Err(RecvError::Closed) => std::future::pending().await,
+                }
+            }
+        }
+    }
+
+    /// Credits the peer at `ip` for a valid message.
+    pub fn report_good(&self, ip: IpAddr) {
+        self.scores
+            .lock()
+            .unwrap()
+            .reward(ip, self.scoring.good_message_reward, self.scoring.max_score);
+    }
+
+    /// Penalizes the peer at `ip` for `misbehavior`.
+    ///
+    /// Returns `true` if the peer has just been banned, in which case the
+    /// caller must drop the connection; the peer's other connections are told
+    /// through [`PeerManager::banned`]. New bans are written to the ban file.
+    pub fn report_misbehavior(&self, ip: IpAddr, misbehavior: Misbehavior) -> bool {
+        if self.connections.is_unconditional(ip) {
+            warn!("Unconditional peer {} misbehaved: {}", ip, misbehavior);
+            return false;
+        }
+
+        let mut scores = self.scores.lock().unwrap();
+        let score = scores.penalize(ip, misbehavior.penalty());
+        warn!("Peer {} misbehaved: {} (score now {})", ip, misbehavior, score);
+        if score > self.scoring.ban_threshold {
+            return false;
+        }
+
+        scores.ban(ip, self.scoring.ban_duration);
+        info!("Banned peer {} for {:?}", ip, self.scoring.ban_duration);
+        // Nobody may be listening, which is fine.
+        let _ = self.bans.send(ip);
+        if let Some(path) = &self.scoring.ban_file {
+            if let Err(e) = scores.save_bans(path) {
+                warn!("Failed to persist ban list to {}: {:?}", path.display(), e);
+            }
+        }
+        true
+    }
+}
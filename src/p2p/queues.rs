@@ -0,0 +1,107 @@
+//! Outgoing message queues, one per connected peer.
+//!
+//! Messages to a peer are written to a connection that stays open, instead of
+//! dialing a new connection per message. A connection serves the queue of the
+//! peer it talks to, registered under the peer's node ID once the peer has
+//! announced itself; if no connection serves a peer yet, one is dialed for it
+//! (see [`ConsensusState::send_to_peer`]). A queue goes away with the
+//! connection serving it.
+//!
+//! [`ConsensusState::send_to_peer`]: crate::consensus::ConsensusState::send_to_peer
+
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+
+use tokio::sync::mpsc::error::TrySendError;
+use tokio::sync::mpsc::{self, Receiver, Sender};
+
+use super::message::P2PMessage;
+
+/// Messages a queue holds before further messages to the peer are dropped.
+pub const QUEUE_CAPACITY: usize = 1024;
+
+#[derive(Default)]
+struct Inner {
+    queues: HashMap<String, Sender<P2PMessage>>,
+    /// Set on shutdown: no queue is created any more.
+    closed: bool,
+}
+
+/// The send queues of all peers, by node ID. Clones share the same queues.
+#[derive(Clone, Default)]
+pub struct SendQueues {
+    inner: Arc<Mutex<Inner>>,
+}
+
+impl SendQueues {
+    /// Creates a queue for `node_id` and returns its receiving end, unless a
+    /// live connection already serves one. The caller's connection must then
+    /// write out everything the receiver yields.
+    pub fn register(&self, node_id: &str) -> Option<Receiver<P2PMessage>> {
+        let mut inner = self.inner.lock().unwrap();
+        if inner.closed || inner.queues.get(node_id).is_some_and(|tx| !tx.is_closed()) {
+            return None;
+        }
+        let (tx, rx) = mpsc::channel(QUEUE_CAPACITY);
+        inner.queues.insert(node_id.to_string(), tx);
+        Some(rx)
+    }
+
+    /// Queues `msg` for `node_id`. Fails if the peer's queue is full, or if no
+    /// connection serves the peer; the message is dropped either way.
+    pub fn push(&self, node_id: &str, msg: P2PMessage) -> Result<(), TrySendError<()>> {
+        let tx = self.inner.lock().unwrap().queues.get(node_id).cloned();
+        match tx {
+            Some(tx) => tx.try_send(msg).map_err(|e| match e {
+                TrySendError::Full(_) => TrySendError::Full(()),
+                TrySendError::Closed(_) => TrySendError::Closed(()),
+            }),
+            None => Err(TrySendError::Closed(())),
+        }
+    }
+
+    /// Drops every queue: connections stop once they have written what was
+    /// already queued, and no new queue is created.
+    pub fn close(&self) {
+        let mut inner = self.inner.lock().unwrap();
+        inner.closed = true;
+        inner.queues.clear();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn goodbye() -> P2PMessage {
+        P2PMessage::Goodbye
+    }
+
+    #[test]
+    fn one_live_queue_per_peer() {
+        let queues = SendQueues::default();
+        let mut rx = queues.register("peer-1").unwrap();
+        assert!(queues.register("peer-1").is_none());
+
+        queues.push("peer-1", goodbye()).unwrap();
+        assert!(matches!(rx.try_recv(), Ok(P2PMessage::Goodbye)));
+        assert!(matches!(queues.push("peer-2", goodbye()), Err(TrySendError::Closed(_))));
+
+        // Once its connection is gone, another one may take over.
+        drop(rx);
+        assert!(matches!(queues.push("peer-1", goodbye()), Err(TrySendError::Closed(_))));
+        assert!(queues.register("peer-1").is_some());
+    }
+
+    #[test]
+    fn closing_drains_and_refuses_new_queues() {
+        let queues = SendQueues::default();
+        let mut rx = queues.register("peer-1").unwrap();
+        queues.push("peer-1", goodbye()).unwrap();
+
+        queues.close();
+        assert!(rx.try_recv().is_ok());
+        assert!(matches!(rx.try_recv(), Err(mpsc::error::TryRecvError::Disconnected)));
+        assert!(queues.register("peer-2").is_none());
+    }
+}
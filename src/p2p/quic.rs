@@ -0,0 +1,454 @@
+//! QUIC implementation of the [`Transport`] trait.
+//!
+//! A single QUIC connection is kept per peer, whichever side opened it, and
+//! every dial opens a new bidirectional stream on it. A connection writes its
+//! queue to one stream per channel (see [`Transport::multiplexes_channels`]).
+//! Because each stream is flow-controlled and retransmitted independently, a
+//! lost packet or a slow reader only stalls its own channel instead of
+//! everything queued behind it on a TCP socket.
+//!
+//! Channels also map onto stream priorities: votes go first, then proposals and
+//! round announcements, then block parts and evidence, then peer bookkeeping.
+//!
+//! The first stream of an accepted connection takes the connection's limits
+//! slot; streams opened on it later, or by the peer on a connection we
+//! dialed, are yielded as substreams of it.
+//!
+//! TLS 1.3 is mandatory in QUIC. Each node presents a self-signed certificate
+//! for its [`NodeKey`], and both sides require the other to prove possession
+//! of the certificate's key. There is no CA: the key *is* the identity. The
+//! node ID derived from the certificate's key is reported by
+//! [`Transport::peer_id`], and a peer whose `PeerInfo` announces a different
+//! ID is refused.
+
+use std::collections::HashMap;
+use std::io;
+use std::net::{Ipv4Addr, Ipv6Addr, SocketAddr};
+use std::sync::{Arc, Mutex};
+
+use anyhow::{anyhow, Result};
+use async_trait::async_trait;
+use ed25519_dalek::pkcs8::DecodePublicKey;
+use ed25519_dalek::VerifyingKey;
+use quinn::crypto::rustls::{QuicClientConfig, QuicServerConfig};
+use quinn::{ClientConfig, Connection, Endpoint, ServerConfig};
+use rustls::client::danger::{HandshakeSignatureValid, ServerCertVerified, ServerCertVerifier};
+use rustls::crypto::{verify_tls13_signature, CryptoProvider};
+use rustls::pki_types::{CertificateDer, PrivateKeyDer, PrivatePkcs8KeyDer, ServerName, UnixTime};
+use rustls::server::danger::{ClientCertVerified, ClientCertVerifier};
+use rustls::{CertificateError, DigitallySignedStruct, DistinguishedName, SignatureScheme};
+use tokio::sync::mpsc;
+use tracing::{debug, warn};
+
+use super::key::{node_id, NodeKey};
+use super::message::Channel;
+use super::transport::{Accepted, BoxConnection, Listener, Transport};
+
+/// ALPN protocol identifier for our P2P protocol.
+const ALPN: &[u8] = b"tm-p2p/1";
+
+/// Name placed in (and expected from) certificates. Peers are identified by
+/// their key, not by a DNS name, so this is a constant.
+const SERVER_NAME: &str = "tendermint-like";
+
+/// Returns the stream priority for a channel (higher is sent first).
+fn channel_priority(channel: Channel) -> i32 {
+    match channel {
+        Channel::Vote | Channel::VoteSetBits => 3,
+        Channel::Consensus => 2,
+        Channel::Data | Channel::Evidence => 1,
+        Channel::Peer | Channel::Mempool => 0,
+    }
+}
+
+/// QUIC transport authenticated with the node key.
+pub struct QuicTransport {
+    server_config: ServerConfig,
+    client_config: ClientConfig,
+    /// Endpoint used for dialing: the listening endpoint once `listen` was called,
+    /// otherwise an ephemeral client-only endpoint created on first dial.
+    endpoint: Mutex<Option<Endpoint>>,
+    /// Established connections, dialed or accepted, reused across dials.
+    connections: Connections,
+    /// Node IDs of the peers we have connections with, by remote address.
+    identities: Identities,
+    /// Where streams the peer opens are yielded, once `listen` was called.
+    incoming: Mutex<Option<mpsc::Sender<Accepted>>>,
+}
+
+type Connections = Arc<Mutex<HashMap<SocketAddr, Connection>>>;
+type Identities = Arc<Mutex<HashMap<SocketAddr, String>>>;
+
+impl QuicTransport {
+    /// Creates a transport whose TLS identity is `node_key`.
+    pub fn new(node_key: &NodeKey) -> Result<Self> {
+        let provider = Arc::new(rustls::crypto::ring::default_provider());
+        let key_der = node_key.to_pkcs8_der()?;
+        let key_pair = rcgen::KeyPair::try_from(key_der.as_slice())?;
+        let cert = rcgen::CertificateParams::new(vec![SERVER_NAME.to_string()])?.self_signed(&key_pair)?;
+        let cert_chain = vec![cert.der().clone()];
+        let private_key = || PrivateKeyDer::Pkcs8(PrivatePkcs8KeyDer::from(key_der.clone()));
+        let verifier = Arc::new(NodeKeyVerifier {
+            provider: provider.clone(),
+        });
+
+        let mut server_tls = rustls::ServerConfig::builder_with_provider(provider.clone())
+            .with_protocol_versions(&[&rustls::version::TLS13])?
+            .with_client_cert_verifier(verifier.clone())
+            .with_single_cert(cert_chain.clone(), private_key())?;
+        server_tls.alpn_protocols = vec![ALPN.to_vec()];
+
+        let mut client_tls = rustls::ClientConfig::builder_with_provider(provider)
+            .with_protocol_versions(&[&rustls::version::TLS13])?
+            .dangerous()
+            .with_custom_certificate_verifier(verifier)
+            .with_client_auth_cert(cert_chain, private_key())?;
+        client_tls.alpn_protocols = vec![ALPN.to_vec()];
+
+        Ok(Self {
+            server_config: ServerConfig::with_crypto(Arc::new(QuicServerConfig::try_from(server_tls)?)),
+            client_config: ClientConfig::new(Arc::new(QuicClientConfig::try_from(client_tls)?)),
+            endpoint: Mutex::new(None),
+            connections: Connections::default(),
+            identities: Identities::default(),
+            incoming: Mutex::new(None),
+        })
+    }
+
+    /// Returns the endpoint to dial `addr` from, creating a client endpoint if needed.
+    fn dial_endpoint(&self, addr: SocketAddr) -> Result<Endpoint> {
+        let mut endpoint = self.endpoint.lock().unwrap();
+        if let Some(ep) = endpoint.as_ref() {
+            return Ok(ep.clone());
+        }
+        let bind: SocketAddr = if addr.is_ipv4() {
+            (Ipv4Addr::UNSPECIFIED, 0).into()
+        } else {
+            (Ipv6Addr::UNSPECIFIED, 0).into()
+        };
+        let mut ep = Endpoint::client(bind)?;
+        ep.set_default_client_config(self.client_config.clone());
+        *endpoint = Some(ep.clone());
+        Ok(ep)
+    }
+
+    /// Returns a live connection to `addr`, reusing a cached one if possible.
+    async fn connection(&self, addr: SocketAddr) -> Result<Connection> {
+        let cached = self.connections.lock().unwrap().get(&addr).cloned();
+        if let Some(conn) = cached {
+            if conn.close_reason().is_none() {
+                return Ok(conn);
+            }
+        }
+
+        let conn = self.dial_endpoint(addr)?.connect(addr, SERVER_NAME)?.await?;
+        debug!("QUIC connection established to {}", addr);
+        record_identity(&self.identities, &conn);
+        self.connections.lock().unwrap().insert(addr, conn.clone());
+        if let Some(tx) = self.incoming.lock().unwrap().clone() {
+            tokio::spawn(accept_streams(conn.clone(), tx, false));
+        }
+        Ok(conn)
+    }
+}
+
+#[async_trait]
+impl Transport for QuicTransport {
+    async fn listen(&self, addr: SocketAddr) -> Result<Box<dyn Listener>> {
+        let mut endpoint = Endpoint::server(self.server_config.clone(), addr)?;
+        endpoint.set_default_client_config(self.client_config.clone());
+        let local_addr = endpoint.local_addr()?;
+        *self.endpoint.lock().unwrap() = Some(endpoint.clone());
+
+        let (tx, rx) = mpsc::channel(128);
+        *self.incoming.lock().unwrap() = Some(tx.clone());
+        tokio::spawn(accept_connections(
+            endpoint,
+            self.connections.clone(),
+            self.identities.clone(),
+            tx,
+        ));
+        Ok(Box::new(QuicListener {
+            local_addr,
+            incoming: rx,
+        }))
+    }
+
+    async fn dial(&self, addr: SocketAddr) -> Result<BoxConnection> {
+        self.dial_channel(addr, Channel::Peer).await
+    }
+
+    async fn dial_channel(&self, addr: SocketAddr, channel: Channel) -> Result<BoxConnection> {
+        let conn = self.connection(addr).await?;
+        let (send, recv) = conn.open_bi().await?;
+        send.set_priority(channel_priority(channel))?;
+        Ok(Box::new(tokio::io::join(recv, send)))
+    }
+
+    fn multiplexes_channels(&self) -> bool {
+        true
+    }
+
+    fn peer_id(&self, addr: SocketAddr) -> Option<String> {
+        self.identities.lock().unwrap().get(&addr).cloned()
+    }
+}
+
+/// Remembers the node ID of `conn`'s peer, as proven during the TLS handshake.
+fn record_identity(identities: &Identities, conn: &Connection) {
+    let id = conn
+        .peer_identity()
+        .and_then(|identity| identity.downcast::<Vec<CertificateDer<'static>>>().ok())
+        .and_then(|certs| certs.first().and_then(|cert| cert_node_id(cert).ok()));
+    if let Some(id) = id {
+        identities.lock().unwrap().insert(conn.remote_address(), id);
+    }
+}
+
+/// Returns the node ID of the Ed25519 key `cert` was issued for.
+fn cert_node_id(cert: &CertificateDer<'_>) -> Result<String, rustls::Error> {
+    let cert = webpki::EndEntityCert::try_from(cert)
+        .map_err(|_| rustls::Error::InvalidCertificate(CertificateError::BadEncoding))?;
+    let key = VerifyingKey::from_public_key_der(&cert.subject_public_key_info())
+        .map_err(|_| rustls::Error::InvalidCertificate(CertificateError::ApplicationVerificationFailure))?;
+    Ok(node_id(&key))
+}
+
+/// Accepts QUIC connections on `endpoint`, keeps each for dials to its peer
+/// (unless one is already open) and spawns a stream acceptor for it.
+async fn accept_connections(
+    endpoint: Endpoint,
+    connections: Connections,
+    identities: Identities,
+    tx: mpsc::Sender<Accepted>,
+) {
+    while let Some(incoming) = endpoint.accept().await {
+        if tx.is_closed() {
+            break;
+        }
+        let tx = tx.clone();
+        let connections = connections.clone();
+        let identities = identities.clone();
+        tokio::spawn(async move {
+            let conn = match incoming.await {
+                Ok(c) => c,
+                Err(e) => {
+                    warn!("QUIC handshake failed: {:?}", e);
+                    return;
+                }
+            };
+            record_identity(&identities, &conn);
+            connections
+                .lock()
+                .unwrap()
+                .entry(conn.remote_address())
+                .and_modify(|open| {
+                    if open.close_reason().is_some() {
+                        *open = conn.clone();
+                    }
+                })
+                .or_insert_with(|| conn.clone());
+            accept_streams(conn, tx, true).await;
+        });
+    }
+}
+
+/// Forwards every bidirectional stream the peer opens on `conn` as a new
+/// connection. Only the first stream of an accepted connection (`accepted`)
+/// is not a substream.
+async fn accept_streams(conn: Connection, tx: mpsc::Sender<Accepted>, accepted: bool) {
+    let remote_addr = conn.remote_address();
+    let mut substream = !accepted;
+    while let Ok((send, recv)) = conn.accept_bi().await {
+        let stream = Accepted {
+            conn: Box::new(tokio::io::join(recv, send)),
+            remote_addr,
+            substream,
+        };
+        substream = true;
+        if tx.send(stream).await.is_err() {
+            break;
+        }
+    }
+}
+
+/// Yields each inbound QUIC stream as a connection.
+struct QuicListener {
+    local_addr: SocketAddr,
+    incoming: mpsc::Receiver<Accepted>,
+}
+
+#[async_trait]
+impl Listener for QuicListener {
+    async fn accept(&mut self) -> Result<Accepted> {
+        self.incoming
+            .recv()
+            .await
+            .ok_or_else(|| anyhow!(io::Error::new(io::ErrorKind::NotConnected, "QUIC endpoint closed")))
+    }
+
+    fn local_addr(&self) -> SocketAddr {
+        self.local_addr
+    }
+}
+
+/// Accepts any self-signed certificate for an Ed25519 key, as long as the
+/// peer proves it holds that key during the TLS 1.3 handshake. Which node the
+/// key belongs to is checked against the peer's `PeerInfo` once connected.
+#[derive(Debug)]
+struct NodeKeyVerifier {
+    provider: Arc<CryptoProvider>,
+}
+
+impl NodeKeyVerifier {
+    fn verify_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer<'_>,
+        dss: &DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, rustls::Error> {
+        verify_tls13_signature(message, cert, dss, &self.provider.signature_verification_algorithms)
+    }
+}
+
+impl ServerCertVerifier for NodeKeyVerifier {
+    fn verify_server_cert(
+        &self,
+        end_entity: &CertificateDer<'_>,
+        _intermediates: &[CertificateDer<'_>],
+        _server_name: &ServerName<'_>,
+        _ocsp_response: &[u8],
+        _now: UnixTime,
+    ) -> Result<ServerCertVerified, rustls::Error> {
+        cert_node_id(end_entity)?;
+        Ok(ServerCertVerified::assertion())
+    }
+
+    fn verify_tls12_signature(
+        &self,
+        _message: &[u8],
+        _cert: &CertificateDer<'_>,
+        _dss: &DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, rustls::Error> {
+        Err(rustls::Error::PeerIncompatible(
+            rustls::PeerIncompatible::Tls12NotOffered,
+        ))
+    }
+
+    fn verify_tls13_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer<'_>,
+        dss: &DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, rustls::Error> {
+        self.verify_signature(message, cert, dss)
+    }
+
+    fn supported_verify_schemes(&self) -> Vec<SignatureScheme> {
+        vec![SignatureScheme::ED25519]
+    }
+}
+
+impl ClientCertVerifier for NodeKeyVerifier {
+    fn root_hint_subjects(&self) -> &[DistinguishedName] {
+        &[]
+    }
+
+    fn verify_client_cert(
+        &self,
+        end_entity: &CertificateDer<'_>,
+        _intermediates: &[CertificateDer<'_>],
+        _now: UnixTime,
+    ) -> Result<ClientCertVerified, rustls::Error> {
+        cert_node_id(end_entity)?;
+        Ok(ClientCertVerified::assertion())
+    }
+
+    fn verify_tls12_signature(
+        &self,
+        _message: &[u8],
+        _cert: &CertificateDer<'_>,
+        _dss: &DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, rustls::Error> {
+        Err(rustls::Error::PeerIncompatible(
+            rustls::PeerIncompatible::Tls12NotOffered,
+        ))
+    }
+
+    fn verify_tls13_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer<'_>,
+        dss: &DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, rustls::Error> {
+        self.verify_signature(message, cert, dss)
+    }
+
+    fn supported_verify_schemes(&self) -> Vec<SignatureScheme> {
+        vec![SignatureScheme::ED25519]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::time::Duration;
+
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+    use crate::consensus::ConsensusState;
+    use crate::p2p::transport::{accept_loop, send_message};
+
+    #[tokio::test]
+    async fn streams_flow_between_nodes_on_localhost() {
+        let server = QuicTransport::new(&NodeKey::generate()).unwrap();
+        let client = QuicTransport::new(&NodeKey::generate()).unwrap();
+
+        let mut listener = server.listen("127.0.0.1:0".parse().unwrap()).await.unwrap();
+        let addr = listener.local_addr();
+
+        // Two channels share one QUIC connection but get separate streams.
+        for (channel, payload) in [(Channel::Vote, &b"vote"[..]), (Channel::Peer, &b"peer"[..])] {
+            let mut out = client.dial_channel(addr, channel).await.unwrap();
+            out.write_all(payload).await.unwrap();
+            out.shutdown().await.unwrap();
+
+            let mut inbound = listener.accept().await.unwrap().conn;
+            let mut received = Vec::new();
+            inbound.read_to_end(&mut received).await.unwrap();
+            assert_eq!(received, payload);
+        }
+        assert_eq!(client.connections.lock().unwrap().len(), 1);
+    }
+
+    #[tokio::test]
+    async fn peer_announcing_another_node_id_is_refused() {
+        let server_key = NodeKey::generate();
+        let client_key = NodeKey::generate();
+        let addr = std::net::UdpSocket::bind("127.0.0.1:0").unwrap().local_addr().unwrap();
+        let mut server = ConsensusState::new(server_key.node_id(), addr.to_string());
+        server.transport = Arc::new(QuicTransport::new(&server_key).unwrap());
+        tokio::spawn(accept_loop(server.clone(), addr));
+
+        let client_node = |node_id: String| {
+            let mut cs = ConsensusState::new(node_id, "127.0.0.1:1".into());
+            cs.transport = Arc::new(QuicTransport::new(&client_key).unwrap());
+            cs
+        };
+        let client = client_node(client_key.node_id());
+        tokio::time::timeout(Duration::from_secs(5), async {
+            while send_message(&client, addr, &client.peer_info()).await.is_err() {
+                tokio::time::sleep(Duration::from_millis(10)).await;
+            }
+        })
+        .await
+        .expect("the key's own node ID is accepted");
+        assert_eq!(client.transport.peer_id(addr), Some(server_key.node_id()));
+        assert!(server.peer_manager().get_peer(&client_key.node_id()).is_some());
+
+        let impostor = client_node(NodeKey::generate().node_id());
+        assert!(send_message(&impostor, addr, &impostor.peer_info()).await.is_err());
+        assert!(server.peer_manager().get_peer(&impostor.node_id).is_none());
+    }
+}
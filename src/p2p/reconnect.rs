@@ -0,0 +1,220 @@
+//! Outbound dialing with automatic reconnection.
+//!
+//! Every outbound peer gets its own supervisor task. The supervisor dials the
+//! peer, keeps the connection running until it closes, and then decides whether
+//! to try again:
+//! - **Persistent peers** are re-dialed forever, both after failed dials and
+//!   after an established connection drops.
+//! - **Other peers** are retried only until `max_attempts` consecutive dials
+//!   have failed, and are not re-dialed once a connection closes.
+//!
+//! Delays between attempts grow exponentially and are jittered so that many
+//! nodes restarting together don't dial each other in lock-step. Every
+//! supervisor stops when the node shuts down.
+
+use std::net::SocketAddr;
+use std::time::Duration;
+
+use rand::Rng;
+use tracing::{info, warn};
+
+use crate::consensus::ConsensusState;
+use super::transport::connect_to_peer;
+
+/// Tuning knobs for the reconnect supervisor.
+#[derive(Debug, Clone)]
+pub struct ReconnectConfig {
+    /// Delay before the first retry. Doubles with each consecutive failure.
+    pub base_delay: Duration,
+    /// Upper bound on the (pre-jitter) delay between attempts.
+    pub max_delay: Duration,
+    /// How many consecutive failed dials a non-persistent peer gets before we give up.
+    pub max_attempts: u32,
+}
+
+impl Default for ReconnectConfig {
+    fn default() -> Self {
+        Self {
+            base_delay: Duration::from_millis(500),
+            max_delay: Duration::from_secs(60),
+            max_attempts: 10,
+        }
+    }
+}
+
+impl ReconnectConfig {
+    /// Returns how long to wait before the next dial, given the number of
+    /// consecutive failures so far (starting at 1).
+    ///
+    /// The delay is `base_delay * 2^(attempt - 1)`, capped at `max_delay`, and then
+    /// randomized to somewhere between half and all of that value.
+    pub fn backoff(&self, attempt: u32) -> Duration {
+        let exp = attempt.saturating_sub(1).min(31);
+        let delay = self
+            .base_delay
+            .saturating_mul(1u32 << exp)
+            .min(self.max_delay);
+        let half = delay / 2;
+        half + rand::thread_rng().gen_range(Duration::ZERO..=half)
+    }
+}
+
+/// Dials `addr` and keeps it connected according to the rules described
+/// in the module documentation. Returns when the supervisor gives up, which
+/// never happens for a persistent peer, or when the node shuts down.
+///
+/// # Arguments
+///
+/// * `cs` - The shared consensus state.
+/// * `addr` - The remote peer's address.
+/// * `persistent` - Whether this peer should be re-dialed forever.
+/// * `config` - Backoff settings.
+pub async fn supervise_peer(
+    cs: ConsensusState,
+    addr: SocketAddr,
+    persistent: bool,
+    config: ReconnectConfig,
+) {
+    let mut failures: u32 = 0;
+    let shutdown = cs.shutdown_token();
+
+    loop {
+        let result = tokio::select! {
+            _ = shutdown.cancelled() => return,
+            result = connect_to_peer(cs.clone(), addr, None) => result,
+        };
+        match result {
+            Ok(()) => {
+                if !persistent {
+                    info!("Connection to {} closed", addr);
+                    return;
+                }
+                info!("Connection to persistent peer {} closed, re-dialing", addr);
+                failures = 0;
+            }
+            Err(e) => {
+                failures += 1;
+                if !persistent && failures >= config.max_attempts {
+                    warn!("Giving up on {} after {} failed attempts: {:?}", addr, failures, e);
+                    return;
+                }
+                warn!("Failed to connect to {} (attempt {}): {:?}", addr, failures, e);
+            }
+        }
+
+        tokio::select! {
+            _ = shutdown.cancelled() => return,
+            _ = tokio::time::sleep(config.backoff(failures.max(1))) => {}
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::sync::atomic::{AtomicU32, Ordering};
+    use std::sync::Arc;
+
+    use anyhow::Result;
+    use async_trait::async_trait;
+
+    use crate::p2p::memory::{MemoryNetwork, MemoryTransport};
+    use crate::p2p::transport::{BoxConnection, Listener, Transport};
+
+    const PEER: &str = "10.0.0.2:26656";
+
+    /// A memory transport that counts dials.
+    struct CountingTransport {
+        inner: MemoryTransport,
+        dials: Arc<AtomicU32>,
+    }
+
+    #[async_trait]
+    impl Transport for CountingTransport {
+        async fn listen(&self, addr: SocketAddr) -> Result<Box<dyn Listener>> {
+            self.inner.listen(addr).await
+        }
+
+        async fn dial(&self, addr: SocketAddr) -> Result<BoxConnection> {
+            self.dials.fetch_add(1, Ordering::SeqCst);
+            self.inner.dial(addr).await
+        }
+    }
+
+    /// Returns a node dialing from 10.0.0.1 and its dial counter.
+    fn node(network: &MemoryNetwork) -> (ConsensusState, Arc<AtomicU32>) {
+        let dials = Arc::new(AtomicU32::new(0));
+        let mut cs = ConsensusState::new("node-a".into(), "10.0.0.1:26656".into());
+        cs.transport = Arc::new(CountingTransport {
+            inner: network.transport("10.0.0.1:26656".parse().unwrap()),
+            dials: dials.clone(),
+        });
+        (cs, dials)
+    }
+
+    fn config() -> ReconnectConfig {
+        ReconnectConfig {
+            base_delay: Duration::from_millis(100),
+            max_delay: Duration::from_secs(1),
+            max_attempts: 3,
+        }
+    }
+
+    #[test]
+    fn backoff_is_jittered_within_half_of_the_capped_delay() {
+        let config = config();
+        for attempt in 1..=40 {
+            let delay = config
+                .base_delay
+                .saturating_mul(1 << (attempt - 1).min(31))
+                .min(config.max_delay);
+            for _ in 0..50 {
+                let backoff = config.backoff(attempt);
+                assert!(backoff >= delay / 2 && backoff <= delay, "attempt {}: {:?}", attempt, backoff);
+                assert!(backoff <= config.max_delay);
+            }
+        }
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn non_persistent_peer_is_given_up_after_max_attempts() {
+        let network = MemoryNetwork::new();
+        let (cs, dials) = node(&network);
+
+        tokio::time::timeout(
+            Duration::from_secs(60),
+            supervise_peer(cs, PEER.parse().unwrap(), false, config()),
+        )
+        .await
+        .expect("the supervisor gives up");
+        assert_eq!(dials.load(Ordering::SeqCst), config().max_attempts);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn persistent_peer_is_redialed_until_it_comes_up() {
+        let network = MemoryNetwork::new();
+        let (cs, dials) = node(&network);
+        let supervisor = tokio::spawn(supervise_peer(cs.clone(), PEER.parse().unwrap(), true, config()));
+
+        tokio::time::sleep(Duration::from_secs(10)).await;
+        assert!(!supervisor.is_finished());
+        assert!(dials.load(Ordering::SeqCst) > config().max_attempts);
+
+        // Once the peer listens, the next dial gets through.
+        let mut listener = network
+            .transport(PEER.parse().unwrap())
+            .listen(PEER.parse().unwrap())
+            .await
+            .unwrap();
+        let from = tokio::time::timeout(Duration::from_secs(2), listener.accept())
+            .await
+            .expect("the peer is re-dialed")
+            .unwrap()
+            .remote_addr;
+        assert_eq!(from.ip(), "10.0.0.1".parse::<std::net::IpAddr>().unwrap());
+
+        cs.shutdown_token().cancel();
+        supervisor.await.unwrap();
+    }
+}
@@ -0,0 +1,305 @@
+//! Peer reputation scoring and time-limited bans.
+//!
+//! Scores are tracked per remote IP address, since that is all we know about a
+//! peer before its first message and it is what the accept loop can refuse.
+//! Every peer starts at zero. Protocol violations subtract a penalty that depends
+//! on how serious they are, and each well-formed, valid message adds a small
+//! reward up to `max_score`, and a negative score creeps back towards zero by one
+//! point per `decay_interval`, so old offences are eventually forgiven. Once a
+//! score falls to `ban_threshold` or below, the address is banned for
+//! `ban_duration` and all its connections are dropped.
+//!
+//! Bans are written to `ban_file` (if configured) so that they survive restarts.
+
+use std::collections::HashMap;
+use std::fmt;
+use std::net::IpAddr;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+use anyhow::Result;
+
+/// A protocol violation committed by a peer.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Misbehavior {
+    /// A message carried a signature that does not verify.
+    BadSignature,
+    /// A proposal or block failed validation.
+    InvalidBlock,
+    /// A vote failed validation (e.g. the voter is not a validator).
+    InvalidVote,
+    /// Evidence of validator misbehavior failed verification.
+    InvalidEvidence,
+    /// A frame exceeded the maximum allowed message size.
+    OversizedMessage,
+    /// A frame could not be decoded into a `P2PMessage`.
+    MalformedMessage,
+    /// The peer sent a response to a request we never made.
+    UnsolicitedResponse,
+    /// The peer sent a message before announcing itself with a `PeerInfo`.
+    MissingHandshake,
+}
+
+impl Misbehavior {
+    /// How many points this violation costs.
+    pub fn penalty(&self) -> i64 {
+        match self {
+            Misbehavior::BadSignature => 100,
+            Misbehavior::InvalidBlock => 50,
+            Misbehavior::OversizedMessage => 50,
+            Misbehavior::InvalidVote => 20,
+            Misbehavior::InvalidEvidence => 50,
+            Misbehavior::MalformedMessage => 20,
+            Misbehavior::UnsolicitedResponse => 10,
+            Misbehavior::MissingHandshake => 20,
+        }
+    }
+}
+
+impl fmt::Display for Misbehavior {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let s = match self {
+            Misbehavior::BadSignature => "bad signature",
+            Misbehavior::InvalidBlock => "invalid block",
+            Misbehavior::InvalidVote => "invalid vote",
+            Misbehavior::InvalidEvidence => "invalid evidence",
+            Misbehavior::OversizedMessage => "oversized message",
+            Misbehavior::MalformedMessage => "malformed message",
+            Misbehavior::UnsolicitedResponse => "unsolicited response",
+            Misbehavior::MissingHandshake => "message before handshake",
+        };
+        f.write_str(s)
+    }
+}
+
+impl std::error::Error for Misbehavior {}
+
+/// Scoring thresholds and ban settings.
+#[derive(Debug, Clone)]
+pub struct ScoreConfig {
+    /// Scores never rise above this value.
+    pub max_score: i64,
+    /// Points added for each valid message.
+    pub good_message_reward: i64,
+    /// Time for a negative score to recover by one point. Zero disables decay.
+    pub decay_interval: Duration,
+    /// A peer whose score drops to this value or below is banned.
+    pub ban_threshold: i64,
+    /// How long a ban lasts.
+    pub ban_duration: Duration,
+    /// Where bans are persisted. `None` keeps them in memory only.
+    pub ban_file: Option<PathBuf>,
+}
+
+impl Default for ScoreConfig {
+    fn default() -> Self {
+        Self {
+            max_score: 100,
+            good_message_reward: 1,
+            decay_interval: Duration::from_secs(60),
+            ban_threshold: -100,
+            ban_duration: Duration::from_secs(24 * 60 * 60),
+            ban_file: None,
+        }
+    }
+}
+
+/// A score and when it last changed.
+#[derive(Debug, Clone, Copy)]
+struct Score {
+    value: i64,
+    updated: Instant,
+}
+
+impl Score {
+    /// Returns the value at `now`, after a negative value has decayed by one
+    /// point per `interval`.
+    fn at(&self, now: Instant, interval: Duration) -> i64 {
+        if self.value >= 0 || interval.is_zero() {
+            return self.value;
+        }
+        let steps = now.saturating_duration_since(self.updated).as_nanos() / interval.as_nanos();
+        self.value.saturating_add(steps.min(i64::MAX as u128) as i64).min(0)
+    }
+}
+
+/// Per-address scores and active bans.
+#[derive(Debug)]
+pub struct PeerScores {
+    scores: HashMap<IpAddr, Score>,
+    /// Address -> ban expiry, in seconds since the Unix epoch.
+    bans: HashMap<IpAddr, u64>,
+    /// See [`ScoreConfig::decay_interval`].
+    decay_interval: Duration,
+}
+
+impl Default for PeerScores {
+    fn default() -> Self {
+        Self::new(ScoreConfig::default().decay_interval)
+    }
+}
+
+impl PeerScores {
+    /// Creates an empty table whose negative scores decay by one point per `decay_interval`.
+    pub fn new(decay_interval: Duration) -> Self {
+        Self {
+            scores: HashMap::new(),
+            bans: HashMap::new(),
+            decay_interval,
+        }
+    }
+
+    /// Returns the current score of `ip` (zero if unknown).
+    pub fn score(&self, ip: IpAddr) -> i64 {
+        self.scores
+            .get(&ip)
+            .map_or(0, |s| s.at(Instant::now(), self.decay_interval))
+    }
+
+    /// Adds `delta` to the current score of `ip`, capped at `max`, and returns the new score.
+    fn add(&mut self, ip: IpAddr, delta: i64, max: i64) -> i64 {
+        let value = (self.score(ip) + delta).min(max);
+        self.scores.insert(ip, Score { value, updated: Instant::now() });
+        value
+    }
+
+    /// Adds `reward` to the score of `ip`, capped at `max`.
+    pub fn reward(&mut self, ip: IpAddr, reward: i64, max: i64) {
+        self.add(ip, reward, max);
+    }
+
+    /// Subtracts `penalty` from the score of `ip` and returns the new score.
+    pub fn penalize(&mut self, ip: IpAddr, penalty: i64) -> i64 {
+        self.add(ip, -penalty, i64::MAX)
+    }
+
+    /// Bans `ip` until `duration` from now and resets its score.
+    pub fn ban(&mut self, ip: IpAddr, duration: Duration) {
+        self.scores.remove(&ip);
+        self.bans.insert(ip, unix_now() + duration.as_secs());
+    }
+
+    /// Returns `true` if `ip` is currently banned. Expired bans are lifted.
+    pub fn is_banned(&mut self, ip: IpAddr) -> bool {
+        match self.bans.get(&ip) {
+            Some(&until) if until > unix_now() => true,
+            Some(_) => {
+                self.bans.remove(&ip);
+                false
+            }
+            None => false,
+        }
+    }
+
+    /// Loads bans from `path`, skipping any that have already expired.
+    /// A missing file is not an error.
+    pub fn load_bans(&mut self, path: &Path) -> Result<usize> {
+        let data = match std::fs::read(path) {
+            Ok(d) => d,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(0),
+            Err(e) => return Err(e.into()),
+        };
+        let stored: HashMap<IpAddr, u64> = serde_json::from_slice(&data)?;
+        let now = unix_now();
+        let before = self.bans.len();
+        self.bans.extend(stored.into_iter().filter(|(_, until)| *until > now));
+        Ok(self.bans.len() - before)
+    }
+
+    /// Writes all unexpired bans to `path`.
+    pub fn save_bans(&mut self, path: &Path) -> Result<()> {
+        let now = unix_now();
+        self.bans.retain(|_, until| *until > now);
+        let data = serde_json::to_vec_pretty(&self.bans)?;
+        std::fs::write(path, data)?;
+        Ok(())
+    }
+}
+
+fn unix_now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ip(s: &str) -> IpAddr {
+        s.parse().unwrap()
+    }
+
+    #[test]
+    fn rewards_are_capped() {
+        let mut scores = PeerScores::default();
+        for _ in 0..10 {
+            scores.reward(ip("10.0.0.1"), 3, 20);
+        }
+        assert_eq!(scores.score(ip("10.0.0.1")), 20);
+        assert_eq!(scores.penalize(ip("10.0.0.1"), 50), -30);
+    }
+
+    #[test]
+    fn negative_scores_decay_towards_zero() {
+        let mut scores = PeerScores::new(Duration::from_millis(5));
+        scores.penalize(ip("10.0.0.1"), 1_000);
+        scores.reward(ip("10.0.0.2"), 10, 100);
+        std::thread::sleep(Duration::from_millis(50));
+
+        let decayed = scores.score(ip("10.0.0.1"));
+        assert!(decayed > -1_000 && decayed < 0, "score {}", decayed);
+        assert_eq!(scores.score(ip("10.0.0.2")), 10);
+
+        // A score that has recovered stays at zero.
+        let mut scores = PeerScores::new(Duration::from_millis(1));
+        scores.penalize(ip("10.0.0.1"), 2);
+        std::thread::sleep(Duration::from_millis(20));
+        assert_eq!(scores.score(ip("10.0.0.1")), 0);
+    }
+
+    #[test]
+    fn zero_decay_interval_keeps_scores() {
+        let mut scores = PeerScores::new(Duration::ZERO);
+        scores.penalize(ip("10.0.0.1"), 5);
+        std::thread::sleep(Duration::from_millis(5));
+        assert_eq!(scores.score(ip("10.0.0.1")), -5);
+    }
+
+    #[test]
+    fn bans_expire() {
+        let mut scores = PeerScores::default();
+        scores.penalize(ip("10.0.0.1"), 500);
+        scores.ban(ip("10.0.0.1"), Duration::from_secs(3600));
+        assert!(scores.is_banned(ip("10.0.0.1")));
+        assert_eq!(scores.score(ip("10.0.0.1")), 0);
+
+        scores.ban(ip("10.0.0.2"), Duration::ZERO);
+        assert!(!scores.is_banned(ip("10.0.0.2")));
+        assert!(!scores.bans.contains_key(&ip("10.0.0.2")));
+    }
+
+    #[test]
+    fn bans_survive_a_save_and_load() {
+        let path = std::env::temp_dir().join(format!("tmlike-bans-{}.json", std::process::id()));
+        let mut scores = PeerScores::default();
+        scores.ban(ip("10.0.0.1"), Duration::from_secs(3600));
+        scores.ban(ip("2001:db8::1"), Duration::from_secs(60));
+        scores.save_bans(&path).unwrap();
+
+        let mut loaded = PeerScores::default();
+        assert_eq!(loaded.load_bans(&path).unwrap(), 2);
+        assert!(loaded.is_banned(ip("10.0.0.1")));
+        assert!(loaded.is_banned(ip("2001:db8::1")));
+        assert!(!loaded.is_banned(ip("10.0.0.2")));
+
+        // Bans that expired while the node was down are dropped on load.
+        let expired = HashMap::from([(ip("10.0.0.3"), unix_now() - 1)]);
+        std::fs::write(&path, serde_json::to_vec(&expired).unwrap()).unwrap();
+        assert_eq!(PeerScores::default().load_bans(&path).unwrap(), 0);
+
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(PeerScores::default().load_bans(&path).unwrap(), 0);
+    }
+}
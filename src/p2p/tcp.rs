@@ -0,0 +1,48 @@
+//! TCP implementation of the [`Transport`] trait.
+
+use std::net::SocketAddr;
+
+use anyhow::Result;
+use async_trait::async_trait;
+use tokio::net::{TcpListener, TcpStream};
+
+use super::transport::{Accepted, BoxConnection, Listener, Transport};
+
+/// Plain TCP transport using tokio sockets.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct TcpTransport;
+
+#[async_trait]
+impl Transport for TcpTransport {
+    async fn listen(&self, addr: SocketAddr) -> Result<Box<dyn Listener>> {
+        let listener = TcpListener::bind(addr).await?;
+        Ok(Box::new(TcpTransportListener { listener }))
+    }
+
+    async fn dial(&self, addr: SocketAddr) -> Result<BoxConnection> {
+        let socket = TcpStream::connect(addr).await?;
+        Ok(Box::new(socket))
+    }
+}
+
+/// A bound TCP listener.
+struct TcpTransportListener {
+    listener: TcpListener,
+}
+
+#[async_trait]
+impl Listener for TcpTransportListener {
+    async fn accept(&mut self) -> Result<Accepted> {
+        let (socket, remote_addr) = self.listener.accept().await?;
+        Ok(Accepted {
+            conn: Box::new(socket),
+            remote_addr,
+            substream: false,
+        })
+    }
+
+    fn local_addr(&self) -> SocketAddr {
+        // A bound socket always has a local address.
+        self.listener.local_addr().expect("bound TCP listener has a local address")
+    }
+}
@@ -1,101 +1,915 @@
+//! Transport-agnostic connection handling.
+//!
+//! The [`Transport`] trait abstracts how bytes get between nodes: it can bind a
+//! [`Listener`] and dial remote addresses, yielding byte streams. Everything
+//! above it (limits, framing, decoding, scoring) is shared by all transports.
+//! Implementations live in [`super::tcp`], [`super::quic`] and [`super::memory`].
+
+use std::collections::HashMap;
 use std::net::SocketAddr;
 
-use anyhow::Result;
-use tokio::net::{TcpListener, TcpStream};
+use anyhow::{bail, Result};
+use async_trait::async_trait;
+use bytes::Bytes;
+use tokio::io::{AsyncRead, AsyncWrite};
+use tokio::sync::mpsc::{self, error::TrySendError, Receiver, Sender};
 use tokio_util::codec::{Framed, LengthDelimitedCodec};
 use futures_util::SinkExt;
 use futures_util::StreamExt;
 
-use tracing::{debug, info, warn};
+use tracing::field::Empty;
+use tracing::{debug, info, instrument, warn, Instrument, Span};
 
 use crate::consensus::ConsensusState;
-use super::message::P2PMessage;
+use super::codec::{self, FrameError};
+use super::limits::Direction;
+use super::message::{Channel, P2PMessage};
+use super::peer::PeerManager;
+use super::queues::QUEUE_CAPACITY;
+use super::score::Misbehavior;
+
+/// A bidirectional byte stream to a peer.
+pub trait Connection: AsyncRead + AsyncWrite + Unpin + Send {}
+
+impl<T: AsyncRead + AsyncWrite + Unpin + Send> Connection for T {}
+
+/// A boxed connection, as handed out by every transport.
+pub type BoxConnection = Box<dyn Connection>;
+
+/// An inbound connection yielded by a [`Listener`].
+pub struct Accepted {
+    pub conn: BoxConnection,
+    pub remote_addr: SocketAddr,
+    /// Set for a further stream of a multiplexed connection that is already
+    /// established, which is covered by that connection's limits slot.
+    pub substream: bool,
+}
+
+/// A bound listener that yields inbound connections.
+#[async_trait]
+pub trait Listener: Send {
+    /// Waits for the next inbound connection.
+    async fn accept(&mut self) -> Result<Accepted>;
+
+    /// Returns the address this listener is bound to.
+    fn local_addr(&self) -> SocketAddr;
+}
+
+/// A way of establishing byte streams between nodes.
+#[async_trait]
+pub trait Transport: Send + Sync {
+    /// Binds a listener on `addr`.
+    async fn listen(&self, addr: SocketAddr) -> Result<Box<dyn Listener>>;
+
+    /// Opens a connection to the node listening on `addr`.
+    async fn dial(&self, addr: SocketAddr) -> Result<BoxConnection>;
 
-/// Accepts inbound TCP connections on the specified `addr`.
-/// Each connection is handled in a new task by `handle_connection`.
+    /// Opens a connection to `addr` for traffic on `channel`.
+    ///
+    /// Multiplexing transports can map channels onto separate streams;
+    /// the default simply dials a new connection.
+    async fn dial_channel(&self, addr: SocketAddr, channel: Channel) -> Result<BoxConnection> {
+        let _ = channel;
+        self.dial(addr).await
+    }
+
+    /// Whether [`dial_channel`](Transport::dial_channel) opens a stream of the
+    /// connection already established with the peer rather than a connection
+    /// of its own. If so, connections write queued messages to one such
+    /// stream per channel, so a backlog on one channel doesn't hold up the others.
+    fn multiplexes_channels(&self) -> bool {
+        false
+    }
+
+    /// Returns the node ID this transport authenticated for the peer at
+    /// `addr`, if it authenticates peers at all. A peer announcing another ID
+    /// is refused.
+    fn peer_id(&self, addr: SocketAddr) -> Option<String> {
+        let _ = addr;
+        None
+    }
+}
+
+/// Accepts inbound connections on the specified `addr`, using `cs.transport`.
+/// Each connection is handled in a new task by `handle_connection`, spawned
+/// with [`ConsensusState::spawn`] so that shutdown waits for it.
+///
+/// Before any bytes are read, connections from banned addresses are closed,
+/// and the connection must obtain a slot from the peer manager's
+/// `ConnectionTracker`; connections over the inbound, per-IP or per-subnet
+/// limits are closed immediately. Further streams of a multiplexed connection
+/// share the slot of the connection they belong to.
+///
+/// Returns `Ok` once the node shuts down, dropping the listener.
 ///
 /// # Arguments
 ///
 /// * `cs` - The shared consensus state (used to process inbound messages).
 /// * `addr` - The listening address (host:port).
 pub async fn accept_loop(cs: ConsensusState, addr: SocketAddr) -> Result<()> {
-    let listener = TcpListener::bind(addr).await?;
-    info!("Listening on {} ...", addr);
+    let mut listener = cs.transport.listen(addr).await?;
+    info!("Listening on {} ...", listener.local_addr());
+    let shutdown = cs.shutdown_token();
 
     loop {
-        // Accept a new socket
-        let (socket, _remote_addr) = listener.accept().await?;
+        // Accept a new socket, until we shut down
+        let Accepted { conn: socket, remote_addr, substream } = tokio::select! {
+            _ = shutdown.cancelled() => {
+                info!("Stopped accepting connections on {}", listener.local_addr());
+                return Ok(());
+            }
+            accepted = listener.accept() => accepted?,
+        };
+
+        // Refuse banned peers and enforce connection limits before the peer gets to talk to us
+        if cs.peer_manager().is_banned(remote_addr.ip()) {
+            debug!("Rejecting inbound connection from banned peer {}", remote_addr);
+            continue;
+        }
+        let guard = match substream {
+            true => None,
+            false => match cs.peer_manager().connections().try_acquire(remote_addr, Direction::Inbound) {
+                Ok(g) => Some(g),
+                Err(e) => {
+                    debug!("Rejecting inbound connection from {}: {}", remote_addr, e);
+                    continue;
+                }
+            },
+        };
         let cs_clone = cs.clone();
 
         // Spawn a task to handle the new connection
-        tokio::spawn(async move {
-            if let Err(e) = handle_connection(cs_clone, socket).await {
+        cs.spawn(async move {
+            let _guard = guard;
+            if let Err(e) = handle_connection(cs_clone, socket, remote_addr, Direction::Inbound, None, None).await {
                 warn!("Inbound connection error: {:?}", e);
             }
         });
     }
 }
 
-/// Attempts to connect to a peer at `addr`.
-/// Upon success, spawns a new task to handle the connection.
+/// Attempts to connect to a peer at `addr` over `cs.transport` and handles the connection
+/// until it closes.
+///
+/// The connection starts with the `PeerInfo` handshake described at
+/// [`handle_connection`], so a peer of another chain is refused by either side.
+///
+/// An outbound slot is reserved before dialing, so a full outbound table
+/// (or a saturated IP/subnet) is reported as a failed dial.
+///
+/// Returns `Err` only if the dial itself failed; errors on an established
+/// connection are logged and reported as a normal close, so callers such as
+/// the reconnect supervisor can tell the two cases apart.
 ///
 /// # Arguments
 ///
 /// * `cs` - The shared consensus state.
 /// * `addr` - The remote peer's address.
-pub async fn connect_to_peer(cs: ConsensusState, addr: SocketAddr) -> Result<()> {
+/// * `queue` - The peer's send queue, if the connection is dialed to serve it.
+pub async fn connect_to_peer(
+    cs: ConsensusState,
+    addr: SocketAddr,
+    queue: Option<Receiver<P2PMessage>>,
+) -> Result<()> {
+    if cs.peer_manager().is_banned(addr.ip()) {
+        bail!("peer {} is banned", addr);
+    }
+    let _guard = cs.peer_manager().connections().try_acquire(addr, Direction::Outbound)?;
     debug!("Connecting to {}", addr);
-    let socket = TcpStream::connect(addr).await?;
-    let cs_clone = cs.clone();
+    let socket = cs.transport.dial(addr).await?;
+    info!("Connected to {}", addr);
 
-    tokio::spawn(async move {
-        if let Err(e) = handle_connection(cs_clone, socket).await {
-            warn!("Outbound connection error: {:?}", e);
-        }
-    });
+    if let Err(e) = handle_connection(cs, socket, addr, Direction::Outbound, queue, None).await {
+        warn!("Outbound connection error: {:?}", e);
+    }
 
     Ok(())
 }
 
-/// Handles a single inbound or outbound TCP connection.
+/// Checks a `PeerInfo` received from `remote_addr`: the peer must be on our
+/// chain, and be the node the transport authenticated, if any. Any other
+/// message fails with [`Misbehavior::MissingHandshake`].
+///
+/// Returns the peer's node ID.
+fn check_peer_info(cs: &ConsensusState, remote_addr: SocketAddr, msg: &P2PMessage) -> Result<String> {
+    let P2PMessage::PeerInfo { node_id, listen_addr, genesis_hash } = msg else {
+        return Err(Misbehavior::MissingHandshake.into());
+    };
+    if *genesis_hash != cs.genesis_hash {
+        bail!(
+            "peer {} at {} is on another chain (genesis {}, ours {})",
+            node_id,
+            listen_addr,
+            genesis_hash,
+            cs.genesis_hash
+        );
+    }
+    if let Some(authenticated) = cs.transport.peer_id(remote_addr) {
+        if *node_id != authenticated {
+            bail!(
+                "peer {} announced node ID {} but authenticated as {}",
+                remote_addr,
+                node_id,
+                authenticated
+            );
+        }
+    }
+    Ok(node_id.clone())
+}
+
+/// Handles a single inbound or outbound connection.
+///
+/// Every connection starts with a `PeerInfo` handshake: the dialing side
+/// announces itself in the first frame, and the accepting side answers with
+/// its own `PeerInfo` once it has accepted the dialer's. Each side checks the
+/// other's with [`check_peer_info`] and closes the connection if it is
+/// refused. Frames that arrive before the peer's `PeerInfo` are dropped and
+/// charged as [`Misbehavior::MissingHandshake`]. A later `PeerInfo` must be
+/// for the same node.
+///
+/// Uses a length-delimited codec to separate messages, capped at the largest
+/// per-channel limit in `cs.frame_limits`. Each frame is decoded with
+/// [`codec::decode`] (binary or JSON, per its envelope) and then checked
+/// against the limit of its message's channel. If successful, the message is
+/// passed to `cs.process_p2p_message`, as coming from the node ID of the
+/// peer's handshake. The bytes of every decoded message
+/// are counted in the p2p metrics, by peer IP and channel.
+///
+/// Once the handshake is done, the connection also writes out the peer's
+/// send queue (see [`super::queues`]): the one it was dialed for, or else the
+/// one it registers for the peer, unless another connection already serves
+/// the peer. Bytes sent are counted like bytes received. If the transport
+/// [multiplexes channels](Transport::multiplexes_channels), each queued
+/// message is handed to the stream of its channel instead, opened on first
+/// use and served by a `handle_connection` of its own; a channel whose stream
+/// has fallen [`QUEUE_CAPACITY`] messages behind drops its new messages.
+///
+/// Everything runs in a `peer` span carrying the remote address, and the
+/// peer's node ID once it announces itself.
 ///
-/// Uses a length-delimited codec to separate messages. Each message is
-/// expected to be valid JSON (deserialized into `P2PMessage`). If successful,
-/// the message is passed to `cs.process_p2p_message`.
+/// A bad frame doesn't end the connection by itself. Each one is classified as
+/// a [`FrameError`], logged with the offending peer's address, and charged to
+/// the peer's score; valid messages earn a small reward. The connection is
+/// closed when:
+/// - the node shuts down, once its queue has been written out,
+/// - the peer gets banned, whichever of its connections caused it,
+/// - the peer's `PeerInfo` is refused,
+/// - a frame exceeds the framing limit (the stream can't be re-synchronized), or
+/// - the underlying stream fails.
 ///
 /// # Arguments
 ///
 /// * `cs` - The shared consensus state.
-/// * `socket` - The TCP stream to handle.
-async fn handle_connection(cs: ConsensusState, socket: TcpStream) -> Result<()> {
-    let mut framed = Framed::new(socket, LengthDelimitedCodec::new());
+/// * `socket` - The byte stream to handle.
+/// * `remote_addr` - The peer's address, used for scoring and reporting.
+/// * `direction` - Whether we dialed the connection, and so speak first.
+/// * `queue` - The peer's send queue, if already known.
+/// * `channel` - The channel the stream is dedicated to, if it is one of a
+///   multiplexed connection's channel streams.
+#[instrument(name = "peer", skip_all, fields(addr = %remote_addr, node_id = Empty))]
+async fn handle_connection<S>(
+    cs: ConsensusState,
+    socket: S,
+    remote_addr: SocketAddr,
+    direction: Direction,
+    mut queue: Option<Receiver<P2PMessage>>,
+    channel: Option<Channel>,
+) -> Result<()>
+where
+    S: AsyncRead + AsyncWrite + Unpin,
+{
+    let max_frame_len = cs.frame_limits.max_frame_len();
+    let framing = LengthDelimitedCodec::builder()
+        .max_frame_length(max_frame_len)
+        .new_codec();
+    let mut framed = Framed::new(socket, framing);
+    let peers = cs.peer_manager().clone();
+    let received = cs.metrics().p2p.peer_receive_bytes_total.clone();
+    let sent = cs.metrics().p2p.peer_send_bytes_total.clone();
+    let peer_label = remote_addr.ip().to_string();
+    let shutdown = cs.shutdown_token();
+    let banned = peers.banned(remote_addr.ip());
+    tokio::pin!(banned);
+    let mut streams = (channel.is_none() && cs.transport.multiplexes_channels())
+        .then(|| ChannelStreams::new(&cs, remote_addr));
 
-    while let Some(Ok(bytes)) = framed.next().await {
-        let msg_json = String::from_utf8(bytes.to_vec())?;
-        let msg: P2PMessage = serde_json::from_str(&msg_json)?;
+    if direction == Direction::Outbound {
+        framed.send(Bytes::from(codec::encode(&cs.peer_info(), cs.wire_format)?)).await?;
+    }
+    // The peer's node ID, once its `PeerInfo` has been accepted.
+    let mut peer_id: Option<String> = None;
+    let mut reading = true;
+
+    loop {
+        let frame = tokio::select! {
+            _ = shutdown.cancelled(), if reading => {
+                // Stop reading, but write out what's queued until the queue is closed.
+                if queue.is_none() {
+                    break;
+                }
+                reading = false;
+                continue;
+            }
+            _ = &mut banned => bail!("peer {} banned", remote_addr),
+            queued = next_queued(&mut queue), if peer_id.is_some() => match queued {
+                Some(msg) => {
+                    if let Some(streams) = &mut streams {
+                        streams.send(msg);
+                        continue;
+                    }
+                    let frame = codec::encode(&msg, cs.wire_format)?;
+                    let len = frame.len();
+                    framed.send(Bytes::from(frame)).await?;
+                    sent.with_label_values(&[&peer_label, msg.channel().name()])
+                        .inc_by(len as u64);
+                    continue;
+                }
+                None => break,
+            },
+            // Keep reading after shutdown until the handshake allows writing.
+            frame = framed.next(), if reading || peer_id.is_none() => match frame {
+                Some(frame) => frame,
+                None => break,
+            },
+        };
+        let bytes = match frame {
+            Ok(b) => b,
+            Err(e) => {
+                let err = FrameError::from_codec(e, max_frame_len);
+                report_frame_error(&peers, remote_addr, &err);
+                return Err(err.into());
+            }
+        };
 
-        // Process the inbound message
-        cs.process_p2p_message(msg).await?;
+        let msg = match codec::decode(&bytes) {
+            Ok(m) => m,
+            Err(e) => {
+                if report_frame_error(&peers, remote_addr, &FrameError::Decode(e)) {
+                    bail!("peer {} banned", remote_addr);
+                }
+                continue;
+            }
+        };
+
+        // Nothing but a `PeerInfo` is accepted until the handshake is done.
+        let announced = if peer_id.is_none() || matches!(msg, P2PMessage::PeerInfo { .. }) {
+            match check_peer_info(&cs, remote_addr, &msg) {
+                Ok(id) => Some(id),
+                Err(e) => match e.downcast_ref::<Misbehavior>() {
+                    Some(&m) => {
+                        if peers.report_misbehavior(remote_addr.ip(), m) {
+                            bail!("peer {} banned", remote_addr);
+                        }
+                        continue;
+                    }
+                    None => return Err(e),
+                },
+            }
+        } else {
+            None
+        };
+        if let (Some(id), Some(announced)) = (&peer_id, &announced) {
+            if id != announced {
+                bail!("peer {} announced node ID {} after {}", remote_addr, announced, id);
+            }
+        }
+
+        let channel = msg.channel();
+        received
+            .with_label_values(&[&peer_label, channel.name()])
+            .inc_by(bytes.len() as u64);
+        let max = cs.frame_limits.max_size(channel);
+        if bytes.len() > max {
+            let err = FrameError::Oversized {
+                len: Some(bytes.len()),
+                max,
+                channel: Some(channel),
+            };
+            if report_frame_error(&peers, remote_addr, &err) {
+                bail!("peer {} banned", remote_addr);
+            }
+            continue;
+        }
+
+        // Process the inbound message as coming from the node the handshake named
+        let from = announced.as_deref().or(peer_id.as_deref()).unwrap_or_default();
+        match cs.process_p2p_message(from, msg).await {
+            Ok(()) => peers.report_good(remote_addr.ip()),
+            Err(e) => match e.downcast_ref::<Misbehavior>() {
+                Some(&m) => {
+                    if peers.report_misbehavior(remote_addr.ip(), m) {
+                        bail!("peer {} banned", remote_addr);
+                    }
+                }
+                None => return Err(e),
+            },
+        }
+
+        // The handshake is done: answer it if the peer dialed us, and serve its queue.
+        if let (None, Some(id)) = (&peer_id, announced) {
+            Span::current().record("node_id", id.as_str());
+            if direction == Direction::Inbound {
+                framed.send(Bytes::from(codec::encode(&cs.peer_info(), cs.wire_format)?)).await?;
+            }
+            if queue.is_none() {
+                queue = cs.send_queues().register(&id);
+            }
+            peer_id = Some(id);
+        }
     }
 
     Ok(())
 }
 
-/// Sends a single message (`msg`) to a peer at `addr`.
+/// The per-channel streams a connection of a multiplexing transport writes
+/// its queue to, by channel.
+struct ChannelStreams {
+    cs: ConsensusState,
+    remote_addr: SocketAddr,
+    queues: HashMap<Channel, Sender<P2PMessage>>,
+}
+
+impl ChannelStreams {
+    fn new(cs: &ConsensusState, remote_addr: SocketAddr) -> Self {
+        Self {
+            cs: cs.clone(),
+            remote_addr,
+            queues: HashMap::new(),
+        }
+    }
+
+    /// Queues `msg` on the stream of its channel, opening the stream if it
+    /// isn't open (any more). Never waits: if the stream is too far behind,
+    /// `msg` is dropped.
+    fn send(&mut self, msg: P2PMessage) {
+        let channel = msg.channel();
+        let queue = self
+            .queues
+            .entry(channel)
+            .or_insert_with(|| open_channel_stream(&self.cs, self.remote_addr, channel));
+        if queue.is_closed() {
+            *queue = open_channel_stream(&self.cs, self.remote_addr, channel);
+        }
+        if let Err(TrySendError::Full(_)) = queue.try_send(msg) {
+            warn!(peer = %self.remote_addr, channel = channel.name(), "Channel stream is full, dropping message");
+        }
+    }
+}
+
+/// Spawns a task that opens `channel`'s stream to `remote_addr` and serves it
+/// with the messages sent on the returned queue.
+fn open_channel_stream(cs: &ConsensusState, remote_addr: SocketAddr, channel: Channel) -> Sender<P2PMessage> {
+    let (tx, rx) = mpsc::channel(QUEUE_CAPACITY);
+    let task_cs = cs.clone();
+    cs.spawn(
+        async move {
+            let stream = match task_cs.transport.dial_channel(remote_addr, channel).await {
+                Ok(s) => s,
+                Err(e) => {
+                    warn!("Failed to open {} stream: {:?}", channel.name(), e);
+                    return;
+                }
+            };
+            if let Err(e) = handle_connection(task_cs, stream, remote_addr, Direction::Outbound, Some(rx), Some(channel)).await {
+                warn!("{} stream error: {:?}", channel.name(), e);
+            }
+        }
+        .in_current_span(),
+    );
+    tx
+}
+
+/// Waits for the next message of `queue`, or forever if there is none.
+/// Returns `None` once the queue is closed and empty.
+async fn next_queued(queue: &mut Option<Receiver<P2PMessage>>) -> Option<P2PMessage> {
+    match queue {
+        Some(queue) => queue.recv().await,
+        None => std::future::pending().await,
+    }
+}
+
+/// Logs a rejected frame with the offending peer and charges the peer for it.
 ///
-/// **Note**: This example opens a *new* TCP connection each time,
-/// which is inefficient. A production system would typically maintain
-/// a persistent connection and reuse it.
+/// Returns `true` if the peer has been banned as a result.
+fn report_frame_error(peers: &PeerManager, remote_addr: SocketAddr, err: &FrameError) -> bool {
+    warn!(peer = %remote_addr, kind = err.kind(), error = %err, "Rejected frame");
+    let misbehavior = match err {
+        FrameError::Io(_) => return false,
+        FrameError::Oversized { .. } => Misbehavior::OversizedMessage,
+        FrameError::Decode(_) => Misbehavior::MalformedMessage,
+    };
+    peers.report_misbehavior(remote_addr.ip(), misbehavior)
+}
+
+/// Sends a single message (`msg`) to a peer at `addr` over a connection of
+/// its own, returning the size of the frame sent.
+///
+/// The connection starts with the usual handshake: `cs`'s `PeerInfo` is sent
+/// first, and `msg` only once the peer's answer has passed [`check_peer_info`].
+///
+/// The node itself sends through its peers' queues, over connections that
+/// stay open (see [`ConsensusState::send_to_peer`]); this is for one-off
+/// messages.
 ///
 /// # Arguments
 ///
+/// * `cs` - The shared consensus state, whose transport and wire format are used.
 /// * `addr` - The peer's address to connect.
 /// * `msg` - The message to send.
-pub async fn send_message(addr: SocketAddr, msg: &P2PMessage) -> Result<()> {
-    let socket = TcpStream::connect(addr).await?;
-    let mut framed = Framed::new(socket, LengthDelimitedCodec::new());
-    let msg_json = serde_json::to_vec(msg)?;
-    framed.send(msg_json.into()).await?;
-    Ok(())
+pub async fn send_message(cs: &ConsensusState, addr: SocketAddr, msg: &P2PMessage) -> Result<usize> {
+    let socket = cs.transport.dial_channel(addr, msg.channel()).await?;
+    let framing = LengthDelimitedCodec::builder()
+        .max_frame_length(cs.frame_limits.max_frame_len())
+        .new_codec();
+    let mut framed = Framed::new(socket, framing);
+    framed.send(Bytes::from(codec::encode(&cs.peer_info(), cs.wire_format)?)).await?;
+    let reply = match framed.next().await {
+        Some(frame) => codec::decode(&frame?)?,
+        None => bail!("peer {} closed the connection during the handshake", addr),
+    };
+    check_peer_info(cs, addr, &reply)?;
+
+    let frame = codec::encode(msg, cs.wire_format)?;
+    let len = frame.len();
+    framed.send(Bytes::from(frame)).await?;
+    Ok(len)
 }
 
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::sync::Arc;
+    use std::time::Duration;
+
+    use rand::rngs::StdRng;
+    use rand::{Rng, SeedableRng};
+    use tokio::io::{duplex, AsyncWriteExt, DuplexStream};
+
+    use crate::consensus::block::Part;
+    use crate::consensus::merkle::MerkleProof;
+    use crate::consensus::types::VoteType;
+    use crate::consensus::vote::Vote;
+    use crate::p2p::codec::WireFormat;
+    use crate::p2p::limits::ConnectionLimits;
+    use crate::p2p::peer::Peer;
+    use crate::p2p::score::ScoreConfig;
+
+    const REMOTE: &str = "10.0.0.7:26656";
+
+    fn remote() -> SocketAddr {
+        REMOTE.parse().unwrap()
+    }
+
+    fn peer_info() -> P2PMessage {
+        P2PMessage::PeerInfo {
+            node_id: "peer-1".into(),
+            listen_addr: REMOTE.into(),
+            genesis_hash: String::new(),
+        }
+    }
+
+    /// Runs `handle_connection` on one end of an in-memory pipe and returns the
+    /// other end, framed, together with the task's handle.
+    fn spawn_connection(
+        cs: ConsensusState,
+    ) -> (Framed<DuplexStream, LengthDelimitedCodec>, tokio::task::JoinHandle<Result<()>>) {
+        let (client, server) = duplex(1 << 20);
+        let handle = tokio::spawn(handle_connection(cs, server, remote(), Direction::Inbound, None, None));
+        (Framed::new(client, LengthDelimitedCodec::new()), handle)
+    }
+
+    async fn send_raw(framed: &mut Framed<DuplexStream, LengthDelimitedCodec>, frame: &[u8]) {
+        framed.send(Bytes::copy_from_slice(frame)).await.unwrap();
+    }
+
+    /// Announces the client with `peer_info()` and waits for the node's answer.
+    async fn handshake(framed: &mut Framed<DuplexStream, LengthDelimitedCodec>) -> P2PMessage {
+        send_raw(framed, &codec::encode(&peer_info(), WireFormat::Binary).unwrap()).await;
+        codec::decode(&framed.next().await.unwrap().unwrap()).unwrap()
+    }
+
+    fn consensus_state() -> ConsensusState {
+        ConsensusState::new("node-a".into(), "127.0.0.1:0".into())
+    }
+
+    /// A multiplexing transport whose channel streams are in-memory pipes; the
+    /// other end of each is handed to the test.
+    struct ChannelPipes(mpsc::UnboundedSender<(Channel, DuplexStream)>);
+
+    #[async_trait]
+    impl Transport for ChannelPipes {
+        async fn listen(&self, _addr: SocketAddr) -> Result<Box<dyn Listener>> {
+            bail!("channel pipes don't listen")
+        }
+
+        async fn dial(&self, addr: SocketAddr) -> Result<BoxConnection> {
+            self.dial_channel(addr, Channel::Peer).await
+        }
+
+        async fn dial_channel(&self, _addr: SocketAddr, channel: Channel) -> Result<BoxConnection> {
+            let (ours, theirs) = duplex(256);
+            self.0.send((channel, theirs))?;
+            Ok(Box::new(ours))
+        }
+
+        fn multiplexes_channels(&self) -> bool {
+            true
+        }
+    }
+
+    #[tokio::test]
+    async fn malformed_frames_are_skipped_and_penalized() {
+        let cs = consensus_state();
+        let (mut client, handle) = spawn_connection(cs.clone());
+
+        send_raw(&mut client, &[]).await;
+        send_raw(&mut client, &[codec::WIRE_VERSION + 1, 0, 1, 2]).await;
+        send_raw(&mut client, &[codec::WIRE_VERSION, 9, 1, 2]).await;
+        send_raw(&mut client, &[codec::WIRE_VERSION, 1, b'{', b'x']).await;
+        let valid = codec::encode(&peer_info(), WireFormat::Json).unwrap();
+        send_raw(&mut client, &valid).await;
+        client.next().await.unwrap().unwrap();
+        drop(client);
+
+        handle.await.unwrap().unwrap();
+        let peers = cs.peer_manager();
+        assert_eq!(peers.get_all_peers().len(), 1);
+        assert_eq!(peers.score(remote().ip()), -4 * Misbehavior::MalformedMessage.penalty() + 1);
+    }
+
+    #[tokio::test]
+    async fn repeated_garbage_bans_the_peer() {
+        let cs = consensus_state();
+        let (mut client, handle) = spawn_connection(cs.clone());
+
+        for _ in 0..10 {
+            if client.send(Bytes::from_static(b"garbage")).await.is_err() {
+                break;
+            }
+        }
+
+        assert!(handle.await.unwrap().is_err());
+        assert!(cs.peer_manager().is_banned(remote().ip()));
+    }
+
+    #[tokio::test]
+    async fn ban_closes_every_connection_of_the_peer() {
+        let cs = consensus_state();
+        let (_quiet, quiet_handle) = spawn_connection(cs.clone());
+        let (mut noisy, noisy_handle) = spawn_connection(cs.clone());
+
+        for _ in 0..10 {
+            if noisy.send(Bytes::from_static(b"garbage")).await.is_err() {
+                break;
+            }
+        }
+
+        assert!(noisy_handle.await.unwrap().is_err());
+        let err = tokio::time::timeout(std::time::Duration::from_secs(5), quiet_handle)
+            .await
+            .expect("the other connection is closed too")
+            .unwrap()
+            .unwrap_err();
+        assert!(err.to_string().contains("banned"));
+    }
+
+    #[tokio::test]
+    async fn frame_over_framing_limit_closes_connection() {
+        let cs = consensus_state();
+        let max = cs.frame_limits.max_frame_len();
+        let (mut client, handle) = spawn_connection(cs.clone());
+
+        // The connection may be closed before the whole frame is written.
+        let _ = client.send(Bytes::from(vec![0u8; max + 1])).await;
+
+        let err = handle.await.unwrap().unwrap_err();
+        match err.downcast_ref::<FrameError>() {
+            Some(FrameError::Oversized { channel: None, .. }) => {}
+            other => panic!("expected framing-level oversized error, got {:?}", other),
+        }
+        assert_eq!(
+            cs.peer_manager().score(remote().ip()),
+            -Misbehavior::OversizedMessage.penalty()
+        );
+    }
+
+    #[tokio::test]
+    async fn a_stalled_block_part_stream_does_not_delay_votes() {
+        let mut cs = consensus_state();
+        let (pipes, mut streams) = mpsc::unbounded_channel();
+        cs.transport = Arc::new(ChannelPipes(pipes));
+        let (queue, queued) = mpsc::channel(QUEUE_CAPACITY);
+        let (client, server) = duplex(1 << 20);
+        tokio::spawn(handle_connection(cs.clone(), server, remote(), Direction::Outbound, Some(queued), None));
+        let mut client = Framed::new(client, LengthDelimitedCodec::new());
+        client.next().await.unwrap().unwrap();
+        send_raw(&mut client, &codec::encode(&peer_info(), WireFormat::Binary).unwrap()).await;
+
+        for index in 0..16 {
+            let part = Part {
+                index,
+                bytes: vec![0; 1024],
+                proof: MerkleProof { total: 16, index, aunts: Vec::new() },
+            };
+            queue.send(P2PMessage::BlockPart { height: 1, round: 0, part }).await.unwrap();
+        }
+        let vote = Vote::new(VoteType::Prevote, 1, 0, "block".into(), "node-a".into());
+        queue.send(P2PMessage::Vote { vote: vote.clone() }).await.unwrap();
+
+        // Both streams open and are answered, but the block parts are never read.
+        let mut opened = HashMap::new();
+        for _ in 0..2 {
+            let (channel, stream) = streams.recv().await.unwrap();
+            let mut stream = Framed::new(stream, LengthDelimitedCodec::new());
+            let hello = codec::decode(&stream.next().await.unwrap().unwrap()).unwrap();
+            assert!(matches!(hello, P2PMessage::PeerInfo { node_id, .. } if node_id == "node-a"));
+            send_raw(&mut stream, &codec::encode(&peer_info(), WireFormat::Binary).unwrap()).await;
+            opened.insert(channel, stream);
+        }
+        let _parts = opened.remove(&Channel::Data).expect("block parts get a stream");
+        let votes = opened.get_mut(&Channel::Vote).expect("votes get a stream");
+
+        let received = tokio::time::timeout(Duration::from_secs(2), votes.next())
+            .await
+            .expect("the vote isn't held up by the block parts")
+            .unwrap()
+            .unwrap();
+        assert!(matches!(codec::decode(&received).unwrap(), P2PMessage::Vote { vote: v } if v == vote));
+    }
+
+    #[tokio::test]
+    async fn message_over_channel_limit_is_skipped() {
+        let mut cs = consensus_state();
+        cs.frame_limits.set(Channel::Vote, 8);
+        let (mut client, handle) = spawn_connection(cs.clone());
+
+        let vote = P2PMessage::Vote {
+            vote: Vote::new(
+                VoteType::Prevote,
+                1,
+                1,
+                "a-block-hash-longer-than-eight-bytes".into(),
+                "node-a".into(),
+            ),
+        };
+        handshake(&mut client).await;
+        send_raw(&mut client, &codec::encode(&vote, WireFormat::Binary).unwrap()).await;
+        drop(client);
+
+        handle.await.unwrap().unwrap();
+        let peers = cs.peer_manager();
+        assert_eq!(peers.get_all_peers().len(), 1);
+        assert_eq!(peers.score(remote().ip()), -Misbehavior::OversizedMessage.penalty() + 1);
+    }
+
+    #[tokio::test]
+    async fn peer_from_another_chain_is_refused() {
+        let cs = consensus_state();
+        let (mut client, handle) = spawn_connection(cs.clone());
+
+        let other_chain = P2PMessage::PeerInfo {
+            node_id: "peer-1".into(),
+            listen_addr: REMOTE.into(),
+            genesis_hash: "0badc0de".into(),
+        };
+        send_raw(&mut client, &codec::encode(&other_chain, WireFormat::Binary).unwrap()).await;
+
+        let err = handle.await.unwrap().unwrap_err();
+        assert!(err.to_string().contains("another chain"));
+        assert!(cs.peer_manager().get_all_peers().is_empty());
+    }
+
+    #[tokio::test]
+    async fn frames_before_peer_info_are_dropped_and_penalized() {
+        let cs = consensus_state();
+        let (mut client, handle) = spawn_connection(cs.clone());
+
+        let tx = P2PMessage::Tx { tx: vec![1, 2, 3] };
+        send_raw(&mut client, &codec::encode(&tx, WireFormat::Binary).unwrap()).await;
+
+        // The listener answers the handshake with its own `PeerInfo`.
+        let reply = handshake(&mut client).await;
+        assert!(matches!(reply, P2PMessage::PeerInfo { ref node_id, .. } if node_id == "node-a"));
+        drop(client);
+
+        handle.await.unwrap().unwrap();
+        assert_eq!(
+            cs.peer_manager().score(remote().ip()),
+            -Misbehavior::MissingHandshake.penalty() + 1
+        );
+    }
+
+    #[tokio::test]
+    async fn dialer_refuses_a_listener_from_another_chain() {
+        let cs = consensus_state();
+        let (client, server) = duplex(1 << 20);
+        let handle = tokio::spawn(handle_connection(cs.clone(), server, remote(), Direction::Outbound, None, None));
+        let mut listener = Framed::new(client, LengthDelimitedCodec::new());
+
+        // The dialer speaks first.
+        let hello = codec::decode(&listener.next().await.unwrap().unwrap()).unwrap();
+        assert!(matches!(hello, P2PMessage::PeerInfo { ref node_id, .. } if node_id == "node-a"));
+
+        let other_chain = P2PMessage::PeerInfo {
+            node_id: "peer-1".into(),
+            listen_addr: REMOTE.into(),
+            genesis_hash: "0badc0de".into(),
+        };
+        send_raw(&mut listener, &codec::encode(&other_chain, WireFormat::Binary).unwrap()).await;
+
+        let err = handle.await.unwrap().unwrap_err();
+        assert!(err.to_string().contains("another chain"));
+        assert!(cs.peer_manager().get_all_peers().is_empty());
+    }
+
+    #[tokio::test]
+    async fn round_steps_are_credited_to_the_handshake_peer() {
+        let cs = consensus_state();
+        let (mut client, handle) = spawn_connection(cs.clone());
+        handshake(&mut client).await;
+
+        let step = P2PMessage::NewRoundStep { height: 1, round: 2, step: Default::default() };
+        send_raw(&mut client, &codec::encode(&step, WireFormat::Binary).unwrap()).await;
+        SinkExt::<Bytes>::close(&mut client).await.unwrap();
+
+        handle.await.unwrap().unwrap();
+        let prs = cs.reactor().peer_state("peer-1").expect("tracked under the handshake's ID");
+        assert_eq!((prs.height, prs.round), (1, 2));
+    }
+
+    #[tokio::test]
+    async fn goodbye_only_removes_the_sender() {
+        let cs = consensus_state();
+        cs.peer_manager().add_peer(Peer::new("peer-2".into(), "10.0.0.8:26656".into()));
+        let (mut client, handle) = spawn_connection(cs.clone());
+        handshake(&mut client).await;
+        assert!(cs.peer_manager().get_peer("peer-1").is_some());
+
+        send_raw(&mut client, &codec::encode(&P2PMessage::Goodbye, WireFormat::Binary).unwrap()).await;
+        SinkExt::<Bytes>::close(&mut client).await.unwrap();
+
+        handle.await.unwrap().unwrap();
+        assert!(cs.peer_manager().get_peer("peer-1").is_none());
+        assert!(cs.peer_manager().get_peer("peer-2").is_some());
+    }
+
+    #[tokio::test]
+    async fn truncated_stream_is_an_io_error() {
+        let cs = consensus_state();
+        let (mut client, server) = duplex(1024);
+        let handle = tokio::spawn(handle_connection(cs.clone(), server, remote(), Direction::Inbound, None, None));
+
+        // A length header promising 10 bytes, followed by only 2.
+        client.write_all(&[0, 0, 0, 10, 1, 2]).await.unwrap();
+        drop(client);
+
+        let err = handle.await.unwrap().unwrap_err();
+        assert!(matches!(err.downcast_ref::<FrameError>(), Some(FrameError::Io(_))));
+        assert_eq!(cs.peer_manager().score(remote().ip()), 0);
+    }
+
+    #[tokio::test]
+    async fn random_frames_never_break_the_connection() {
+        // Keep the peer unconditional so it's never banned and every frame gets processed.
+        let limits = ConnectionLimits {
+            unconditional_peers: vec![remote().ip()],
+            ..ConnectionLimits::default()
+        };
+        let cs = ConsensusState::with_peer_manager(
+            "node-a".into(),
+            "127.0.0.1:0".into(),
+            PeerManager::with_config(limits, ScoreConfig::default()),
+        );
+        let (mut client, handle) = spawn_connection(cs);
+
+        handshake(&mut client).await;
+
+        let mut rng = StdRng::seed_from_u64(0x5eed);
+        let valid = codec::encode(&P2PMessage::Tx { tx: vec![7; 16] }, WireFormat::Binary).unwrap();
+        for _ in 0..500 {
+            let frame: Vec<u8> = match rng.gen_range(0..3) {
+                // Entirely random bytes
+                0 => (0..rng.gen_range(0..64)).map(|_| rng.gen()).collect(),
+                // A valid envelope around a random payload
+                1 => {
+                    let mut f = vec![codec::WIRE_VERSION, rng.gen_range(0..2)];
+                    f.extend((0..rng.gen_range(0..64)).map(|_| rng.gen::<u8>()));
+                    f
+                }
+                // A valid message with one byte flipped
+                _ => {
+                    let mut f = valid.clone();
+                    let i = rng.gen_range(0..f.len());
+                    f[i] ^= rng.gen_range(1..=255);
+                    f
+                }
+            };
+            send_raw(&mut client, &frame).await;
+        }
+        // Stop writing only: the node relays the valid transactions back to us.
+        SinkExt::<Bytes>::close(&mut client).await.unwrap();
+
+        handle.await.unwrap().unwrap();
+    }
+}
@@ -0,0 +1,917 @@
+//! The JSON-RPC server: a read-only view of the node plus transaction submission.
+//!
+//! Methods can be called with a JSON-RPC 2.0 request (single or batch) POSTed
+//! to `/`:
+//!
+//! ```text
+//! curl -d '{"jsonrpc":"2.0","id":1,"method":"block","params":{"height":"5"}}' localhost:26657
+//! ```
+//!
+//! or with a GET of `/<method>`, passing params in the query string:
+//!
+//! ```text
+//! curl 'localhost:26657/broadcast_tx_commit?tx=0x68656c6c6f'
+//! ```
+//!
+//! Numeric params may be given as numbers or strings. Binary params (`tx`,
+//! `data`) and hashes are hex, with an optional `0x` prefix; binary results
+//! are hex too. Transaction hashes are the SHA-256 of the transaction.
+//!
+//! | Method                | Params                  | Result                                        |
+//! |-----------------------|-------------------------|-----------------------------------------------|
+//! | `health`              |                         | `{}`                                          |
+//! | `status`              |                         | node info, latest block, our validator info   |
+//! | `net_info`            |                         | connected peers                               |
+//! | `block`               | `height?`               | the block at `height` (default: latest)       |
+//! | `block_by_hash`       | `hash`                  | the block with `hash`                         |
+//! | `commit`              | `height?`               | the precommits that committed the block       |
+//! | `validators`          | `height?, page?, per_page?` | the validator set in force at `height`    |
+//! | `consensus_state`     |                         | the current height, round, step and votes     |
+//! | `broadcast_tx_async`  | `tx`                    | the hash, without waiting for `check_tx`      |
+//! | `broadcast_tx_sync`   | `tx`                    | the `check_tx` response                       |
+//! | `broadcast_tx_commit` | `tx`                    | `check_tx` and the result once committed      |
+//! | `abci_query`          | `path, data?`           | the application's answer                      |
+//! | `tx`                  | `hash`                  | a committed transaction and its result        |
+//! | `tx_search`           | `query, page?, per_page?, order_by?` | committed transactions matching a [`Query`] |
+//! | `block_search`        | `query, page?, per_page?, order_by?` | blocks whose events match a [`Query`] |
+//!
+//! Paginated methods return `per_page` items (default 30, at most 100) of
+//! page `page` (from 1), along with the `total_count`; `order_by` is `asc`
+//! (the default) or `desc` by height.
+//!
+//! Event subscriptions need a WebSocket connection to `/websocket`, over
+//! which any of the methods above can be called too. `subscribe` takes a
+//! [`Query`] and answers `{}`; from then on, every matching event arrives as a
+//! response with the id of the `subscribe` request:
+//!
+//! ```text
+//! > {"jsonrpc":"2.0","id":1,"method":"subscribe","params":{"query":"tm.event='Tx' AND transfer.sender='x'"}}
+//! < {"jsonrpc":"2.0","id":1,"result":{}}
+//! < {"jsonrpc":"2.0","id":1,"result":{"query":"...","data":{"type":"Tx","value":{...}},"events":{...}}}
+//! ```
+//!
+//! `unsubscribe` (same `query`) and `unsubscribe_all` end subscriptions. A
+//! client that doesn't read its events fast enough to keep its subscription
+//! buffers from filling up, or that blocks a write for longer than
+//! [`WS_WRITE_TIMEOUT`], is sent an error and disconnected.
+
+use std::collections::HashMap;
+use std::net::SocketAddr;
+use std::time::Duration;
+
+use anyhow::Result;
+use axum::body::Bytes;
+use axum::extract::ws::{Message as WsMessage, WebSocket, WebSocketUpgrade};
+use axum::extract::{Path, Query as UrlQuery, State};
+use axum::response::Response;
+use axum::routing::{get, post};
+use axum::{Json, Router};
+use futures_util::stream::{self, BoxStream, SelectAll};
+use futures_util::{SinkExt, StreamExt};
+use serde_json::{json, Value};
+use tokio::net::TcpListener;
+use tracing::{debug, info};
+
+use crate::config::RpcConfig;
+use crate::consensus::block::{tx_hash, Block};
+use crate::consensus::state::ConsensusCore;
+use crate::consensus::ConsensusState;
+use crate::events::query::QueryError;
+use crate::events::{Message, Query};
+use crate::indexer::TxRecord;
+
+/// Invalid JSON was received.
+pub const PARSE_ERROR: i64 = -32700;
+/// The JSON is not a valid request object.
+pub const INVALID_REQUEST: i64 = -32600;
+/// The method does not exist.
+pub const METHOD_NOT_FOUND: i64 = -32601;
+/// Missing or malformed params.
+pub const INVALID_PARAMS: i64 = -32602;
+/// The call failed, e.g. the requested block doesn't exist.
+pub const INTERNAL_ERROR: i64 = -32603;
+
+/// Default and maximum page size of paginated methods.
+const DEFAULT_PER_PAGE: usize = 30;
+const MAX_PER_PAGE: usize = 100;
+
+/// How often `broadcast_tx_commit` looks for its transaction in new blocks.
+const COMMIT_POLL_INTERVAL: Duration = Duration::from_millis(50);
+
+/// How long a write to a WebSocket client may take before it is disconnected.
+pub const WS_WRITE_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// A JSON-RPC error object.
+#[derive(Debug, Clone, PartialEq)]
+pub struct RpcError {
+    pub code: i64,
+    pub message: String,
+    /// Details, e.g. why the params are invalid.
+    pub data: Option<String>,
+}
+
+impl RpcError {
+    fn new(code: i64, message: &str, data: Option<String>) -> Self {
+        Self {
+            code,
+            message: message.to_string(),
+            data,
+        }
+    }
+
+    fn invalid_params(data: impl Into<String>) -> Self {
+        Self::new(INVALID_PARAMS, "Invalid params", Some(data.into()))
+    }
+
+    fn internal(data: impl Into<String>) -> Self {
+        Self::new(INTERNAL_ERROR, "Internal error", Some(data.into()))
+    }
+
+    fn to_json(&self) -> Value {
+        let mut error = json!({ "code": self.code, "message": self.message });
+        if let Some(data) = &self.data {
+            error["data"] = json!(data);
+        }
+        error
+    }
+}
+
+/// Everything the RPC methods read from, shared by all requests.
+#[derive(Clone)]
+pub struct RpcContext {
+    pub cs: ConsensusState,
+    /// Human-readable node name, reported by `status`.
+    pub moniker: String,
+    pub chain_id: String,
+    pub config: RpcConfig,
+}
+
+/// Serves RPC on `listen_addr` until the node shuts down or the server fails.
+/// WebSocket sessions are closed on shutdown.
+pub async fn serve(ctx: RpcContext, listen_addr: &str) -> Result<()> {
+    let addr: SocketAddr = listen_addr.parse()?;
+    let listener = TcpListener::bind(addr).await?;
+    info!("RPC server listening on {}", listener.local_addr()?);
+    let shutdown = ctx.cs.shutdown_token();
+    axum::serve(listener, router(ctx))
+        .with_graceful_shutdown(shutdown.cancelled_owned())
+        .await?;
+    Ok(())
+}
+
+/// Returns the HTTP routes: JSON-RPC on `POST /`, `GET /<method>`, and
+/// WebSocket on `/websocket`.
+pub fn router(ctx: RpcContext) -> Router {
+    Router::new()
+        .route("/", post(handle_post))
+        .route("/websocket", get(handle_websocket))
+        .route("/{method}", get(handle_get))
+        .with_state(ctx)
+}
+
+/// Handles a JSON-RPC request or batch of requests.
+async fn handle_post(State(ctx): State<RpcContext>, body: Bytes) -> Json<Value> {
+    let request: Value = match serde_json::from_slice(&body) {
+        Ok(request) => request,
+        Err(e) => {
+            let error = RpcError::new(PARSE_ERROR, "Parse error", Some(e.to_string()));
+            return Json(response(Value::Null, Err(error)));
+        }
+    };
+    match request {
+        Value::Array(batch) => {
+            let mut responses = Vec::with_capacity(batch.len());
+            for request in batch {
+                responses.push(handle_request(&ctx, request).await);
+            }
+            Json(Value::Array(responses))
+        }
+        request => Json(handle_request(&ctx, request).await),
+    }
+}
+
+/// Handles `GET /<method>?<params>`. Quoted param values are unquoted.
+async fn handle_get(
+    State(ctx): State<RpcContext>,
+    Path(method): Path<String>,
+    UrlQuery(query): UrlQuery<HashMap<String, String>>,
+) -> Json<Value> {
+    let params = query
+        .into_iter()
+        .map(|(k, v)| {
+            let v = v.strip_prefix('"').and_then(|v| v.strip_suffix('"')).unwrap_or(&v).to_string();
+            (k, Value::String(v))
+        })
+        .collect();
+    Json(response(json!(-1), call(&ctx, &method, &Value::Object(params)).await))
+}
+
+async fn handle_websocket(State(ctx): State<RpcContext>, ws: WebSocketUpgrade) -> Response {
+    ws.on_upgrade(move |socket| websocket_session(ctx, socket))
+}
+
+/// Events of one subscription, tagged with its local ID. `None` marks the
+/// end of the subscription.
+type EventStream = BoxStream<'static, (u64, Option<Message>)>;
+
+/// A WebSocket client's subscriptions.
+struct Session {
+    client_id: String,
+    next_id: u64,
+    /// Local ID -> (query, id of the `subscribe` request).
+    active: HashMap<u64, (Query, Value)>,
+    events: SelectAll<EventStream>,
+}
+
+/// Serves one WebSocket client until it disconnects or falls behind.
+async fn websocket_session(ctx: RpcContext, socket: WebSocket) {
+    let (mut sink, mut incoming) = socket.split();
+    let mut session = Session {
+        client_id: uuid::Uuid::new_v4().to_string(),
+        next_id: 0,
+        active: HashMap::new(),
+        events: SelectAll::new(),
+    };
+    debug!("WebSocket client {} connected", session.client_id);
+    let shutdown = ctx.cs.shutdown_token();
+
+    loop {
+        let reply = tokio::select! {
+            _ = shutdown.cancelled() => break,
+            msg = incoming.next() => match msg {
+                Some(Ok(WsMessage::Text(text))) => match serde_json::from_str(&text) {
+                    Ok(request) => handle_ws_request(&ctx, &mut session, request).await,
+                    Err(e) => {
+                        let error = RpcError::new(PARSE_ERROR, "Parse error", Some(e.to_string()));
+                        response(Value::Null, Err(error))
+                    }
+                },
+                Some(Ok(WsMessage::Close(_))) | Some(Err(_)) | None => break,
+                Some(Ok(_)) => continue,
+            },
+            Some((id, event)) = session.events.next(), if !session.events.is_empty() => {
+                let Some(event) = event else {
+                    // Ended by us (unsubscribe), or cancelled by the bus because it filled up.
+                    if let Some((query, rpc_id)) = session.active.remove(&id) {
+                        let reason = format!(
+                            "subscription to `{}` was cancelled: client is not pulling messages fast enough",
+                            query
+                        );
+                        let error = response(rpc_id, Err(RpcError::internal(reason)));
+                        let _ = send_json(&mut sink, &error).await;
+                        break;
+                    }
+                    continue;
+                };
+                let Some((query, rpc_id)) = session.active.get(&id) else {
+                    continue;
+                };
+                let result = json!({ "query": query.to_string(), "data": event.data, "events": event.events });
+                response(rpc_id.clone(), Ok(result))
+            }
+        };
+        if !send_json(&mut sink, &reply).await {
+            break;
+        }
+    }
+
+    ctx.cs.event_bus().unsubscribe_all(&session.client_id);
+    let _ = sink.close().await;
+    debug!("WebSocket client {} disconnected", session.client_id);
+}
+
+/// Sends `value` as a text frame. Returns `false` if the client is gone or too slow.
+async fn send_json(
+    sink: &mut futures_util::stream::SplitSink<WebSocket, WsMessage>,
+    value: &Value,
+) -> bool {
+    let frame = WsMessage::Text(value.to_string().into());
+    matches!(tokio::time::timeout(WS_WRITE_TIMEOUT, sink.send(frame)).await, Ok(Ok(())))
+}
+
+/// Handles a request received over WebSocket: the subscription methods, or any other method.
+async fn handle_ws_request(ctx: &RpcContext, session: &mut Session, request: Value) -> Value {
+    let id = request.get("id").cloned().unwrap_or(Value::Null);
+    let method = request.get("method").and_then(Value::as_str).unwrap_or_default();
+    let params = request.get("params").cloned().unwrap_or(Value::Null);
+    let bus = ctx.cs.event_bus();
+    let result = match method {
+        "subscribe" | "unsubscribe" => {
+            let query = Params::new(&params).and_then(|p| {
+                let source = p.string("query")?.ok_or_else(|| RpcError::invalid_params("missing query"))?;
+                source.parse::<Query>().map_err(|e| RpcError::invalid_params(e.to_string()))
+            });
+            match query {
+                Err(e) => Err(e),
+                Ok(query) if method == "subscribe" => subscribe(ctx, session, query, id.clone()),
+                Ok(query) if bus.unsubscribe(&session.client_id, &query) => {
+                    session.active.retain(|_, (q, _)| *q != query);
+                    Ok(json!({}))
+                }
+                Ok(_) => Err(RpcError::internal("subscription not found")),
+            }
+        }
+        "unsubscribe_all" => {
+            bus.unsubscribe_all(&session.client_id);
+            session.active.clear();
+            Ok(json!({}))
+        }
+        _ => return handle_request(ctx, request).await,
+    };
+    response(id, result)
+}
+
+/// Subscribes the session to `query`; events will be sent with `rpc_id`.
+fn subscribe(ctx: &RpcContext, session: &mut Session, query: Query, rpc_id: Value) -> Result<Value, RpcError> {
+    let subscription = ctx
+        .cs
+        .event_bus()
+        .subscribe(
+            &session.client_id,
+            query.clone(),
+            ctx.config.subscription_buffer_size,
+            ctx.config.max_subscriptions_per_client,
+        )
+        .map_err(|e| RpcError::internal(e.to_string()))?;
+    let local_id = session.next_id;
+    session.next_id += 1;
+    session.active.insert(local_id, (query, rpc_id));
+    let events = stream::unfold(Some(subscription.events), move |rx| async move {
+        let mut rx = rx?;
+        match rx.recv().await {
+            Some(event) => Some(((local_id, Some(event)), Some(rx))),
+            None => Some(((local_id, None), None)),
+        }
+    });
+    session.events.push(events.boxed());
+    Ok(json!({}))
+}
+
+/// Handles one JSON-RPC request object.
+async fn handle_request(ctx: &RpcContext, request: Value) -> Value {
+    let id = request.get("id").cloned().unwrap_or(Value::Null);
+    let Some(method) = request.get("method").and_then(Value::as_str) else {
+        let error = RpcError::new(INVALID_REQUEST, "Invalid Request", Some("missing method".into()));
+        return response(id, Err(error));
+    };
+    let params = request.get("params").cloned().unwrap_or(Value::Null);
+    response(id, call(ctx, method, &params).await)
+}
+
+/// Wraps a result in a JSON-RPC response object.
+fn response(id: Value, result: Result<Value, RpcError>) -> Value {
+    match result {
+        Ok(result) => json!({ "jsonrpc": "2.0", "id": id, "result": result }),
+        Err(error) => json!({ "jsonrpc": "2.0", "id": id, "error": error.to_json() }),
+    }
+}
+
+/// Runs `method` with `params`, which must be an object (or absent).
+async fn call(ctx: &RpcContext, method: &str, params: &Value) -> Result<Value, RpcError> {
+    let params = Params::new(params)?;
+    match method {
+        "health" => Ok(json!({})),
+        "status" => Ok(status(ctx)),
+        "net_info" => Ok(net_info(ctx)),
+        "block" => ctx.cs.inspect(|core| {
+            let height = height_param(core, &params)?;
+            let block = core
+                .block_store
+                .load_block(height)
+                .ok_or_else(|| RpcError::internal(format!("no block at height {}", height)))?;
+            Ok(block_json(block))
+        }),
+        "block_by_hash" => {
+            let hash = params.hash("hash")?;
+            ctx.cs.inspect(|core| match core.block_store.block_by_hash(&hash) {
+                Some(block) => Ok(block_json(block)),
+                None => Err(RpcError::internal(format!("no block with hash {}", hash))),
+            })
+        }
+        "commit" => ctx.cs.inspect(|core| {
+            let height = height_param(core, &params)?;
+            let commit = core
+                .block_store
+                .load_commit(height)
+                .ok_or_else(|| RpcError::internal(format!("no commit at height {}", height)))?;
+            let signatures: Vec<Value> = commit
+                .signatures
+                .iter()
+                .map(|vote| {
+                    json!({
+                        "validator_id": vote.validator_id,
+                        "signature": hex::encode(&vote.signature),
+                    })
+                })
+                .collect();
+            Ok(json!({
+                "height": commit.height.to_string(),
+                "round": commit.round,
+                "block_hash": commit.block_hash,
+                "signatures": signatures,
+            }))
+        }),
+        "validators" => ctx.cs.inspect(|core| validators(core, &params)),
+        "tx" => {
+            let hash = params.hash("hash")?;
+            let record = ctx.cs.inspect(|core| core.indexer.txs.get(&hash));
+            record
+                .map(|r| tx_json(&r))
+                .ok_or_else(|| RpcError::internal(format!("tx {} not found", hash)))
+        }
+        "tx_search" => {
+            let query = params.query()?;
+            let mut txs = ctx.cs.inspect(|core| core.indexer.txs.search(&query));
+            if params.descending()? {
+                txs.reverse();
+            }
+            let (skip, take) = params.page(txs.len())?;
+            Ok(json!({
+                "txs": txs.iter().skip(skip).take(take).map(tx_json).collect::<Vec<_>>(),
+                "total_count": txs.len().to_string(),
+            }))
+        }
+        "block_search" => {
+            let query = params.query()?;
+            ctx.cs.inspect(|core| {
+                let mut heights = core.indexer.blocks.search(&query);
+                if params.descending()? {
+                    heights.reverse();
+                }
+                let (skip, take) = params.page(heights.len())?;
+                let blocks: Vec<Value> = heights
+                    .iter()
+                    .skip(skip)
+                    .take(take)
+                    .filter_map(|&h| core.block_store.load_block(h))
+                    .map(block_json)
+                    .collect();
+                Ok(json!({ "blocks": blocks, "total_count": heights.len().to_string() }))
+            })
+        }
+        "consensus_state" => Ok(ctx.cs.inspect(consensus_state)),
+        "broadcast_tx_async" | "broadcast_tx_sync" | "broadcast_tx_commit" => {
+            broadcast_tx(ctx, method, params.bytes("tx")?).await
+        }
+        "abci_query" => {
+            let path = params.string("path")?.unwrap_or_default();
+            let data = params.optional_bytes("data")?.unwrap_or_default();
+            let response = ctx.cs.query(&path, &data);
+            Ok(json!({
+                "response": {
+                    "code": response.code,
+                    "log": response.log,
+                    "value": hex::encode(&response.value),
+                    "height": response.height.to_string(),
+                }
+            }))
+        }
+        _ => Err(RpcError::new(METHOD_NOT_FOUND, "Method not found", Some(method.to_string()))),
+    }
+}
+
+/// The params of a call, by name.
+struct Params<'a>(Option<&'a serde_json::Map<String, Value>>);
+
+impl<'a> Params<'a> {
+    fn new(params: &'a Value) -> Result<Self, RpcError> {
+        match params {
+            Value::Null => Ok(Self(None)),
+            Value::Object(map) => Ok(Self(Some(map))),
+            _ => Err(RpcError::invalid_params("params must be an object")),
+        }
+    }
+
+    fn get(&self, name: &str) -> Option<&'a Value> {
+        self.0.and_then(|map| map.get(name)).filter(|v| !v.is_null())
+    }
+
+    /// A non-negative integer, as a number or a string.
+    fn u64(&self, name: &str) -> Result<Option<u64>, RpcError> {
+        let invalid = || RpcError::invalid_params(format!("{} must be a non-negative integer", name));
+        match self.get(name) {
+            None => Ok(None),
+            Some(Value::Number(n)) => n.as_u64().map(Some).ok_or_else(invalid),
+            Some(Value::String(s)) => s.parse().map(Some).map_err(|_| invalid()),
+            Some(_) => Err(invalid()),
+        }
+    }
+
+    fn string(&self, name: &str) -> Result<Option<String>, RpcError> {
+        match self.get(name) {
+            None => Ok(None),
+            Some(Value::String(s)) => Ok(Some(s.clone())),
+            Some(_) => Err(RpcError::invalid_params(format!("{} must be a string", name))),
+        }
+    }
+
+    /// Hex-encoded bytes, with an optional `0x` prefix.
+    fn optional_bytes(&self, name: &str) -> Result<Option<Vec<u8>>, RpcError> {
+        let Some(s) = self.string(name)? else {
+            return Ok(None);
+        };
+        let s = s.strip_prefix("0x").unwrap_or(&s);
+        hex::decode(s)
+            .map(Some)
+            .map_err(|e| RpcError::invalid_params(format!("{} must be hex: {}", name, e)))
+    }
+
+    fn bytes(&self, name: &str) -> Result<Vec<u8>, RpcError> {
+        self.optional_bytes(name)?
+            .ok_or_else(|| RpcError::invalid_params(format!("missing {}", name)))
+    }
+
+    /// A hash, normalized to lowercase hex without prefix.
+    fn hash(&self, name: &str) -> Result<String, RpcError> {
+        Ok(hex::encode(self.bytes(name)?))
+    }
+
+    /// The required `query`.
+    fn query(&self) -> Result<Query, RpcError> {
+        let source = self
+            .string("query")?
+            .ok_or_else(|| RpcError::invalid_params("missing query"))?;
+        source.parse().map_err(|e: QueryError| RpcError::invalid_params(e.to_string()))
+    }
+
+    /// Whether `order_by` asks for descending order.
+    fn descending(&self) -> Result<bool, RpcError> {
+        match self.string("order_by")?.as_deref() {
+            None | Some("") | Some("asc") => Ok(false),
+            Some("desc") => Ok(true),
+            Some(other) => Err(RpcError::invalid_params(format!(
+                "order_by must be asc or desc, not {}",
+                other
+            ))),
+        }
+    }
+
+    /// The items to skip and take for the `page` and `per_page` params, out of `total`.
+    fn page(&self, total: usize) -> Result<(usize, usize), RpcError> {
+        let per_page = self
+            .u64("per_page")?
+            .map_or(DEFAULT_PER_PAGE, |n| (n as usize).clamp(1, MAX_PER_PAGE));
+        let pages = total.div_ceil(per_page).max(1);
+        let page = self.u64("page")?.unwrap_or(1) as usize;
+        if page == 0 || page > pages {
+            return Err(RpcError::invalid_params(format!("page must be in 1..={}", pages)));
+        }
+        Ok(((page - 1) * per_page, per_page))
+    }
+}
+
+/// The `height` param, defaulting to the latest block; it must be a stored height.
+fn height_param(core: &ConsensusCore, params: &Params) -> Result<u64, RpcError> {
+    let latest = core.block_store.height();
+    match params.u64("height")? {
+        None | Some(0) if latest > 0 => Ok(latest),
+        Some(h) if h >= 1 && h <= latest => Ok(h),
+        h => Err(RpcError::internal(format!(
+            "height {} is not available, latest height is {}",
+            h.unwrap_or(0),
+            latest
+        ))),
+    }
+}
+
+fn block_json(block: &Block) -> Value {
+    json!({
+        "block_id": { "hash": block.hash() },
+        "block": {
+            "height": block.height.to_string(),
+            "data": block.data,
+            "txs": block.txs.iter().map(hex::encode).collect::<Vec<_>>(),
+            "evidence": block.evidence,
+        }
+    })
+}
+
+fn tx_json(record: &TxRecord) -> Value {
+    let result = &record.result;
+    json!({
+        "hash": tx_hash(&record.tx),
+        "height": record.height.to_string(),
+        "index": record.index,
+        "tx": hex::encode(&record.tx),
+        "tx_result": {
+            "code": result.code,
+            "data": hex::encode(&result.data),
+            "log": result.log,
+            "events": result.events,
+        },
+    })
+}
+
+fn status(ctx: &RpcContext) -> Value {
+    ctx.cs.inspect(|core| {
+        let height = core.block_store.height();
+        let latest_hash = core
+            .block_store
+            .load_commit(height)
+            .map(|c| c.block_hash.clone())
+            .unwrap_or_default();
+        json!({
+            "node_info": {
+                "id": core.node_id,
+                "listen_addr": core.listen_addr,
+                "network": ctx.chain_id,
+                "moniker": ctx.moniker,
+                "version": env!("CARGO_PKG_VERSION"),
+            },
+            "sync_info": {
+                "latest_block_hash": latest_hash,
+                "latest_block_height": height.to_string(),
+                "genesis_hash": ctx.cs.genesis_hash,
+            },
+            "validator_info": {
+                "id": core.node_id,
+                "pub_key": hex::encode(core.public_key().as_bytes()),
+                "voting_power": core.validators.power_of(&core.node_id).to_string(),
+            },
+        })
+    })
+}
+
+fn net_info(ctx: &RpcContext) -> Value {
+    let manager = ctx.cs.peer_manager();
+    let mut peers = manager.get_all_peers();
+    peers.sort_by(|a, b| a.id.cmp(&b.id));
+    let peers: Vec<Value> = peers
+        .iter()
+        .map(|peer| {
+            let score = peer
+                .listen_addr
+                .parse::<SocketAddr>()
+                .map(|addr| manager.score(addr.ip()))
+                .ok();
+            json!({ "id": peer.id, "listen_addr": peer.listen_addr, "score": score })
+        })
+        .collect();
+    json!({
+        "listening": true,
+        "listeners": [ctx.cs.listen_addr],
+        "n_peers": peers.len().to_string(),
+        "peers": peers,
+    })
+}
+
+fn validators(core: &ConsensusCore, params: &Params) -> Result<Value, RpcError> {
+    // Validators are known one height past the latest block.
+    let latest = core.block_store.height() + 1;
+    let height = match params.u64("height")? {
+        None | Some(0) => latest,
+        Some(h) => h,
+    };
+    let set = core.block_store.validators_at(height).ok_or_else(|| {
+        RpcError::internal(format!("no validator set at height {} (latest is {})", height, latest))
+    })?;
+    let (skip, take) = params.page(set.len())?;
+    let validators: Vec<Value> = set
+        .validators
+        .iter()
+        .skip(skip)
+        .take(take)
+        .map(|v| {
+            json!({
+                "id": v.id,
+                "pub_key": hex::encode(v.pub_key.as_bytes()),
+                "voting_power": v.power.to_string(),
+                "proposer_priority": v.proposer_priority.to_string(),
+            })
+        })
+        .collect();
+    Ok(json!({
+        "block_height": height.to_string(),
+        "count": validators.len().to_string(),
+        "total": set.len().to_string(),
+        "validators": validators,
+    }))
+}
+
+fn consensus_state(core: &ConsensusCore) -> Value {
+    let rs = &core.round_state;
+    let voters = |votes: &HashMap<String, _>| {
+        let mut ids: Vec<&String> = votes.keys().collect();
+        ids.sort();
+        json!(ids)
+    };
+    json!({
+        "round_state": {
+            "height": rs.height.to_string(),
+            "round": rs.round,
+            "step": rs.step,
+            "proposer": rs.proposer_id,
+            "proposal_block_hash": rs.proposal_block_hash,
+            "locked_block_hash": rs.locked_block_hash,
+            "prevotes": voters(&rs.prevotes),
+            "precommits": voters(&rs.precommits),
+        }
+    })
+}
+
+/// Submits `tx` to the mempool and, depending on `method`, waits for
+/// nothing, for `check_tx`, or for the transaction to be committed.
+async fn broadcast_tx(ctx: &RpcContext, method: &str, tx: Vec<u8>) -> Result<Value, RpcError> {
+    let hash = tx_hash(&tx);
+    let start_height = ctx.cs.inspect(|core| core.block_store.height());
+    if method == "broadcast_tx_async" {
+        let cs = ctx.cs.clone();
+        tokio::spawn(async move { cs.check_tx(tx).await });
+        return Ok(json!({ "hash": hash }));
+    }
+
+    let check = ctx
+        .cs
+        .check_tx(tx)
+        .await
+        .map_err(|e| RpcError::internal(e.to_string()))?;
+    let check_json = json!({ "code": check.code, "log": check.log });
+    if method == "broadcast_tx_sync" {
+        return Ok(json!({ "code": check.code, "log": check.log, "hash": hash }));
+    }
+    if !check.is_ok() {
+        return Ok(json!({ "check_tx": check_json, "tx_result": null, "hash": hash, "height": "0" }));
+    }
+
+    let timeout = Duration::from_millis(ctx.config.timeout_broadcast_tx_commit_ms);
+    let deadline = tokio::time::Instant::now() + timeout;
+    loop {
+        let committed = ctx.cs.inspect(|core| {
+            let (height, index) = core.block_store.find_tx(&hash, start_height)?;
+            let result = core.block_store.tx_results(height).and_then(|r| r.get(index)).cloned();
+            Some((height, result))
+        });
+        if let Some((height, result)) = committed {
+            let tx_result = result.map(|r| json!({ "code": r.code, "data": hex::encode(&r.data), "log": r.log }));
+            return Ok(json!({
+                "check_tx": check_json,
+                "tx_result": tx_result,
+                "hash": hash,
+                "height": height.to_string(),
+            }));
+        }
+        if tokio::time::Instant::now() >= deadline {
+            return Err(RpcError::internal("timed out waiting for tx to be committed"));
+        }
+        tokio::time::sleep(COMMIT_POLL_INTERVAL).await;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+    fn context() -> RpcContext {
+        RpcContext {
+            cs: ConsensusState::new("node-a".into(), "127.0.0.1:0".into()),
+            moniker: "test".into(),
+            chain_id: "test-chain".into(),
+            config: RpcConfig::default(),
+        }
+    }
+
+    /// Runs a round; as the only validator, we propose and commit at once.
+    async fn commit_round(cs: &ConsensusState) {
+        cs.start_new_round("block".into()).await;
+    }
+
+    #[tokio::test]
+    async fn broadcast_txs_end_up_in_blocks() {
+        let ctx = context();
+        let sync = call(&ctx, "broadcast_tx_sync", &json!({ "tx": "0x01" })).await.unwrap();
+        assert_eq!(sync["code"], 0);
+        assert_eq!(sync["hash"], tx_hash(&[1]));
+        let err = call(&ctx, "broadcast_tx_sync", &json!({ "tx": "01" })).await.unwrap_err();
+        assert_eq!(err.data.as_deref(), Some("tx already exists in cache"));
+
+        commit_round(&ctx.cs).await;
+        let block = call(&ctx, "block", &json!({ "height": 1 })).await.unwrap();
+        assert_eq!(block["block"]["txs"], json!(["01"]));
+        let hash = block["block_id"]["hash"].as_str().unwrap().to_string();
+        let by_hash = call(&ctx, "block_by_hash", &json!({ "hash": hash })).await.unwrap();
+        assert_eq!(by_hash, block);
+        let commit = call(&ctx, "commit", &Value::Null).await.unwrap();
+        assert_eq!(commit["signatures"][0]["validator_id"], "node-a");
+
+        let committer = tokio::spawn({
+            let cs = ctx.cs.clone();
+            async move {
+                tokio::time::sleep(Duration::from_millis(100)).await;
+                commit_round(&cs).await;
+            }
+        });
+        let result = call(&ctx, "broadcast_tx_commit", &json!({ "tx": "02" })).await.unwrap();
+        committer.await.unwrap();
+        assert_eq!(result["height"], "2");
+        assert_eq!(result["tx_result"]["code"], 0);
+    }
+
+    #[tokio::test]
+    async fn searches_committed_txs_and_blocks() {
+        let ctx = context();
+        for (round, txs) in [["01", "02"], ["03", "04"]].iter().enumerate() {
+            for tx in txs {
+                call(&ctx, "broadcast_tx_sync", &json!({ "tx": tx })).await.unwrap();
+            }
+            commit_round(&ctx.cs).await;
+            assert_eq!(ctx.cs.inspect(|core| core.block_store.height()), round as u64 + 1);
+        }
+
+        let tx = call(&ctx, "tx", &json!({ "hash": tx_hash(&[3]) })).await.unwrap();
+        assert_eq!((tx["height"].clone(), tx["index"].clone()), (json!("2"), json!(0)));
+        let page = json!({ "query": "tx.height >= 1", "order_by": "desc", "per_page": 3, "page": 2 });
+        let found = call(&ctx, "tx_search", &page).await.unwrap();
+        assert_eq!(found["total_count"], "4");
+        assert_eq!(found["txs"][0]["tx"], "01");
+        let by_hash = json!({ "query": format!("tx.hash='{}' AND tx.height=1", tx_hash(&[2])) });
+        assert_eq!(call(&ctx, "tx_search", &by_hash).await.unwrap()["txs"][0]["index"], 1);
+
+        let blocks = call(&ctx, "block_search", &json!({ "query": "block.height > 1" })).await.unwrap();
+        assert_eq!(blocks["total_count"], "1");
+        assert_eq!(blocks["blocks"][0]["block"]["txs"], json!(["03", "04"]));
+        let bad = call(&ctx, "tx_search", &json!({ "query": "tx.height >= 1", "page": 3 })).await;
+        assert_eq!(bad.unwrap_err().code, INVALID_PARAMS);
+    }
+
+    #[tokio::test]
+    async fn reports_errors_in_json_rpc_form() {
+        let ctx = context();
+        let status = call(&ctx, "status", &Value::Null).await.unwrap();
+        assert_eq!(status["sync_info"]["latest_block_height"], "0");
+        assert_eq!(status["validator_info"]["voting_power"], "10");
+        let validators = call(&ctx, "validators", &json!({ "per_page": "1" })).await.unwrap();
+        assert_eq!(validators["total"], "1");
+
+        let missing = call(&ctx, "block", &json!({ "height": 7 })).await.unwrap_err();
+        assert_eq!(missing.code, INTERNAL_ERROR);
+        let bad = call(&ctx, "broadcast_tx_sync", &json!({ "tx": "zz" })).await.unwrap_err();
+        assert_eq!(bad.code, INVALID_PARAMS);
+        let unknown = handle_request(&ctx, json!({ "jsonrpc": "2.0", "id": 7, "method": "nope" })).await;
+        assert_eq!(unknown["id"], 7);
+        assert_eq!(unknown["error"]["code"], METHOD_NOT_FOUND);
+    }
+
+    /// Reads text frames until one arrives, and parses it.
+    async fn next_text<S, E>(ws: &mut S) -> Value
+    where
+        S: futures_util::Stream<Item = Result<tokio_tungstenite::tungstenite::Message, E>> + Unpin,
+        E: std::fmt::Debug,
+    {
+        loop {
+            if let tokio_tungstenite::tungstenite::Message::Text(text) = ws.next().await.unwrap().unwrap() {
+                return serde_json::from_str(&text).unwrap();
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn websocket_clients_receive_matching_events() {
+        use tokio_tungstenite::tungstenite::Message as Frame;
+
+        let ctx = context();
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let app = router(ctx.clone());
+        tokio::spawn(async move { axum::serve(listener, app).await });
+
+        let (mut ws, _) = tokio_tungstenite::connect_async(format!("ws://{}/websocket", addr)).await.unwrap();
+        let subscribe = json!({
+            "jsonrpc": "2.0", "id": 5, "method": "subscribe",
+            "params": { "query": format!("tm.event='Tx' AND tx.hash='{}'", tx_hash(b"hi")) },
+        });
+        ws.send(Frame::text(subscribe.to_string())).await.unwrap();
+        assert_eq!(next_text(&mut ws).await["result"], json!({}));
+
+        ctx.cs.check_tx(b"other".to_vec()).await.unwrap();
+        ctx.cs.check_tx(b"hi".to_vec()).await.unwrap();
+        commit_round(&ctx.cs).await;
+        let event = next_text(&mut ws).await;
+        assert_eq!(event["id"], 5);
+        assert_eq!(event["result"]["data"]["type"], "Tx");
+        assert_eq!(event["result"]["data"]["value"]["tx"], hex::encode(b"hi"));
+        assert_eq!(event["result"]["events"]["tx.height"], json!(["1"]));
+
+        ws.send(Frame::text(json!({ "id": 6, "method": "unsubscribe_all" }).to_string())).await.unwrap();
+        assert_eq!(next_text(&mut ws).await["id"], 6);
+        assert_eq!(ctx.cs.event_bus().num_subscriptions(), 0);
+    }
+
+    #[tokio::test]
+    async fn serves_get_requests_over_http() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move { axum::serve(listener, router(context())).await });
+
+        let mut stream = tokio::net::TcpStream::connect(addr).await.unwrap();
+        stream
+            .write_all(b"GET /abci_query?path=%22store%22 HTTP/1.1\r\nHost: x\r\nConnection: close\r\n\r\n")
+            .await
+            .unwrap();
+        let mut reply = String::new();
+        stream.read_to_string(&mut reply).await.unwrap();
+        let body: Value = serde_json::from_str(reply.split("\r\n\r\n").nth(1).unwrap()).unwrap();
+        assert_eq!(body["id"], -1);
+        assert_eq!(body["result"]["response"]["log"], "unknown query path store");
+    }
+}